@@ -0,0 +1,522 @@
+use actix_files::NamedFile;
+use actix_web::http::header::{ContentDisposition, DispositionParam, DispositionType};
+use actix_web::http::StatusCode;
+use actix_web::web::Bytes;
+use actix_web::{web, HttpRequest, HttpResponse};
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use futures_util::StreamExt;
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io::{self, Read, Seek, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Component, Path, PathBuf};
+use tokio::sync::mpsc;
+use walkdir::WalkDir;
+
+use crate::fileio::{self, AtomicFile};
+use crate::files::{error_response, max_upload_bytes, UploadError};
+use crate::sandbox;
+
+/// Size of the chunks a streamed archive is sent in.
+const STREAM_CHUNK_BYTES: usize = 64 * 1024;
+/// Conflicting paths listed in the error when extraction would overwrite files.
+const MAX_LISTED_CONFLICTS: usize = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ArchiveFormat {
+    TarGz,
+    Tar,
+    Zip,
+}
+
+impl ArchiveFormat {
+    fn parse(format: &str) -> Option<Self> {
+        match format {
+            "tar.gz" | "tgz" => Some(ArchiveFormat::TarGz),
+            "tar" => Some(ArchiveFormat::Tar),
+            "zip" => Some(ArchiveFormat::Zip),
+            _ => None,
+        }
+    }
+
+    /// Recognizes an archive by its first bytes.
+    fn detect(header: &[u8]) -> Option<Self> {
+        if header.starts_with(&[0x1f, 0x8b]) {
+            Some(ArchiveFormat::TarGz)
+        } else if header.starts_with(b"PK\x03\x04") || header.starts_with(b"PK\x05\x06") {
+            Some(ArchiveFormat::Zip)
+        } else if header.get(257..262) == Some(b"ustar") {
+            Some(ArchiveFormat::Tar)
+        } else {
+            None
+        }
+    }
+
+    fn extension(&self) -> &'static str {
+        match self {
+            ArchiveFormat::TarGz => "tar.gz",
+            ArchiveFormat::Tar => "tar",
+            ArchiveFormat::Zip => "zip",
+        }
+    }
+
+    fn content_type(&self) -> &'static str {
+        match self {
+            ArchiveFormat::TarGz => "application/gzip",
+            ArchiveFormat::Tar => "application/x-tar",
+            ArchiveFormat::Zip => "application/zip",
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ArchiveQuery {
+    /// Directory to export, or to extract into.
+    pub path: String,
+    /// "tar.gz" (default for export), "tar" or "zip". Detected from the
+    /// content when importing without it.
+    pub format: Option<String>,
+    /// Comma separated globs of paths, relative to `path`, left out of an export.
+    pub exclude: Option<String>,
+    /// Replace existing files when extracting.
+    pub overwrite: Option<bool>,
+    /// Create the target directory when extracting.
+    pub mkdir: Option<bool>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ExtractReport {
+    pub path: String,
+    pub files: usize,
+    pub directories: usize,
+    /// Entries that were not extracted, such as symlinks and device files.
+    pub skipped: Vec<String>,
+}
+
+fn exclude_set(exclude: Option<&str>) -> Result<GlobSet, String> {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in exclude.unwrap_or_default().split(',').map(str::trim).filter(|p| !p.is_empty()) {
+        builder.add(Glob::new(pattern).map_err(|e| format!("Invalid exclude glob {}: {}", pattern, e))?);
+    }
+    builder.build().map_err(|e| format!("Invalid exclude globs: {}", e))
+}
+
+/// Lists the entries below `root` that go into an export, as absolute and
+/// relative paths. Excluded directories are not descended into, and entries
+/// the sandbox policy hides are left out.
+fn export_entries(root: &Path, excludes: &GlobSet) -> Vec<(PathBuf, PathBuf)> {
+    WalkDir::new(root)
+        .min_depth(1)
+        .follow_links(false)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_entry(|entry| {
+            let relative = entry.path().strip_prefix(root).unwrap_or(entry.path());
+            !excludes.is_match(relative)
+                && sandbox::check(&entry.path().to_string_lossy(), sandbox::Access::Read).is_ok()
+        })
+        .filter_map(|entry| match entry {
+            Ok(entry) => {
+                let relative = entry.path().strip_prefix(root).ok()?.to_path_buf();
+                Some((entry.path().to_path_buf(), relative))
+            }
+            Err(e) => {
+                log::warn!("Skipping entry in export: {}", e);
+                None
+            }
+        })
+        .collect()
+}
+
+/// Sends everything written to it to the response stream in chunks.
+struct ChannelWriter {
+    tx: mpsc::Sender<Result<Bytes, io::Error>>,
+    buffer: Vec<u8>,
+}
+
+impl ChannelWriter {
+    fn send(&mut self) -> io::Result<()> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+        let chunk = Bytes::from(std::mem::take(&mut self.buffer));
+        self.tx
+            .blocking_send(Ok(chunk))
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "client disconnected"))
+    }
+}
+
+impl Write for ChannelWriter {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        self.buffer.extend_from_slice(data);
+        if self.buffer.len() >= STREAM_CHUNK_BYTES {
+            self.send()?;
+        }
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.send()
+    }
+}
+
+fn write_tar<W: Write>(writer: W, entries: &[(PathBuf, PathBuf)]) -> io::Result<W> {
+    let mut builder = tar::Builder::new(writer);
+    // Symlinks are stored as links, so they cannot pull in files from elsewhere
+    builder.follow_symlinks(false);
+    for (path, relative) in entries {
+        builder.append_path_with_name(path, relative)?;
+    }
+    builder.into_inner()
+}
+
+fn write_zip<W: Write + Seek>(writer: W, entries: &[(PathBuf, PathBuf)]) -> zip::result::ZipResult<W> {
+    let mut zip = zip::ZipWriter::new(writer);
+    for (path, relative) in entries {
+        let meta = fs::symlink_metadata(path)?;
+        let options = zip::write::SimpleFileOptions::default()
+            .compression_method(zip::CompressionMethod::Deflated)
+            .unix_permissions(meta.permissions().mode() & 0o7777);
+        let name = relative.to_string_lossy();
+        if meta.is_dir() {
+            zip.add_directory(name, options)?;
+        } else if meta.is_file() {
+            zip.start_file(name, options)?;
+            io::copy(&mut File::open(path)?, &mut zip)?;
+        } else {
+            log::debug!("Skipping {} in zip export: not a regular file", path.display());
+        }
+    }
+    zip.finish()
+}
+
+/// Streams the directory `path` as an archive. tar archives are produced
+/// while they are sent; zip needs seeking, so it is built in a temporary
+/// file first.
+pub async fn handle_export(req: HttpRequest, query: web::Query<ArchiveQuery>) -> HttpResponse {
+    let format = match query.format.as_deref().map(ArchiveFormat::parse) {
+        None => ArchiveFormat::TarGz,
+        Some(Some(format)) => format,
+        Some(None) => return error_response((StatusCode::BAD_REQUEST, String::from("format should be one of: tar.gz, tar, zip"))),
+    };
+    let root = match sandbox::check(&query.path, sandbox::Access::Read) {
+        Ok(root) => root,
+        Err(e) => return error_response((StatusCode::FORBIDDEN, e)),
+    };
+    if !root.is_dir() {
+        return error_response((StatusCode::BAD_REQUEST, format!("{} is not a directory", query.path)));
+    }
+    let excludes = match exclude_set(query.exclude.as_deref()) {
+        Ok(excludes) => excludes,
+        Err(e) => return error_response((StatusCode::BAD_REQUEST, e)),
+    };
+
+    let name = root.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_else(|| String::from("root"));
+    let disposition = ContentDisposition {
+        disposition: DispositionType::Attachment,
+        parameters: vec![DispositionParam::Filename(format!("{}.{}", name, format.extension()))],
+    };
+    log::info!("Exporting {} as {}", root.display(), format.extension());
+
+    if format == ArchiveFormat::Zip {
+        let built = web::block(move || -> Result<File, String> {
+            let entries = export_entries(&root, &excludes);
+            let temp = std::env::temp_dir().join(format!(".consoley-export-{:016x}.zip", rand::random::<u64>()));
+            let file = fs::OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create_new(true)
+                .open(&temp)
+                .map_err(|e| format!("Failed to create temporary file: {}", e))?;
+            // The open handle keeps the data alive until it has been sent
+            let _ = fs::remove_file(&temp);
+            let mut file = write_zip(file, &entries).map_err(|e| format!("Failed to build zip: {}", e))?;
+            file.rewind().map_err(|e| e.to_string())?;
+            Ok(file)
+        })
+        .await;
+        return match built {
+            Ok(Ok(file)) => match NamedFile::from_file(file, format!("{}.zip", name)) {
+                Ok(file) => file
+                    .set_content_type(format.content_type().parse().unwrap())
+                    .set_content_disposition(disposition)
+                    .into_response(&req),
+                Err(e) => error_response((StatusCode::INTERNAL_SERVER_ERROR, e.to_string())),
+            },
+            Ok(Err(e)) => error_response((StatusCode::INTERNAL_SERVER_ERROR, e)),
+            Err(e) => error_response((StatusCode::INTERNAL_SERVER_ERROR, e.to_string())),
+        };
+    }
+
+    let (tx, rx) = mpsc::channel::<Result<Bytes, io::Error>>(8);
+    std::thread::spawn(move || {
+        let entries = export_entries(&root, &excludes);
+        let writer = ChannelWriter {
+            tx: tx.clone(),
+            buffer: Vec::with_capacity(STREAM_CHUNK_BYTES),
+        };
+        let result = match format {
+            ArchiveFormat::TarGz => write_tar(GzEncoder::new(writer, flate2::Compression::default()), &entries)
+                .and_then(|encoder| encoder.finish()),
+            _ => write_tar(writer, &entries),
+        };
+        match result.and_then(|mut writer| writer.flush()) {
+            Ok(_) => log::info!("Exported {} entries from {}", entries.len(), root.display()),
+            Err(e) => {
+                log::error!("Failed to export {}: {}", root.display(), e);
+                // Aborts the response so the client sees a failed transfer
+                let _ = tx.blocking_send(Err(e));
+            }
+        }
+    });
+
+    let stream = futures_util::stream::unfold(rx, |mut rx| async move { rx.recv().await.map(|item| (item, rx)) });
+    HttpResponse::Ok()
+        .content_type(format.content_type())
+        .insert_header(disposition)
+        .streaming(stream)
+}
+
+/// Returns `path` if it stays inside the extraction directory, i.e. it has
+/// no root, prefix or `..` components.
+fn safe_relative(path: &Path) -> Option<PathBuf> {
+    let mut relative = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::Normal(name) => relative.push(name),
+            Component::CurDir => {}
+            Component::ParentDir | Component::RootDir | Component::Prefix(_) => return None,
+        }
+    }
+    (!relative.as_os_str().is_empty()).then_some(relative)
+}
+
+#[derive(Debug)]
+enum EntryKind {
+    Directory,
+    File,
+    Other,
+}
+
+#[derive(Debug)]
+struct PlannedEntry {
+    relative: PathBuf,
+    kind: EntryKind,
+    mode: Option<u32>,
+}
+
+/// Calls `visit` for every entry of the archive in `file`, with a reader for
+/// the content of regular files.
+fn for_each_entry(
+    file: &Path,
+    format: ArchiveFormat,
+    mut visit: impl FnMut(&Path, EntryKind, Option<u32>, &mut dyn Read) -> Result<(), String>,
+) -> Result<(), String> {
+    let archive = File::open(file).map_err(|e| format!("Failed to open archive: {}", e))?;
+    match format {
+        ArchiveFormat::Zip => {
+            let mut zip = zip::ZipArchive::new(archive).map_err(|e| format!("Invalid zip archive: {}", e))?;
+            for index in 0..zip.len() {
+                let mut entry = zip.by_index(index).map_err(|e| format!("Invalid zip archive: {}", e))?;
+                let kind = if entry.is_dir() {
+                    EntryKind::Directory
+                } else if entry.is_symlink() {
+                    EntryKind::Other
+                } else {
+                    EntryKind::File
+                };
+                let mode = entry.unix_mode().map(|mode| mode & 0o7777);
+                // Names that would escape are passed on as is and rejected by the caller
+                let name = entry.enclosed_name().unwrap_or_else(|| PathBuf::from(entry.name()));
+                visit(&name, kind, mode, &mut entry)?;
+            }
+        }
+        ArchiveFormat::TarGz | ArchiveFormat::Tar => {
+            let reader: Box<dyn Read> = match format {
+                ArchiveFormat::TarGz => Box::new(GzDecoder::new(archive)),
+                _ => Box::new(archive),
+            };
+            let mut tar = tar::Archive::new(reader);
+            for entry in tar.entries().map_err(|e| format!("Invalid tar archive: {}", e))? {
+                let mut entry = entry.map_err(|e| format!("Invalid tar archive: {}", e))?;
+                let kind = match entry.header().entry_type() {
+                    tar::EntryType::Directory => EntryKind::Directory,
+                    tar::EntryType::Regular | tar::EntryType::Continuous => EntryKind::File,
+                    _ => EntryKind::Other,
+                };
+                let mode = entry.header().mode().ok().map(|mode| mode & 0o7777);
+                let name = entry.path().map_err(|e| format!("Invalid tar entry name: {}", e))?.to_path_buf();
+                visit(&name, kind, mode, &mut entry)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Checks every entry before anything is written: names must stay inside
+/// `target` (also through existing symlinks), be allowed by the sandbox and,
+/// unless `overwrite` is set, not replace existing files.
+fn plan_extraction(archive: &Path, format: ArchiveFormat, target: &Path, overwrite: bool) -> Result<Vec<PlannedEntry>, UploadError> {
+    let mut planned = Vec::new();
+    let mut conflicts = Vec::new();
+    for_each_entry(archive, format, |name, kind, mode, _| {
+        let relative = safe_relative(name).ok_or_else(|| format!("Archive entry {} points outside the target directory", name.display()))?;
+        let destination = target.join(&relative);
+        let resolved = sandbox::check(&destination.to_string_lossy(), sandbox::Access::Write)?;
+        if !resolved.starts_with(target) {
+            return Err(format!("Archive entry {} resolves outside the target directory", name.display()));
+        }
+        if matches!(kind, EntryKind::File) && destination.exists() && !overwrite {
+            conflicts.push(relative.display().to_string());
+        }
+        planned.push(PlannedEntry { relative, kind, mode });
+        Ok(())
+    })
+    .map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+
+    if !conflicts.is_empty() {
+        let listed = conflicts.iter().take(MAX_LISTED_CONFLICTS).cloned().collect::<Vec<_>>().join(", ");
+        return Err((
+            StatusCode::CONFLICT,
+            format!("{} files already exist ({}), set overwrite=true to replace them", conflicts.len(), listed),
+        ));
+    }
+    Ok(planned)
+}
+
+fn create_dirs(target: &Path, relative: &Path) -> Result<(), String> {
+    let mut current = target.to_path_buf();
+    for component in relative.components() {
+        current.push(component);
+        if !current.is_dir() {
+            fs::create_dir(&current).map_err(|e| format!("Failed to create {}: {}", current.display(), e))?;
+            fileio::chown_to_desktop_user(&current);
+        }
+    }
+    Ok(())
+}
+
+fn extract(archive: &Path, format: ArchiveFormat, target: &Path, planned: Vec<PlannedEntry>) -> Result<ExtractReport, String> {
+    let mut report = ExtractReport {
+        path: target.to_string_lossy().to_string(),
+        files: 0,
+        directories: 0,
+        skipped: Vec::new(),
+    };
+    let mut planned = planned.into_iter();
+    let mut buffer = vec![0u8; STREAM_CHUNK_BYTES];
+
+    for_each_entry(archive, format, |_, _, _, content| {
+        // Entries come in the same order as during planning
+        let entry = planned.next().ok_or("Archive changed during extraction")?;
+        let destination = target.join(&entry.relative);
+        match entry.kind {
+            EntryKind::Directory => {
+                create_dirs(target, &entry.relative)?;
+                report.directories += 1;
+            }
+            EntryKind::File => {
+                if let Some(parent) = entry.relative.parent() {
+                    create_dirs(target, parent)?;
+                }
+                let is_new = !destination.exists();
+                let mut file = AtomicFile::create(&destination.to_string_lossy())?;
+                loop {
+                    let read = content.read(&mut buffer).map_err(|e| format!("Failed to read archive: {}", e))?;
+                    if read == 0 {
+                        break;
+                    }
+                    file.write(&buffer[..read])?;
+                }
+                file.commit()?;
+                if let (true, Some(mode)) = (is_new, entry.mode) {
+                    let _ = fs::set_permissions(&destination, fs::Permissions::from_mode(mode));
+                }
+                report.files += 1;
+            }
+            EntryKind::Other => report.skipped.push(entry.relative.display().to_string()),
+        }
+        Ok(())
+    })?;
+    Ok(report)
+}
+
+/// Receives an archive and extracts it into the directory `path`.
+pub async fn handle_import(query: web::Query<ArchiveQuery>, mut payload: web::Payload) -> HttpResponse {
+    let requested = match query.format.as_deref().map(ArchiveFormat::parse) {
+        None => None,
+        Some(Some(format)) => Some(format),
+        Some(None) => return error_response((StatusCode::BAD_REQUEST, String::from("format should be one of: tar.gz, tar, zip"))),
+    };
+    let target = match sandbox::check(&query.path, sandbox::Access::Write) {
+        Ok(target) => target,
+        Err(e) => return error_response((StatusCode::FORBIDDEN, e)),
+    };
+    if !target.is_dir() {
+        if !query.mkdir.unwrap_or(false) {
+            return error_response((
+                StatusCode::BAD_REQUEST,
+                format!("Directory {} does not exist, set mkdir=true to create it", query.path),
+            ));
+        }
+        let created = target
+            .ancestors()
+            .find(|ancestor| ancestor.is_dir())
+            .map(Path::to_path_buf)
+            .ok_or_else(|| String::from("No existing ancestor"))
+            .and_then(|existing| create_dirs(&existing, target.strip_prefix(&existing).unwrap_or(&target)));
+        if let Err(e) = created {
+            return error_response((StatusCode::INTERNAL_SERVER_ERROR, e));
+        }
+    }
+
+    // Spool the upload to disk; both tar and zip need it more than once
+    let spool = std::env::temp_dir().join(format!(".consoley-import-{:016x}", rand::random::<u64>()));
+    let received = async {
+        let mut file = File::create(&spool).map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to create temporary file: {}", e)))?;
+        let (max_size, mut size) = (max_upload_bytes(), 0u64);
+        while let Some(chunk) = payload.next().await {
+            let chunk = chunk.map_err(|e| (StatusCode::BAD_REQUEST, format!("Failed to read request body: {}", e)))?;
+            size += chunk.len() as u64;
+            if size > max_size {
+                return Err((StatusCode::PAYLOAD_TOO_LARGE, format!("Upload exceeds the limit of {} bytes", max_size)));
+            }
+            file.write_all(&chunk).map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to store upload: {}", e)))?;
+        }
+        Ok::<_, UploadError>(())
+    }
+    .await;
+    if let Err(e) = received {
+        let _ = fs::remove_file(&spool);
+        return error_response(e);
+    }
+
+    let overwrite = query.overwrite.unwrap_or(false);
+    let spooled = spool.clone();
+    let result = web::block(move || -> Result<ExtractReport, UploadError> {
+        let mut header = [0u8; 512];
+        let read = File::open(&spooled).and_then(|mut file| file.read(&mut header)).unwrap_or(0);
+        let format = requested
+            .or_else(|| ArchiveFormat::detect(&header[..read]))
+            .ok_or((StatusCode::BAD_REQUEST, String::from("Unrecognized archive format, set format to tar.gz, tar or zip")))?;
+        let planned = plan_extraction(&spooled, format, &target, overwrite)?;
+        extract(&spooled, format, &target, planned).map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e))
+    })
+    .await;
+    let _ = fs::remove_file(&spool);
+
+    match result {
+        Ok(Ok(report)) => {
+            log::info!("Extracted {} files into {}", report.files, report.path);
+            HttpResponse::Ok().json(crate::ActionResponse {
+                r#type: String::from("success"),
+                media_type: String::from("application/json"),
+                data: serde_json::to_string(&report).unwrap_or_default(),
+            })
+        }
+        Ok(Err(e)) => error_response(e),
+        Err(e) => error_response((StatusCode::INTERNAL_SERVER_ERROR, e.to_string())),
+    }
+}
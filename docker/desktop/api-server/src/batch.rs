@@ -0,0 +1,217 @@
+use actix_web::{web, HttpResponse};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::Path;
+
+use crate::encoding::{self, TextFormat};
+use crate::{conflict, fileio, history, insert_text, sandbox, replace_matches, ActionResponse, EditCommand, EditRequest};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BatchRequest {
+    pub edits: Vec<EditRequest>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct StepResult {
+    pub index: usize,
+    pub command: String,
+    pub path: String,
+    /// "ok", "failed" or "skipped" (not validated after an earlier failure).
+    pub status: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BatchReport {
+    pub applied: bool,
+    pub steps: Vec<StepResult>,
+}
+
+/// A file touched by the batch: its content on disk before the batch and the
+/// content it has after the steps validated so far.
+struct FileState {
+    path: String,
+    original: Option<Vec<u8>>,
+    content: Option<String>,
+    /// Encoding and line endings `content` is written back with.
+    format: TextFormat,
+}
+
+/// Edits of different spellings of the same file (e.g. through a symlink)
+/// must see each other's changes.
+fn file_key(path: &str) -> String {
+    fileio::resolve_symlinks(Path::new(path))
+        .map(|resolved| resolved.to_string_lossy().to_string())
+        .unwrap_or_else(|_| path.to_string())
+}
+
+/// Applies one step to the in-memory state of its file.
+fn apply_step(req: &EditRequest, file: &mut FileState) -> Result<(), String> {
+    match req.parse_command() {
+        Some(EditCommand::Create) => {
+            let text = req.file_text.as_ref().ok_or("file_text is required for create action")?;
+            let parent = Path::new(&req.path).parent().filter(|p| !p.as_os_str().is_empty());
+            if parent.is_some_and(|parent| !parent.is_dir()) {
+                return Err(String::from("Parent directory does not exist"));
+            }
+            file.content = Some(text.clone());
+            file.format = TextFormat::default();
+        }
+        Some(EditCommand::StrReplace) => {
+            let (Some(old_str), Some(new_str)) = (&req.old_str, &req.new_str) else {
+                return Err(String::from("old_str and new_str are required for str_replace action"));
+            };
+            let content = file.content.as_ref().ok_or("File does not exist")?;
+            let (new_content, _) = replace_matches(content, old_str, new_str, req)?;
+            file.content = Some(new_content);
+        }
+        Some(EditCommand::Insert) => {
+            let (Some(text), Some(line_num)) = (&req.file_text, req.insert_line) else {
+                return Err(String::from("file_text and insert_line are required for insert action"));
+            };
+            let content = file.content.as_ref().ok_or("File does not exist")?;
+            let new_content = usize::try_from(line_num)
+                .ok()
+                .and_then(|line| insert_text(content, text, line))
+                .ok_or_else(|| format!("Line number {} is out of range", line_num))?;
+            file.content = Some(new_content);
+        }
+        _ => return Err(String::from("Only create, str_replace and insert can be used in a batch")),
+    }
+    Ok(())
+}
+
+/// Writes the final content of every touched file. If a write fails, the
+/// files written before it are restored to their original content.
+fn write_all(files: &[FileState]) -> Result<(), String> {
+    for (index, file) in files.iter().enumerate() {
+        let Some(content) = &file.content else {
+            continue;
+        };
+        let written = encoding::encode(content, &file.format).and_then(|encoded| fileio::write_atomic(&file.path, &encoded));
+        if let Err(e) = written {
+            log::error!("Failed to write {} in batch, rolling back: {}", file.path, e);
+            for written in &files[..index] {
+                if let Err(e) = history::restore(&written.path, &written.original) {
+                    log::error!("Failed to roll back {}: {}", written.path, e);
+                }
+            }
+            return Err(format!("Failed to write {}: {}", file.path, e));
+        }
+    }
+    Ok(())
+}
+
+/// Validates every edit against the files as the earlier edits leave them,
+/// and only writes anything if all of them succeed.
+pub async fn handle_batch_action(req: web::Json<BatchRequest>) -> HttpResponse {
+    if req.edits.is_empty() {
+        return HttpResponse::BadRequest().json(ActionResponse {
+            r#type: String::from("error"),
+            media_type: String::from("text/plain"),
+            data: String::from("edits must contain at least one edit"),
+        });
+    }
+
+    let _edit_guard = conflict::lock_edits();
+    let mut files: Vec<FileState> = Vec::new();
+    let mut index_by_key: HashMap<String, usize> = HashMap::new();
+    let mut report = BatchReport {
+        applied: true,
+        steps: Vec::new(),
+    };
+    let mut forbidden = false;
+    let mut conflicted = false;
+
+    for (index, edit) in req.edits.iter().enumerate() {
+        let mut step = StepResult {
+            index,
+            command: edit.command.clone(),
+            path: edit.path.clone(),
+            status: String::from("skipped"),
+            error: None,
+        };
+        if !report.applied {
+            report.steps.push(step);
+            continue;
+        }
+
+        if let Err(e) = sandbox::check(&edit.path, sandbox::Access::Write) {
+            step.status = String::from("failed");
+            step.error = Some(e);
+            report.applied = false;
+            forbidden = true;
+            report.steps.push(step);
+            continue;
+        }
+
+        let key = file_key(&edit.path);
+        let slot = match index_by_key.get(&key) {
+            Some(&slot) => Ok(slot),
+            None => history::read_current(&edit.path).and_then(|original| {
+                let decoded = original.as_deref().map(encoding::decode).transpose()?;
+                let (content, format) = decoded.map_or((None, TextFormat::default()), |(text, format)| (Some(text), format));
+                files.push(FileState {
+                    path: edit.path.clone(),
+                    original,
+                    content,
+                    format,
+                });
+                index_by_key.insert(key, files.len() - 1);
+                Ok(files.len() - 1)
+            }),
+        };
+        // expected_hash refers to the file as it was before the batch
+        let slot = slot.and_then(|slot| match &edit.expected_hash {
+            Some(expected) => {
+                let checked = conflict::check(&edit.path, expected, files[slot].original.as_deref());
+                conflicted |= checked.is_err();
+                checked.map(|_| slot)
+            }
+            None => Ok(slot),
+        });
+        match slot.and_then(|slot| apply_step(edit, &mut files[slot])) {
+            Ok(_) => step.status = String::from("ok"),
+            Err(e) => {
+                step.status = String::from("failed");
+                step.error = Some(e);
+                report.applied = false;
+            }
+        }
+        report.steps.push(step);
+    }
+
+    if !report.applied {
+        let mut response = if forbidden {
+            HttpResponse::Forbidden()
+        } else if conflicted {
+            HttpResponse::Conflict()
+        } else {
+            HttpResponse::BadRequest()
+        };
+        return response.json(ActionResponse {
+            r#type: String::from("error"),
+            media_type: String::from("application/json"),
+            data: serde_json::to_string(&report).unwrap_or_default(),
+        });
+    }
+
+    if let Err(e) = write_all(&files) {
+        return HttpResponse::InternalServerError().json(ActionResponse {
+            r#type: String::from("error"),
+            media_type: String::from("text/plain"),
+            data: e,
+        });
+    }
+    for file in files {
+        history::record(&file.path, "batch", file.original);
+    }
+
+    log::info!("Batch of {} edits applied", report.steps.len());
+    HttpResponse::Ok().json(ActionResponse {
+        r#type: String::from("success"),
+        media_type: String::from("application/json"),
+        data: serde_json::to_string(&report).unwrap_or_default(),
+    })
+}
@@ -0,0 +1,106 @@
+use actix_web::http::header::{HeaderValue, ETAG};
+use actix_web::HttpResponse;
+use sha2::{Digest, Sha256};
+use std::collections::VecDeque;
+use std::sync::{Mutex, MutexGuard};
+
+use crate::{history, view};
+
+/// Versions handed out with a hash are kept so a conflict can show what
+/// changed since; the oldest are dropped first.
+const MAX_REMEMBERED_VERSIONS: usize = 100;
+/// Larger versions are not kept; their conflicts are reported without a diff.
+const MAX_REMEMBERED_BYTES: usize = 1024 * 1024;
+
+static EDIT_LOCK: Mutex<()> = Mutex::new(());
+static SEEN_VERSIONS: Mutex<VecDeque<SeenVersion>> = Mutex::new(VecDeque::new());
+
+/// File content a client was given the hash of.
+struct SeenVersion {
+    hash: String,
+    content: Vec<u8>,
+}
+
+/// Serializes edits, so no other edit can land between checking
+/// `expected_hash` and writing the file.
+pub fn lock_edits() -> MutexGuard<'static, ()> {
+    EDIT_LOCK.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+pub fn content_hash(content: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(content))
+}
+
+/// Returns the hash of `content` and remembers the content under it.
+fn remember(content: &[u8]) -> String {
+    let hash = content_hash(content);
+    if content.len() <= MAX_REMEMBERED_BYTES {
+        let mut seen = SEEN_VERSIONS.lock().unwrap();
+        seen.retain(|version| version.hash != hash);
+        seen.push_back(SeenVersion {
+            hash: hash.clone(),
+            content: content.to_vec(),
+        });
+        if seen.len() > MAX_REMEMBERED_VERSIONS {
+            seen.pop_front();
+        }
+    }
+    hash
+}
+
+/// Sends the hash of the file content the client now knows as `ETag`, to be
+/// passed back as `expected_hash`.
+pub fn with_content_hash(mut response: HttpResponse, content: &[u8]) -> HttpResponse {
+    let hash = remember(content);
+    if let Ok(value) = HeaderValue::from_str(&format!("\"{}\"", hash)) {
+        response.headers_mut().insert(ETAG, value);
+    }
+    response
+}
+
+/// Like `with_content_hash`, for the content currently on disk.
+pub fn with_current_hash(response: HttpResponse, path: &str) -> HttpResponse {
+    match history::read_current(path) {
+        Ok(Some(content)) => with_content_hash(response, &content),
+        _ => response,
+    }
+}
+
+/// Accepts the hash with or without the quotes of an `ETag`.
+fn normalize(hash: &str) -> String {
+    hash.trim().trim_matches('"').to_ascii_lowercase()
+}
+
+/// Checks that `current` (the file content, `None` if it does not exist)
+/// still has the hash the client expects. The error explains the conflict
+/// and, if the expected version is known, shows how the file changed since.
+pub fn check(path: &str, expected: &str, current: Option<&[u8]>) -> Result<(), String> {
+    let expected = normalize(expected);
+    let Some(current) = current else {
+        return Err(format!("Conflict: {} no longer exists, but expected_hash {} was given", path, expected));
+    };
+    let actual = content_hash(current);
+    if actual == expected {
+        return Ok(());
+    }
+
+    let seen = SEEN_VERSIONS
+        .lock()
+        .unwrap()
+        .iter()
+        .find(|version| version.hash == expected)
+        .map(|version| version.content.clone());
+    let details = match seen {
+        Some(seen) => view::edit_feedback(
+            path,
+            &String::from_utf8_lossy(&seen),
+            &String::from_utf8_lossy(current),
+            view::ResponseFormat::Diff,
+        ),
+        None => String::from("The expected version is unknown; view the file again before editing."),
+    };
+    Err(format!(
+        "Conflict: {} was modified since it was viewed (expected hash {}, current hash {}).\n{}",
+        path, expected, actual, details
+    ))
+}
@@ -0,0 +1,189 @@
+/// Bytes looked at when guessing whether a file without BOM is UTF-16.
+const UTF16_SAMPLE_BYTES: usize = 4096;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Encoding {
+    Utf8,
+    Utf16Le,
+    Utf16Be,
+    /// Used for files that are not valid UTF-8; every byte is one character,
+    /// so any file round-trips unchanged.
+    Latin1,
+}
+
+impl Encoding {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Encoding::Utf8 => "UTF-8",
+            Encoding::Utf16Le => "UTF-16LE",
+            Encoding::Utf16Be => "UTF-16BE",
+            Encoding::Latin1 => "ISO-8859-1",
+        }
+    }
+
+    fn bom(&self) -> &'static [u8] {
+        match self {
+            Encoding::Utf8 => &[0xEF, 0xBB, 0xBF],
+            Encoding::Utf16Le => &[0xFF, 0xFE],
+            Encoding::Utf16Be => &[0xFE, 0xFF],
+            Encoding::Latin1 => &[],
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LineEnding {
+    Lf,
+    CrLf,
+    /// Both styles occur; the text is edited as is.
+    Mixed,
+}
+
+/// How a text file is stored on disk.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TextFormat {
+    pub encoding: Encoding,
+    pub bom: bool,
+    pub line_ending: LineEnding,
+    pub trailing_newline: bool,
+}
+
+impl Default for TextFormat {
+    fn default() -> Self {
+        TextFormat {
+            encoding: Encoding::Utf8,
+            bom: false,
+            line_ending: LineEnding::Lf,
+            trailing_newline: true,
+        }
+    }
+}
+
+impl TextFormat {
+    /// Describes the format for responses, or `None` for plain UTF-8 with LF
+    /// line endings.
+    pub fn describe(&self) -> Option<String> {
+        let mut parts = Vec::new();
+        if self.encoding != Encoding::Utf8 || self.bom {
+            parts.push(format!("{}{}", self.encoding.name(), if self.bom { " with BOM" } else { "" }));
+        }
+        match self.line_ending {
+            LineEnding::Lf => {}
+            LineEnding::CrLf => parts.push(String::from("CRLF line endings")),
+            LineEnding::Mixed => parts.push(String::from("mixed line endings")),
+        }
+        if parts.is_empty() {
+            return None;
+        }
+        if !self.trailing_newline {
+            parts.push(String::from("no newline at end of file"));
+        }
+        Some(parts.join(", "))
+    }
+}
+
+/// Recognizes UTF-16 without BOM by the NUL bytes ASCII characters have in
+/// their high byte.
+fn guess_utf16(bytes: &[u8]) -> Option<Encoding> {
+    if bytes.len() < 2 || !bytes.len().is_multiple_of(2) {
+        return None;
+    }
+    let sample = &bytes[..bytes.len().min(UTF16_SAMPLE_BYTES)];
+    let pairs = sample.len() / 2;
+    let zeros_at = |offset: usize| sample.iter().skip(offset).step_by(2).filter(|&&b| b == 0).count();
+    let (even, odd) = (zeros_at(0), zeros_at(1));
+    if odd * 2 > pairs && even * 10 < pairs {
+        Some(Encoding::Utf16Le)
+    } else if even * 2 > pairs && odd * 10 < pairs {
+        Some(Encoding::Utf16Be)
+    } else {
+        None
+    }
+}
+
+fn decode_utf16(bytes: &[u8], encoding: Encoding) -> Option<String> {
+    if !bytes.len().is_multiple_of(2) {
+        return None;
+    }
+    let units: Vec<u16> = bytes
+        .chunks_exact(2)
+        .map(|pair| match encoding {
+            Encoding::Utf16Be => u16::from_be_bytes([pair[0], pair[1]]),
+            _ => u16::from_le_bytes([pair[0], pair[1]]),
+        })
+        .collect();
+    String::from_utf16(&units).ok()
+}
+
+fn detect_line_ending(text: &str) -> LineEnding {
+    let crlf = text.matches("\r\n").count();
+    if crlf == 0 {
+        LineEnding::Lf
+    } else if crlf == text.matches('\n').count() {
+        LineEnding::CrLf
+    } else {
+        LineEnding::Mixed
+    }
+}
+
+/// Decodes file content for editing. CRLF line endings are turned into `\n`;
+/// `encode` restores them.
+pub fn decode(bytes: &[u8]) -> Result<(String, TextFormat), String> {
+    let bom = [Encoding::Utf8, Encoding::Utf16Le, Encoding::Utf16Be]
+        .into_iter()
+        .find(|encoding| bytes.starts_with(encoding.bom()));
+    let (text, encoding) = match bom {
+        Some(Encoding::Utf8) => (String::from_utf8(bytes[3..].to_vec()).ok(), Encoding::Utf8),
+        Some(encoding) => (decode_utf16(&bytes[2..], encoding), encoding),
+        None => match guess_utf16(bytes).and_then(|encoding| decode_utf16(bytes, encoding).map(|text| (text, encoding))) {
+            Some((text, encoding)) => (Some(text), encoding),
+            None => match std::str::from_utf8(bytes) {
+                Ok(text) => (Some(text.to_string()), Encoding::Utf8),
+                // Other binary files would be mangled by editing them as text
+                Err(_) if bytes.contains(&0) => (None, Encoding::Latin1),
+                Err(_) => (Some(bytes.iter().map(|&b| b as char).collect()), Encoding::Latin1),
+            },
+        },
+    };
+    let Some(text) = text else {
+        return Err(String::from(
+            "File is not text in a supported encoding (UTF-8, UTF-16 or ISO-8859-1)",
+        ));
+    };
+
+    let line_ending = detect_line_ending(&text);
+    let format = TextFormat {
+        encoding,
+        bom: bom.is_some(),
+        line_ending,
+        trailing_newline: text.is_empty() || text.ends_with('\n'),
+    };
+    let text = match line_ending {
+        LineEnding::CrLf => text.replace("\r\n", "\n"),
+        _ => text,
+    };
+    Ok((text, format))
+}
+
+/// Encodes edited text in `format`. Fails if the text contains characters
+/// the encoding cannot represent.
+pub fn encode(text: &str, format: &TextFormat) -> Result<Vec<u8>, String> {
+    let text = match format.line_ending {
+        LineEnding::CrLf => text.replace("\r\n", "\n").replace('\n', "\r\n"),
+        _ => text.to_string(),
+    };
+    let mut bytes = if format.bom { format.encoding.bom().to_vec() } else { Vec::new() };
+    match format.encoding {
+        Encoding::Utf8 => bytes.extend_from_slice(text.as_bytes()),
+        Encoding::Utf16Le => bytes.extend(text.encode_utf16().flat_map(u16::to_le_bytes)),
+        Encoding::Utf16Be => bytes.extend(text.encode_utf16().flat_map(u16::to_be_bytes)),
+        Encoding::Latin1 => {
+            for c in text.chars() {
+                let byte = u8::try_from(u32::from(c))
+                    .map_err(|_| format!("{:?} cannot be written to a file encoded as {}", c, format.encoding.name()))?;
+                bytes.push(byte);
+            }
+        }
+    }
+    Ok(bytes)
+}
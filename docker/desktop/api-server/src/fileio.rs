@@ -0,0 +1,181 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::os::unix::fs::{MetadataExt, OpenOptionsExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+
+/// Owner of files created by the server. supervisord runs the server as this
+/// user, so new files already belong to it; handing them over only matters
+/// when the server runs as root, e.g. outside the container setup.
+const DESKTOP_USER: &str = "consoley";
+/// Same limit as the kernel's for following nested symlinks.
+const MAX_SYMLINK_DEPTH: usize = 40;
+/// Mode of newly created files, subject to the process umask.
+const DEFAULT_FILE_MODE: u32 = 0o644;
+
+/// Follows symlinks from `path` to the file that should actually be written.
+/// The target does not need to exist, so dangling links are resolved too.
+pub fn resolve_symlinks(path: &Path) -> Result<PathBuf, String> {
+    let mut resolved = path.to_path_buf();
+    for _ in 0..MAX_SYMLINK_DEPTH {
+        match fs::symlink_metadata(&resolved) {
+            Ok(meta) if meta.file_type().is_symlink() => {
+                let target = fs::read_link(&resolved)
+                    .map_err(|e| format!("Failed to read symlink {}: {}", resolved.display(), e))?;
+                resolved = match resolved.parent() {
+                    Some(parent) if target.is_relative() => parent.join(target),
+                    _ => target,
+                };
+            }
+            _ => return Ok(resolved),
+        }
+    }
+    Err(format!("Too many levels of symbolic links: {}", path.display()))
+}
+
+/// Looks up the fields of `user`'s line in /etc/passwd.
+fn passwd_entry(user: &str) -> Option<Vec<String>> {
+    let passwd = fs::read_to_string("/etc/passwd").ok()?;
+    passwd
+        .lines()
+        .map(|line| line.split(':').map(String::from).collect::<Vec<_>>())
+        .find(|fields| fields.first().is_some_and(|name| name == user))
+}
+
+/// Looks up the uid and gid of `user` in /etc/passwd.
+fn user_ids(user: &str) -> Option<(u32, u32)> {
+    match passwd_entry(user)?.as_slice() {
+        [_, _, uid, gid, ..] => Some((uid.parse().ok()?, gid.parse().ok()?)),
+        _ => None,
+    }
+}
+
+/// Home directory of the desktop user, if the user exists.
+pub fn desktop_home() -> Option<PathBuf> {
+    match passwd_entry(DESKTOP_USER)?.as_slice() {
+        [_, _, _, _, _, home, ..] if !home.is_empty() => Some(PathBuf::from(home)),
+        _ => None,
+    }
+}
+
+/// Logs a failed ownership change. Without root it is expected, as only
+/// root may give files away; as root it means the owner is actually wrong.
+pub fn log_chown_failure(path: &Path, e: &std::io::Error) {
+    if unsafe { libc::geteuid() } == 0 {
+        log::warn!("Failed to change owner of {}: {}", path.display(), e);
+    } else {
+        log::debug!("Failed to change owner of {}: {}", path.display(), e);
+    }
+}
+
+/// Hands `path` to the desktop user. Failures are only logged, since without
+/// root the file already belongs to the user the server runs as.
+pub fn chown_to_desktop_user(path: &Path) {
+    if let Some((uid, gid)) = user_ids(DESKTOP_USER) {
+        if let Err(e) = std::os::unix::fs::chown(path, Some(uid), Some(gid)) {
+            log_chown_failure(path, &e);
+        }
+    }
+}
+
+fn temp_path(target: &Path) -> PathBuf {
+    let name = target.file_name().map(|n| n.to_string_lossy()).unwrap_or_default();
+    target.with_file_name(format!(".{}.{}.{:08x}.tmp", name, std::process::id(), rand::random::<u32>()))
+}
+
+/// A file being written next to its target. The content only replaces the
+/// target on `commit`; if the `AtomicFile` is dropped before, the temporary
+/// file is removed and the target is left untouched.
+pub struct AtomicFile {
+    file: File,
+    temp: PathBuf,
+    target: PathBuf,
+    committed: bool,
+}
+
+impl AtomicFile {
+    /// Starts writing `path`. Symlinks are followed, and the mode and owner of
+    /// an existing file are kept. New files are owned by the desktop user.
+    pub fn create(path: &str) -> Result<Self, String> {
+        let target = resolve_symlinks(Path::new(path))?;
+        let existing = match fs::metadata(&target) {
+            Ok(meta) if meta.is_dir() => return Err(format!("{} is a directory", target.display())),
+            Ok(meta) => Some(meta),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+            Err(e) => return Err(format!("Failed to read file metadata: {}", e)),
+        };
+
+        let mode = existing.as_ref().map(|meta| meta.mode() & 0o7777).unwrap_or(DEFAULT_FILE_MODE);
+        let temp = temp_path(&target);
+        let file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(mode)
+            .open(&temp)
+            .map_err(|e| format!("Failed to create temporary file: {}", e))?;
+        let atomic = AtomicFile {
+            file,
+            temp,
+            target,
+            committed: false,
+        };
+
+        let owner = match &existing {
+            Some(meta) => Some((meta.uid(), meta.gid())),
+            None => user_ids(DESKTOP_USER),
+        };
+        if let Some((uid, gid)) = owner {
+            // Only root may change ownership; elsewhere the file keeps our own
+            if let Err(e) = std::os::unix::fs::fchown(&atomic.file, Some(uid), Some(gid)) {
+                log_chown_failure(&atomic.temp, &e);
+            }
+        }
+        // The umask only applies on creation, so existing modes are set
+        // explicitly. This comes after the chown, which clears setuid/setgid.
+        if existing.is_some() {
+            atomic
+                .file
+                .set_permissions(fs::Permissions::from_mode(mode))
+                .map_err(|e| format!("Failed to set permissions: {}", e))?;
+        }
+        Ok(atomic)
+    }
+
+    pub fn write(&mut self, content: &[u8]) -> Result<(), String> {
+        self.file
+            .write_all(content)
+            .map_err(|e| format!("Failed to write file: {}", e))
+    }
+
+    /// Flushes the content to disk and renames it over the target.
+    pub fn commit(mut self) -> Result<(), String> {
+        self.file
+            .sync_all()
+            .map_err(|e| format!("Failed to sync file: {}", e))?;
+        fs::rename(&self.temp, &self.target).map_err(|e| format!("Failed to replace file: {}", e))?;
+        self.committed = true;
+
+        // Persist the rename itself
+        let parent = self.target.parent().filter(|p| !p.as_os_str().is_empty()).unwrap_or(Path::new("."));
+        if let Err(e) = File::open(parent).and_then(|dir| dir.sync_all()) {
+            log::debug!("Failed to sync directory {}: {}", parent.display(), e);
+        }
+        Ok(())
+    }
+}
+
+impl Drop for AtomicFile {
+    fn drop(&mut self) {
+        if !self.committed {
+            let _ = fs::remove_file(&self.temp);
+        }
+    }
+}
+
+/// Writes `content` to `path` atomically: the data goes to a temporary file in
+/// the same directory, is flushed to disk and then renamed over the target.
+/// See `AtomicFile::create` for how symlinks, mode and owner are handled.
+pub fn write_atomic(path: &str, content: &[u8]) -> Result<(), String> {
+    let mut file = AtomicFile::create(path)?;
+    file.write(content)?;
+    file.commit()
+}
@@ -0,0 +1,258 @@
+use actix_files::NamedFile;
+use actix_multipart::Multipart;
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpRequest, HttpResponse};
+use futures_util::StreamExt;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::path::Path;
+
+use crate::fileio::AtomicFile;
+use crate::{fsops, sandbox, ActionResponse};
+
+/// Uploads larger than this are rejected unless `CONSOLEY_MAX_UPLOAD_BYTES`
+/// says otherwise.
+const DEFAULT_MAX_UPLOAD_BYTES: u64 = 2 * 1024 * 1024 * 1024;
+
+#[derive(Debug, Deserialize)]
+pub struct FileQuery {
+    pub path: String,
+    /// Replace an existing file instead of failing.
+    pub overwrite: Option<bool>,
+    /// Create missing parent directories.
+    pub mkdir: Option<bool>,
+    /// Expected SHA-256 of the uploaded content, as hex.
+    pub sha256: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct UploadedFile {
+    pub path: String,
+    pub size: u64,
+    pub sha256: String,
+}
+
+pub type UploadError = (StatusCode, String);
+
+pub fn error_response((status, message): UploadError) -> HttpResponse {
+    HttpResponse::build(status).json(ActionResponse {
+        r#type: String::from("error"),
+        media_type: String::from("text/plain"),
+        data: message,
+    })
+}
+
+pub fn max_upload_bytes() -> u64 {
+    std::env::var("CONSOLEY_MAX_UPLOAD_BYTES")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(DEFAULT_MAX_UPLOAD_BYTES)
+}
+
+/// A file being received. Nothing is visible at `path` until it is committed.
+struct Upload {
+    path: String,
+    file: AtomicFile,
+    hasher: Sha256,
+    size: u64,
+    max_size: u64,
+}
+
+impl Upload {
+    fn begin(path: &str, overwrite: bool, mkdir: bool) -> Result<Self, UploadError> {
+        sandbox::check(path, sandbox::Access::Write).map_err(|e| (StatusCode::FORBIDDEN, e))?;
+        let target = Path::new(path);
+        if target.is_dir() {
+            return Err((StatusCode::BAD_REQUEST, format!("{} is a directory", path)));
+        }
+        if target.exists() && !overwrite {
+            return Err((StatusCode::CONFLICT, format!("{} already exists, set overwrite=true to replace it", path)));
+        }
+        if let Some(parent) = target.parent().filter(|parent| !parent.exists()) {
+            if !mkdir {
+                return Err((
+                    StatusCode::BAD_REQUEST,
+                    format!("Directory {} does not exist, set mkdir=true to create it", parent.display()),
+                ));
+            }
+            fsops::create_dirs(&fsops::missing_dirs(parent)).map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e))?;
+        }
+
+        Ok(Upload {
+            path: path.to_string(),
+            file: AtomicFile::create(path).map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e))?,
+            hasher: Sha256::new(),
+            size: 0,
+            max_size: max_upload_bytes(),
+        })
+    }
+
+    fn write(&mut self, chunk: &[u8]) -> Result<(), UploadError> {
+        self.size += chunk.len() as u64;
+        if self.size > self.max_size {
+            return Err((
+                StatusCode::PAYLOAD_TOO_LARGE,
+                format!("Upload of {} exceeds the limit of {} bytes", self.path, self.max_size),
+            ));
+        }
+        self.hasher.update(chunk);
+        self.file.write(chunk).map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e))
+    }
+
+    /// Verifies the checksum, if one was given, and returns the result
+    /// without publishing the file yet.
+    fn verify(self, expected: Option<&str>) -> Result<(AtomicFile, UploadedFile), UploadError> {
+        let sha256 = format!("{:x}", self.hasher.finalize());
+        if let Some(expected) = expected {
+            if !expected.trim().eq_ignore_ascii_case(&sha256) {
+                return Err((
+                    StatusCode::BAD_REQUEST,
+                    format!("Checksum mismatch for {}: expected {}, got {}", self.path, expected.trim(), sha256),
+                ));
+            }
+        }
+        let uploaded = UploadedFile {
+            path: self.path,
+            size: self.size,
+            sha256,
+        };
+        Ok((self.file, uploaded))
+    }
+}
+
+/// Streams a file, honouring `Range` requests.
+pub async fn handle_download(req: HttpRequest, query: web::Query<FileQuery>) -> HttpResponse {
+    if let Err(e) = sandbox::check(&query.path, sandbox::Access::Read) {
+        return error_response((StatusCode::FORBIDDEN, e));
+    }
+    if Path::new(&query.path).is_dir() {
+        return error_response((StatusCode::BAD_REQUEST, format!("{} is a directory", query.path)));
+    }
+    match NamedFile::open_async(&query.path).await {
+        Ok(file) => file.use_etag(true).use_last_modified(true).into_response(&req),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            error_response((StatusCode::NOT_FOUND, format!("File not found: {}", query.path)))
+        }
+        Err(e) => error_response((StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to open file: {}", e))),
+    }
+}
+
+/// Stores the raw request body at `path`.
+pub async fn handle_put(req: HttpRequest, query: web::Query<FileQuery>, mut payload: web::Payload) -> HttpResponse {
+    let max_size = max_upload_bytes();
+    let declared = req
+        .headers()
+        .get(actix_web::http::header::CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<u64>().ok());
+    if declared.is_some_and(|length| length > max_size) {
+        return error_response((
+            StatusCode::PAYLOAD_TOO_LARGE,
+            format!("Upload exceeds the limit of {} bytes", max_size),
+        ));
+    }
+
+    let mut upload = match Upload::begin(&query.path, query.overwrite.unwrap_or(false), query.mkdir.unwrap_or(false)) {
+        Ok(upload) => upload,
+        Err(e) => return error_response(e),
+    };
+    while let Some(chunk) = payload.next().await {
+        let result = match chunk {
+            Ok(chunk) => upload.write(&chunk),
+            Err(e) => Err((StatusCode::BAD_REQUEST, format!("Failed to read request body: {}", e))),
+        };
+        if let Err(e) = result {
+            return error_response(e);
+        }
+    }
+
+    let (file, uploaded) = match upload.verify(query.sha256.as_deref()) {
+        Ok(result) => result,
+        Err(e) => return error_response(e),
+    };
+    if let Err(e) = file.commit() {
+        return error_response((StatusCode::INTERNAL_SERVER_ERROR, e));
+    }
+    log::info!("Uploaded {} ({} bytes)", uploaded.path, uploaded.size);
+    HttpResponse::Ok().json(ActionResponse {
+        r#type: String::from("success"),
+        media_type: String::from("application/json"),
+        data: serde_json::to_string(&uploaded).unwrap_or_default(),
+    })
+}
+
+/// Reads the multipart form into staged uploads. A text field named `sha256`
+/// sets the expected checksum of the file part that follows it.
+async fn receive_parts(query: &FileQuery, mut multipart: Multipart) -> Result<Vec<(AtomicFile, UploadedFile)>, UploadError> {
+    let mut staged = Vec::new();
+    let mut expected_sha256: Option<String> = None;
+
+    while let Some(field) = multipart.next().await {
+        let mut field = field.map_err(|e| (StatusCode::BAD_REQUEST, format!("Invalid multipart body: {}", e)))?;
+        let filename = field
+            .content_disposition()
+            .and_then(|disposition| disposition.get_filename())
+            .map(str::to_string);
+
+        let Some(filename) = filename else {
+            // Plain form field
+            let mut value = Vec::new();
+            while let Some(chunk) = field.next().await {
+                let chunk = chunk.map_err(|e| (StatusCode::BAD_REQUEST, format!("Invalid multipart body: {}", e)))?;
+                value.extend_from_slice(&chunk);
+            }
+            if field.name() == Some("sha256") {
+                expected_sha256 = Some(String::from_utf8_lossy(&value).to_string());
+            }
+            continue;
+        };
+
+        // Only the file name is used, so parts cannot point outside the directory
+        let name = Path::new(&filename)
+            .file_name()
+            .filter(|name| *name != "..")
+            .ok_or_else(|| (StatusCode::BAD_REQUEST, format!("Invalid file name: {}", filename)))?;
+        let path = Path::new(&query.path).join(name).to_string_lossy().to_string();
+        if staged.iter().any(|(_, uploaded): &(AtomicFile, UploadedFile)| uploaded.path == path) {
+            return Err((StatusCode::BAD_REQUEST, format!("{} is uploaded more than once", path)));
+        }
+
+        let mut upload = Upload::begin(&path, query.overwrite.unwrap_or(false), query.mkdir.unwrap_or(false))?;
+        while let Some(chunk) = field.next().await {
+            let chunk = chunk.map_err(|e| (StatusCode::BAD_REQUEST, format!("Invalid multipart body: {}", e)))?;
+            upload.write(&chunk)?;
+        }
+        staged.push(upload.verify(expected_sha256.take().as_deref())?);
+    }
+
+    if staged.is_empty() {
+        return Err((StatusCode::BAD_REQUEST, String::from("No file parts found in multipart body")));
+    }
+    Ok(staged)
+}
+
+/// Stores every file part of a multipart form in the directory `path`. The
+/// files are only published once all of them have been received and verified.
+pub async fn handle_multipart(query: web::Query<FileQuery>, multipart: Multipart) -> HttpResponse {
+    let staged = match receive_parts(&query, multipart).await {
+        Ok(staged) => staged,
+        Err(e) => return error_response(e),
+    };
+
+    let mut files = Vec::new();
+    for (file, uploaded) in staged {
+        if let Err(e) = file.commit() {
+            return error_response((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Failed to store {}: {}", uploaded.path, e),
+            ));
+        }
+        log::info!("Uploaded {} ({} bytes)", uploaded.path, uploaded.size);
+        files.push(uploaded);
+    }
+    HttpResponse::Ok().json(ActionResponse {
+        r#type: String::from("success"),
+        media_type: String::from("application/json"),
+        data: serde_json::json!({ "files": files }).to_string(),
+    })
+}
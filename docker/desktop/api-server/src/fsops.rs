@@ -0,0 +1,520 @@
+use actix_web::http::StatusCode;
+use actix_web::HttpResponse;
+use serde::Serialize;
+use std::fs;
+use std::io::ErrorKind;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::history::{self, Snapshot};
+use crate::{fileio, sandbox, ActionResponse, EditCommand, EditRequest};
+
+#[derive(Debug, Serialize)]
+pub struct FileStat {
+    pub path: String,
+    /// "file", "directory", "symlink" or "other". Symlinks are not followed.
+    pub kind: String,
+    pub size: u64,
+    /// Permission bits in octal, e.g. "0644".
+    pub mode: String,
+    /// The permission bits as `ls -l` shows them, e.g. "rw-r--r--".
+    pub permissions: String,
+    pub uid: u32,
+    pub gid: u32,
+    pub modified_ms: i64,
+    pub accessed_ms: i64,
+    /// Where a symlink points.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub target: Option<String>,
+    /// Number of entries in a directory.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub entries: Option<usize>,
+}
+
+#[derive(Debug, Serialize)]
+struct OperationReport {
+    message: String,
+    /// The entry the operation created or changed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stat: Option<FileStat>,
+    /// Where `delete` put the entry.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    trash_path: Option<String>,
+}
+
+fn not_found(path: &Path, e: std::io::Error) -> (StatusCode, String) {
+    if e.kind() == ErrorKind::NotFound {
+        (StatusCode::NOT_FOUND, format!("{} does not exist", path.display()))
+    } else {
+        (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to read metadata of {}: {}", path.display(), e))
+    }
+}
+
+fn permission_string(mode: u32) -> String {
+    (0..9)
+        .map(|bit| if mode & (0o400 >> bit) != 0 { ['r', 'w', 'x'][bit % 3] } else { '-' })
+        .collect()
+}
+
+pub fn stat(path: &Path) -> Result<FileStat, (StatusCode, String)> {
+    let meta = fs::symlink_metadata(path).map_err(|e| not_found(path, e))?;
+    let file_type = meta.file_type();
+    let kind = if file_type.is_symlink() {
+        "symlink"
+    } else if file_type.is_dir() {
+        "directory"
+    } else if file_type.is_file() {
+        "file"
+    } else {
+        "other"
+    };
+    Ok(FileStat {
+        path: path.to_string_lossy().to_string(),
+        kind: kind.to_string(),
+        size: meta.len(),
+        mode: format!("{:04o}", meta.mode() & 0o7777),
+        permissions: permission_string(meta.mode()),
+        uid: meta.uid(),
+        gid: meta.gid(),
+        modified_ms: meta.mtime() * 1000 + meta.mtime_nsec() / 1_000_000,
+        accessed_ms: meta.atime() * 1000 + meta.atime_nsec() / 1_000_000,
+        target: file_type
+            .is_symlink()
+            .then(|| fs::read_link(path).ok())
+            .flatten()
+            .map(|target| target.to_string_lossy().to_string()),
+        entries: file_type
+            .is_dir()
+            .then(|| fs::read_dir(path).ok())
+            .flatten()
+            .map(|entries| entries.count()),
+    })
+}
+
+/// Permission bits of `path`, following symlinks.
+pub fn mode(path: &Path) -> Result<u32, String> {
+    fs::metadata(path)
+        .map(|meta| meta.mode() & 0o7777)
+        .map_err(|e| format!("Failed to read metadata of {}: {}", path.display(), e))
+}
+
+pub fn set_mode(path: &Path, mode: u32) -> Result<(), String> {
+    fs::set_permissions(path, fs::Permissions::from_mode(mode))
+        .map_err(|e| format!("Failed to change mode of {}: {}", path.display(), e))
+}
+
+/// Applies a chmod mode to `current`: octal bits ("755", "0o644") or
+/// symbolic clauses ("+x", "u+x,go-w", "a=r").
+fn parse_mode(spec: &str, current: u32) -> Option<u32> {
+    let octal = spec.strip_prefix("0o").unwrap_or(spec);
+    if !octal.is_empty() && octal.chars().all(|c| c.is_digit(8)) {
+        return u32::from_str_radix(octal, 8).ok().filter(|mode| *mode <= 0o7777);
+    }
+
+    let mut mode = current;
+    for clause in spec.split(',') {
+        let (who, rest) = clause.split_at(clause.find(['+', '-', '='])?);
+        let (op, perms) = rest.split_at(1);
+        let mut who_mask = if who.is_empty() { 0o7777 } else { 0 };
+        for c in who.chars() {
+            who_mask |= match c {
+                'u' => 0o4700,
+                'g' => 0o2070,
+                'o' => 0o1007,
+                'a' => 0o7777,
+                _ => return None,
+            };
+        }
+        let mut bits = 0;
+        for c in perms.chars() {
+            bits |= match c {
+                'r' => 0o444,
+                'w' => 0o222,
+                'x' => 0o111,
+                's' => 0o6000,
+                't' => 0o1000,
+                _ => return None,
+            };
+        }
+        bits &= who_mask;
+        mode = match op {
+            "+" => mode | bits,
+            "-" => mode & !bits,
+            _ => (mode & !who_mask) | bits,
+        };
+    }
+    Some(mode)
+}
+
+/// The directories that have to be created for `path` to exist, outermost
+/// first.
+pub fn missing_dirs(path: &Path) -> Vec<PathBuf> {
+    let mut dirs: Vec<PathBuf> = path
+        .ancestors()
+        .take_while(|dir| fs::symlink_metadata(dir).is_err())
+        .map(Path::to_path_buf)
+        .collect();
+    dirs.reverse();
+    dirs
+}
+
+/// Creates `dirs` in order, owned by the desktop user. If one fails, the
+/// ones created before it are removed again.
+pub fn create_dirs(dirs: &[PathBuf]) -> Result<(), String> {
+    for (index, dir) in dirs.iter().enumerate() {
+        if let Err(e) = fs::create_dir(dir) {
+            if let Err(e) = remove_dirs(&dirs[..index]) {
+                log::error!("Failed to clean up after mkdir: {}", e);
+            }
+            return Err(format!("Failed to create directory {}: {}", dir.display(), e));
+        }
+        fileio::chown_to_desktop_user(dir);
+    }
+    Ok(())
+}
+
+/// Removes `dirs` innermost first. They have to be empty apart from each other.
+pub fn remove_dirs(dirs: &[PathBuf]) -> Result<(), String> {
+    for dir in dirs.iter().rev() {
+        match fs::remove_dir(dir) {
+            Err(e) if e.kind() != ErrorKind::NotFound => {
+                return Err(format!("Failed to remove directory {}: {}", dir.display(), e))
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Copies an entry, recursing into directories and copying symlinks as
+/// links. Modes are kept; the copies are given to the desktop user unless
+/// `keep_owner` is set. Returns the number of entries copied.
+fn copy_entry(from: &Path, to: &Path, keep_owner: bool) -> std::io::Result<usize> {
+    let meta = fs::symlink_metadata(from)?;
+    let file_type = meta.file_type();
+    let mut copied = 1;
+    if file_type.is_symlink() {
+        std::os::unix::fs::symlink(fs::read_link(from)?, to)?;
+    } else if file_type.is_dir() {
+        fs::create_dir(to)?;
+        for entry in fs::read_dir(from)? {
+            let entry = entry?;
+            copied += copy_entry(&entry.path(), &to.join(entry.file_name()), keep_owner)?;
+        }
+        // Set last, so that read-only directories can be filled first
+        fs::set_permissions(to, meta.permissions())?;
+    } else if file_type.is_file() {
+        fs::copy(from, to)?;
+    } else {
+        log::debug!("Skipping special file {}", from.display());
+        return Ok(0);
+    }
+
+    if keep_owner {
+        if let Err(e) = std::os::unix::fs::lchown(to, Some(meta.uid()), Some(meta.gid())) {
+            fileio::log_chown_failure(to, &e);
+        }
+    } else if !file_type.is_symlink() {
+        fileio::chown_to_desktop_user(to);
+    }
+    Ok(copied)
+}
+
+fn remove_entry(path: &Path) -> std::io::Result<()> {
+    if fs::symlink_metadata(path)?.is_dir() {
+        fs::remove_dir_all(path)
+    } else {
+        fs::remove_file(path)
+    }
+}
+
+/// Trash in the freedesktop.org layout, so the desktop's file manager can
+/// restore deleted entries too.
+fn trash_dir() -> PathBuf {
+    if let Some(dir) = std::env::var_os("CONSOLEY_TRASH_DIR").filter(|dir| !dir.is_empty()) {
+        return PathBuf::from(dir);
+    }
+    match fileio::desktop_home() {
+        Some(home) if home.is_dir() => home.join(".local/share/Trash"),
+        _ => std::env::temp_dir().join("consoley-trash"),
+    }
+}
+
+/// The `.trashinfo` file belonging to `stored`, if it is in the trash.
+fn trash_info_path(stored: &Path) -> Option<PathBuf> {
+    let files = stored.parent()?;
+    let trash = files.parent()?;
+    if files.file_name()? != "files" || trash != trash_dir() {
+        return None;
+    }
+    let name = stored.file_name()?.to_string_lossy();
+    Some(trash.join("info").join(format!("{}.trashinfo", name)))
+}
+
+fn percent_encode(path: &Path) -> String {
+    path.as_os_str()
+        .as_bytes()
+        .iter()
+        .map(|&byte| {
+            if byte.is_ascii_alphanumeric() || b"/-_.~".contains(&byte) {
+                char::from(byte).to_string()
+            } else {
+                format!("%{:02X}", byte)
+            }
+        })
+        .collect()
+}
+
+/// The current time as a trash info `DeletionDate`. The container runs in
+/// UTC, so that is taken as the local time.
+fn deletion_date() -> String {
+    let secs = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or_default();
+    let (days, time) = ((secs / 86400) as i64, secs % 86400);
+    // Civil date from days since the epoch, after Howard Hinnant's algorithm
+    let shifted = days + 719_468;
+    let era = shifted.div_euclid(146_097);
+    let day_of_era = shifted.rem_euclid(146_097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}",
+        year,
+        month,
+        day,
+        time / 3600,
+        time % 3600 / 60,
+        time % 60
+    )
+}
+
+/// Moves an entry (a symlink itself, not its target) to `to`, which must not
+/// exist. Entries are copied if `to` is on another filesystem. Moves into and
+/// out of the trash write or remove the entry's trash info.
+pub fn move_entry(from: &Path, to: &Path) -> Result<(), String> {
+    if fs::symlink_metadata(to).is_ok() {
+        return Err(format!("{} already exists", to.display()));
+    }
+    if let Err(e) = fs::rename(from, to) {
+        if e.raw_os_error() != Some(libc::EXDEV) {
+            return Err(format!("Failed to move {} to {}: {}", from.display(), to.display(), e));
+        }
+        if let Err(e) = copy_entry(from, to, true) {
+            let _ = remove_entry(to);
+            return Err(format!("Failed to copy {} to {}: {}", from.display(), to.display(), e));
+        }
+        remove_entry(from).map_err(|e| format!("Failed to remove {} after copying it: {}", from.display(), e))?;
+    }
+
+    if let Some(info) = trash_info_path(from) {
+        let _ = fs::remove_file(info);
+    }
+    if let Some(info) = trash_info_path(to) {
+        let content = format!("[Trash Info]\nPath={}\nDeletionDate={}\n", percent_encode(from), deletion_date());
+        match fs::write(&info, content) {
+            Ok(()) => fileio::chown_to_desktop_user(&info),
+            Err(e) => log::warn!("Failed to write {}: {}", info.display(), e),
+        }
+    }
+    Ok(())
+}
+
+/// Moves `path` to the trash and returns where it is stored there.
+pub fn trash(path: &Path) -> Result<PathBuf, String> {
+    let trash = trash_dir();
+    for dir in [trash.join("files"), trash.join("info")] {
+        create_dirs(&missing_dirs(&dir))?;
+    }
+    let name = path.file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or_default();
+    let stored = (1..)
+        .map(|n| match n {
+            1 => trash.join("files").join(&name),
+            n => trash.join("files").join(format!("{}.{}", name, n)),
+        })
+        .find(|stored| fs::symlink_metadata(stored).is_err() && trash_info_path(stored).is_some_and(|info| !info.exists()))
+        .unwrap_or_default();
+    move_entry(path, &stored)?;
+    Ok(stored)
+}
+
+/// Checks the entries below the directory `source`, and where they would go
+/// below `destination`, against the sandbox.
+fn check_tree(source: &Path, destination: Option<&Path>, access: sandbox::Access) -> Result<(), String> {
+    for entry in walkdir::WalkDir::new(source).min_depth(1) {
+        let entry = entry.map_err(|e| format!("Failed to read {}: {}", source.display(), e))?;
+        sandbox::check(&entry.path().to_string_lossy(), access)?;
+        if let (Some(destination), Ok(relative)) = (destination, entry.path().strip_prefix(source)) {
+            sandbox::check(&destination.join(relative).to_string_lossy(), sandbox::Access::Write)?;
+        }
+    }
+    Ok(())
+}
+
+fn make_dir(req: &EditRequest, path: &Path) -> Result<OperationReport, (StatusCode, String)> {
+    let parents = req.parents.unwrap_or(false);
+    if path.is_dir() {
+        if !parents {
+            return Err((StatusCode::BAD_REQUEST, format!("{} already exists", path.display())));
+        }
+        return Ok(OperationReport {
+            message: format!("{} already exists", path.display()),
+            stat: stat(path).ok(),
+            trash_path: None,
+        });
+    }
+    if fs::symlink_metadata(path).is_ok() {
+        return Err((StatusCode::BAD_REQUEST, format!("{} already exists and is not a directory", path.display())));
+    }
+
+    let dirs = missing_dirs(path);
+    if let Some(parent) = dirs.first().and_then(|dir| dir.parent()).filter(|parent| !parent.is_dir()) {
+        return Err((StatusCode::BAD_REQUEST, format!("{} is not a directory", parent.display())));
+    }
+    if dirs.len() > 1 && !parents {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!("{} does not exist; set parents to create it too", dirs[0].display()),
+        ));
+    }
+    create_dirs(&dirs).map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e))?;
+    history::record_snapshot(&req.path, &req.command, Snapshot::Directories { dirs: dirs.clone(), present: false });
+
+    let message = match dirs.len() {
+        1 => format!("Created directory {}", path.display()),
+        n => format!("Created directory {} and {} parent directories", path.display(), n - 1),
+    };
+    Ok(OperationReport {
+        message,
+        stat: stat(path).ok(),
+        trash_path: None,
+    })
+}
+
+fn move_or_copy(req: &EditRequest, path: &Path, command: &EditCommand) -> Result<OperationReport, (StatusCode, String)> {
+    let Some(destination) = req.destination.as_deref() else {
+        return Err((StatusCode::BAD_REQUEST, format!("destination is required for {} action", req.command)));
+    };
+    let resolved_destination = sandbox::check(destination, sandbox::Access::Write).map_err(|e| (StatusCode::FORBIDDEN, e))?;
+    let destination = Path::new(destination);
+    let meta = fs::symlink_metadata(path).map_err(|e| not_found(path, e))?;
+    if fs::symlink_metadata(destination).is_ok() {
+        return Err((StatusCode::BAD_REQUEST, format!("{} already exists", destination.display())));
+    }
+    if let Some(parent) = destination.parent().filter(|parent| !parent.is_dir()) {
+        return Err((StatusCode::BAD_REQUEST, format!("Directory {} does not exist", parent.display())));
+    }
+    if meta.is_dir() && sandbox::canonicalize(path).is_ok_and(|source| resolved_destination.starts_with(source)) {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!("Cannot {} {} into itself", req.command, path.display()),
+        ));
+    }
+
+    let moving = matches!(command, EditCommand::Move);
+    let access = if moving { sandbox::Access::Write } else { sandbox::Access::Read };
+    check_tree(path, Some(destination), access).map_err(|e| (StatusCode::FORBIDDEN, e))?;
+
+    let message = if moving {
+        move_entry(path, destination).map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e))?;
+        history::record_snapshot(&destination.to_string_lossy(), &req.command, Snapshot::MovedFrom(path.to_path_buf()));
+        format!("Moved {} to {}", path.display(), destination.display())
+    } else {
+        let copied = copy_entry(path, destination, false).map_err(|e| {
+            let _ = remove_entry(destination);
+            (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to copy {}: {}", path.display(), e))
+        })?;
+        history::record_snapshot(&destination.to_string_lossy(), &req.command, Snapshot::Absent);
+        if meta.is_dir() {
+            format!("Copied {} to {} ({} entries)", path.display(), destination.display(), copied)
+        } else {
+            format!("Copied {} to {}", path.display(), destination.display())
+        }
+    };
+    Ok(OperationReport {
+        message,
+        stat: stat(destination).ok(),
+        trash_path: None,
+    })
+}
+
+fn delete(req: &EditRequest, path: &Path) -> Result<OperationReport, (StatusCode, String)> {
+    fs::symlink_metadata(path).map_err(|e| not_found(path, e))?;
+    check_tree(path, None, sandbox::Access::Write).map_err(|e| (StatusCode::FORBIDDEN, e))?;
+    let stored = trash(path).map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e))?;
+    history::record_snapshot(&req.path, &req.command, Snapshot::MovedTo(stored.clone()));
+    Ok(OperationReport {
+        message: format!("Moved {} to the trash", path.display()),
+        stat: None,
+        trash_path: Some(stored.to_string_lossy().to_string()),
+    })
+}
+
+fn chmod(req: &EditRequest, path: &Path) -> Result<OperationReport, (StatusCode, String)> {
+    let Some(spec) = req.mode.as_deref() else {
+        return Err((StatusCode::BAD_REQUEST, String::from("mode is required for chmod action")));
+    };
+    let current = fs::metadata(path).map_err(|e| not_found(path, e))?.mode() & 0o7777;
+    let Some(new) = parse_mode(spec, current) else {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!("Invalid mode {}: expected octal bits such as 755 or symbolic ones such as u+x", spec),
+        ));
+    };
+    set_mode(path, new).map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e))?;
+    history::record_snapshot(&req.path, &req.command, Snapshot::Mode(current));
+
+    let resolved = fileio::resolve_symlinks(path).unwrap_or_else(|_| path.to_path_buf());
+    Ok(OperationReport {
+        message: format!("Changed mode of {} from {:04o} to {:04o}", path.display(), current, new),
+        stat: stat(&resolved).ok(),
+        trash_path: None,
+    })
+}
+
+fn error_response(status: StatusCode, e: String) -> HttpResponse {
+    HttpResponse::build(status).json(ActionResponse {
+        r#type: String::from("error"),
+        media_type: String::from("text/plain"),
+        data: e,
+    })
+}
+
+/// Handles `stat`, `mkdir`, `move`, `copy`, `delete` and `chmod`. Each
+/// change is recorded in the edit history: `undo_edit` on `path` (or on
+/// `destination` for `move` and `copy`) reverts it, and deleted entries are
+/// kept in the trash.
+pub fn handle_fs_command(req: &EditRequest, command: EditCommand) -> HttpResponse {
+    let path = Path::new(&req.path);
+    let result = match command {
+        EditCommand::Stat => {
+            return match stat(path) {
+                Ok(stat) => HttpResponse::Ok().json(ActionResponse {
+                    r#type: String::from("success"),
+                    media_type: String::from("application/json"),
+                    data: serde_json::to_string(&stat).unwrap_or_default(),
+                }),
+                Err((status, e)) => error_response(status, e),
+            }
+        }
+        EditCommand::Mkdir => make_dir(req, path),
+        EditCommand::Move | EditCommand::Copy => move_or_copy(req, path, &command),
+        EditCommand::Delete => delete(req, path),
+        _ => chmod(req, path),
+    };
+    match result {
+        Ok(report) => {
+            log::info!("{}", report.message);
+            HttpResponse::Ok().json(ActionResponse {
+                r#type: String::from("success"),
+                media_type: String::from("application/json"),
+                data: serde_json::to_string(&report).unwrap_or_default(),
+            })
+        }
+        Err((status, e)) => error_response(status, e),
+    }
+}
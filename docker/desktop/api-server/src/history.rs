@@ -0,0 +1,306 @@
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::{fileio, fsops};
+
+/// Number of undo steps kept per file; older versions are dropped first.
+const MAX_HISTORY_ENTRIES: usize = 50;
+/// Budget for the histories of all files together. When it is exceeded the
+/// histories of the least recently edited files are dropped.
+const MAX_HISTORY_FILES: usize = 1000;
+const MAX_HISTORY_BYTES: usize = 64 * 1024 * 1024;
+
+/// Orders file histories by their last use, for least-recently-used eviction.
+static USE_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+static EDIT_HISTORY: OnceLock<Mutex<HashMap<PathBuf, FileHistory>>> = OnceLock::new();
+
+/// What a path held before (for undo) or after (for redo) an edit, as far as
+/// the edit changed it.
+#[derive(Debug, Clone)]
+pub enum Snapshot {
+    /// File content, or `None` if the file did not exist.
+    Content(Option<Vec<u8>>),
+    /// Permission bits.
+    Mode(u32),
+    /// The entry that belongs at the path is stored at this other path, e.g.
+    /// in the trash.
+    MovedTo(PathBuf),
+    /// Nothing was at the path; the entry there now belongs at this other path.
+    MovedFrom(PathBuf),
+    /// Nothing was at the path; the entry there now goes to the trash.
+    Absent,
+    /// Whether the directories `mkdir` created (outermost first) exist.
+    Directories { dirs: Vec<PathBuf>, present: bool },
+}
+
+impl Snapshot {
+    /// Approximate memory held by the snapshot.
+    fn size(&self) -> usize {
+        match self {
+            Snapshot::Content(content) => content.as_ref().map_or(0, Vec::len),
+            Snapshot::MovedTo(path) | Snapshot::MovedFrom(path) => path.as_os_str().len(),
+            Snapshot::Directories { dirs, .. } => dirs.iter().map(|dir| dir.as_os_str().len()).sum(),
+            Snapshot::Mode(_) | Snapshot::Absent => 0,
+        }
+    }
+
+    fn exists(&self) -> bool {
+        match self {
+            Snapshot::Content(content) => content.is_some(),
+            Snapshot::Mode(_) | Snapshot::MovedTo(_) => true,
+            Snapshot::MovedFrom(_) | Snapshot::Absent => false,
+            Snapshot::Directories { present, .. } => *present,
+        }
+    }
+
+    /// Puts `path` into this state and returns the snapshot that reverts it.
+    fn restore(&self, path: &str) -> Result<Snapshot, String> {
+        match self {
+            Snapshot::Content(content) => {
+                let current = read_current(path)?;
+                restore(path, content)?;
+                Ok(Snapshot::Content(current))
+            }
+            Snapshot::Mode(mode) => {
+                let current = fsops::mode(Path::new(path))?;
+                fsops::set_mode(Path::new(path), *mode)?;
+                Ok(Snapshot::Mode(current))
+            }
+            Snapshot::MovedTo(stored) => {
+                fsops::move_entry(stored, Path::new(path))?;
+                Ok(Snapshot::MovedFrom(stored.clone()))
+            }
+            Snapshot::MovedFrom(origin) => {
+                fsops::move_entry(Path::new(path), origin)?;
+                Ok(Snapshot::MovedTo(origin.clone()))
+            }
+            Snapshot::Absent => Ok(Snapshot::MovedTo(fsops::trash(Path::new(path))?)),
+            Snapshot::Directories { dirs, present } => {
+                if *present {
+                    fsops::create_dirs(dirs)?;
+                } else {
+                    fsops::remove_dirs(dirs)?;
+                }
+                Ok(Snapshot::Directories {
+                    dirs: dirs.clone(),
+                    present: !present,
+                })
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Version {
+    state: Snapshot,
+    command: String,
+    timestamp_ms: u128,
+}
+
+#[derive(Debug, Default)]
+struct FileHistory {
+    undo: Vec<Version>,
+    redo: Vec<Version>,
+    last_used: u64,
+}
+
+impl FileHistory {
+    fn touch(&mut self) {
+        self.last_used = USE_COUNTER.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn size(&self) -> usize {
+        self.undo
+            .iter()
+            .chain(&self.redo)
+            .map(|version| version.state.size() + version.command.len())
+            .sum()
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct HistoryEntry {
+    /// 1 is the most recent edit, i.e. the one the next `undo_edit` reverts.
+    pub depth: usize,
+    pub command: String,
+    pub timestamp_ms: u128,
+    /// Whether the file exists in the version this entry restores.
+    pub file_exists: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct HistoryListing {
+    pub path: String,
+    pub undo: Vec<HistoryEntry>,
+    pub redo: Vec<HistoryEntry>,
+}
+
+fn history() -> &'static Mutex<HashMap<PathBuf, FileHistory>> {
+    EDIT_HISTORY.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+fn now_ms() -> u128 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis())
+        .unwrap_or_default()
+}
+
+/// Resolves the parent directory so that different spellings of the same
+/// path share one history, even for files that do not exist yet.
+fn history_key(path: &str) -> PathBuf {
+    let path = Path::new(path);
+    match (path.parent().map(fs::canonicalize), path.file_name()) {
+        (Some(Ok(parent)), Some(name)) => parent.join(name),
+        _ => path.to_path_buf(),
+    }
+}
+
+pub fn read_current(path: &str) -> Result<Option<Vec<u8>>, String> {
+    match fs::read(path) {
+        Ok(content) => Ok(Some(content)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(format!("Failed to read file: {}", e)),
+    }
+}
+
+/// Writes `content` to `path`, or removes the file if `content` is `None`.
+pub fn restore(path: &str, content: &Option<Vec<u8>>) -> Result<(), String> {
+    match content {
+        Some(content) => fileio::write_atomic(path, content),
+        None => match fs::remove_file(fileio::resolve_symlinks(Path::new(path))?) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(format!("Failed to remove file: {}", e)),
+            _ => Ok(()),
+        },
+    }
+}
+
+/// Records the content a file had before `command` modified it. Any redo
+/// steps are discarded, as they no longer apply on top of the new version.
+pub fn record(path: &str, command: &str, previous: Option<Vec<u8>>) {
+    record_snapshot(path, command, Snapshot::Content(previous));
+}
+
+/// Like `record`, for edits that change more than the content of a file.
+pub fn record_snapshot(path: &str, command: &str, previous: Snapshot) {
+    let mut history = history().lock().unwrap();
+    let file = history.entry(history_key(path)).or_default();
+    file.redo.clear();
+    file.undo.push(Version {
+        state: previous,
+        command: command.to_string(),
+        timestamp_ms: now_ms(),
+    });
+    if file.undo.len() > MAX_HISTORY_ENTRIES {
+        file.undo.remove(0);
+    }
+    file.touch();
+    enforce_budget(&mut history, &history_key(path));
+}
+
+/// Drops the histories of the least recently used files until all histories
+/// fit into the global budget. If the history of `current` alone is too
+/// large, its oldest versions are dropped instead, keeping the newest one.
+fn enforce_budget(history: &mut HashMap<PathBuf, FileHistory>, current: &Path) {
+    let mut bytes: usize = history.values().map(FileHistory::size).sum();
+    while history.len() > MAX_HISTORY_FILES || bytes > MAX_HISTORY_BYTES {
+        let least_recent = history
+            .iter()
+            .filter(|(key, _)| key.as_path() != current)
+            .min_by_key(|(_, file)| file.last_used)
+            .map(|(key, _)| key.clone());
+        match least_recent {
+            Some(key) => {
+                if let Some(file) = history.remove(&key) {
+                    log::debug!("Dropping edit history of {:?} to stay within the history budget", key);
+                    bytes -= file.size();
+                }
+            }
+            None => {
+                let Some(file) = history.get_mut(current).filter(|file| file.undo.len() > 1) else {
+                    break;
+                };
+                let version = file.undo.remove(0);
+                bytes -= version.state.size() + version.command.len();
+            }
+        }
+    }
+}
+
+/// Pops a version from `from`, restores it on disk and pushes the state it
+/// replaced onto `to`. Returns the command of the moved version, or `None`
+/// if `from` is empty.
+fn swap_version(path: &str, from: &mut Vec<Version>, to: &mut Vec<Version>) -> Result<Option<String>, String> {
+    let Some(version) = from.pop() else {
+        return Ok(None);
+    };
+
+    let replaced = match version.state.restore(path) {
+        Ok(replaced) => replaced,
+        Err(e) => {
+            from.push(version);
+            return Err(e);
+        }
+    };
+
+    to.push(Version {
+        state: replaced,
+        command: version.command.clone(),
+        timestamp_ms: version.timestamp_ms,
+    });
+    Ok(Some(version.command))
+}
+
+/// Reverts the most recent edit of a file and returns the command it undid.
+pub fn undo(path: &str) -> Result<String, String> {
+    let mut history = history().lock().unwrap();
+    let file = history.entry(history_key(path)).or_default();
+    file.touch();
+    swap_version(path, &mut file.undo, &mut file.redo)?
+        .ok_or_else(|| format!("No edit history found for {}", path))
+}
+
+/// Re-applies the most recently undone edit of a file and returns its command.
+pub fn redo(path: &str) -> Result<String, String> {
+    let mut history = history().lock().unwrap();
+    let file = history.entry(history_key(path)).or_default();
+    file.touch();
+    swap_version(path, &mut file.redo, &mut file.undo)?
+        .ok_or_else(|| format!("Nothing to redo for {}", path))
+}
+
+pub fn listing(path: &str) -> HistoryListing {
+    let history = history().lock().unwrap();
+    let entries = |versions: &Vec<Version>| {
+        versions
+            .iter()
+            .rev()
+            .enumerate()
+            .map(|(index, version)| HistoryEntry {
+                depth: index + 1,
+                command: version.command.clone(),
+                timestamp_ms: version.timestamp_ms,
+                file_exists: version.state.exists(),
+            })
+            .collect()
+    };
+
+    match history.get(&history_key(path)) {
+        Some(file) => HistoryListing {
+            path: path.to_string(),
+            undo: entries(&file.undo),
+            redo: entries(&file.redo),
+        },
+        None => HistoryListing {
+            path: path.to_string(),
+            undo: Vec::new(),
+            redo: Vec::new(),
+        },
+    }
+}
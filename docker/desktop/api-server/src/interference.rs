@@ -0,0 +1,181 @@
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::io::{BufRead, BufReader};
+use std::process::{Command, Stdio};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Events arriving this long after one of our own injections finished are
+/// still attributed to it, since xinput reports them asynchronously.
+const INJECTION_GRACE: Duration = Duration::from_millis(150);
+const MAX_RECENT_EVENTS: usize = 50;
+const MONITOR_RESTART_DELAY: Duration = Duration::from_secs(5);
+
+static MONITOR: OnceLock<Mutex<InterferenceState>> = OnceLock::new();
+
+#[derive(Debug, Clone, Serialize)]
+pub struct InputEvent {
+    pub kind: String,
+    pub timestamp_ms: u128,
+    /// Seen while the server was injecting input, so it may be our own.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub suppressed: bool,
+}
+
+#[derive(Debug, Default)]
+struct InterferenceState {
+    monitoring: bool,
+    /// Number of our own input injections currently running.
+    active_injections: usize,
+    last_injection_end: Option<Instant>,
+    count: u64,
+    /// Events seen during an injection, which are not counted as human input.
+    suppressed: u64,
+    /// Set when human input was seen and cleared once reported to the agent.
+    pending: bool,
+    recent: VecDeque<InputEvent>,
+}
+
+impl InterferenceState {
+    fn is_injecting(&self) -> bool {
+        self.active_injections > 0
+            || self
+                .last_injection_end
+                .is_some_and(|end| end.elapsed() < INJECTION_GRACE)
+    }
+}
+
+fn state() -> &'static Mutex<InterferenceState> {
+    MONITOR.get_or_init(|| Mutex::new(InterferenceState::default()))
+}
+
+/// Marks an input injection by the server; events seen while the guard is
+/// alive (plus a short grace period) are recorded as suppressed instead of
+/// being counted as human input. Guards should only cover the xdotool calls
+/// themselves, not the pauses between them.
+pub struct InjectionGuard;
+
+impl Drop for InjectionGuard {
+    fn drop(&mut self) {
+        let mut state = state().lock().unwrap();
+        state.active_injections = state.active_injections.saturating_sub(1);
+        state.last_injection_end = Some(Instant::now());
+    }
+}
+
+pub fn begin_injection() -> InjectionGuard {
+    state().lock().unwrap().active_injections += 1;
+    InjectionGuard
+}
+
+/// Returns whether human input was detected since the last call and clears the flag.
+pub fn take_detected() -> bool {
+    std::mem::take(&mut state().lock().unwrap().pending)
+}
+
+pub fn status() -> serde_json::Value {
+    let state = state().lock().unwrap();
+    serde_json::json!({
+        "monitoring": state.monitoring,
+        "count": state.count,
+        "suppressed": state.suppressed,
+        "pending": state.pending,
+        "recent": state.recent,
+    })
+}
+
+/// Maps the raw XI2 event names printed by `xinput test-xi2` to the input
+/// kinds we report. Releases are ignored so that one click counts once.
+fn event_kind(line: &str) -> Option<&'static str> {
+    if !line.starts_with("EVENT type") {
+        return None;
+    }
+    if line.contains("(RawKeyPress)") {
+        Some("key")
+    } else if line.contains("(RawButtonPress)") {
+        Some("button")
+    } else if line.contains("(RawMotion)") {
+        Some("motion")
+    } else {
+        None
+    }
+}
+
+fn record_event(kind: &str) {
+    let mut state = state().lock().unwrap();
+    let suppressed = state.is_injecting();
+    let timestamp_ms = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis())
+        .unwrap_or_default();
+    if suppressed {
+        state.suppressed += 1;
+    } else {
+        log::debug!("Human input detected: {}", kind);
+        state.count += 1;
+        state.pending = true;
+    }
+    if state.recent.len() == MAX_RECENT_EVENTS {
+        state.recent.pop_front();
+    }
+    state.recent.push_back(InputEvent {
+        kind: kind.to_string(),
+        timestamp_ms,
+        suppressed,
+    });
+}
+
+/// Runs `xinput test-xi2` until it exits. Returns an error if the monitor
+/// could not be started or exited without reporting anything.
+fn run_monitor() -> Result<(), String> {
+    let mut child = Command::new("xinput")
+        .env("DISPLAY", ":1")
+        .args(["test-xi2", "--root"])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .map_err(|e| format!("Failed to spawn xinput: {}", e))?;
+    let stdout = child.stdout.take().ok_or("Failed to open xinput stdout")?;
+
+    state().lock().unwrap().monitoring = true;
+    log::debug!("Human input monitor started");
+    let mut reported = false;
+    for line in BufReader::new(stdout).lines().map_while(Result::ok) {
+        if !reported {
+            log::info!("Human input monitor running");
+            reported = true;
+        }
+        if let Some(kind) = event_kind(&line) {
+            record_event(kind);
+        }
+    }
+    state().lock().unwrap().monitoring = false;
+
+    let status = child.wait().map_err(|e| e.to_string())?;
+    if !reported {
+        return Err(format!("xinput exited with {}", status));
+    }
+    log::warn!("Human input monitor exited with {}", status);
+    Ok(())
+}
+
+/// Starts a background thread that watches raw XInput events on the desktop
+/// and counts those not caused by the server's own injections. The monitor
+/// is restarted if xinput exits, e.g. because the X server is not up yet.
+pub fn start_monitor() {
+    std::thread::spawn(|| {
+        // Only warn once until the monitor has run successfully again
+        let mut warned = false;
+        loop {
+            match run_monitor() {
+                Ok(_) => warned = false,
+                Err(e) if !warned => {
+                    log::warn!("Human input monitor unavailable: {}", e);
+                    warned = true;
+                }
+                Err(e) => log::debug!("Human input monitor unavailable: {}", e),
+            }
+            std::thread::sleep(MONITOR_RESTART_DELAY);
+        }
+    });
+}
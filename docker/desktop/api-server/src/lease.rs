@@ -0,0 +1,292 @@
+use actix_web::{web, HttpResponse};
+use serde::{Deserialize, Serialize};
+use std::process::Command;
+use std::sync::{Mutex, OnceLock};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use tokio::time::Duration;
+
+use crate::ActionResponse;
+
+const DEFAULT_TTL_SECS: u64 = 30;
+const MAX_TTL_SECS: u64 = 3600;
+const WAIT_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+static CONTROL_LEASE: OnceLock<Mutex<Option<Lease>>> = OnceLock::new();
+
+/// Who is driving the desktop while a lease is held.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ControlHolder {
+    Human,
+    Agent,
+}
+
+impl ControlHolder {
+    fn as_str(&self) -> &'static str {
+        match self {
+            ControlHolder::Human => "human",
+            ControlHolder::Agent => "agent",
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Lease {
+    /// Incremented for every newly granted lease so that expiry watchers of
+    /// earlier leases can tell they are stale.
+    generation: u64,
+    owner: String,
+    holder: ControlHolder,
+    acquired_at_ms: u128,
+    expires: Instant,
+    ttl: Duration,
+    block_vnc_input: bool,
+}
+
+#[derive(Debug)]
+pub enum ControlCommand {
+    Acquire,
+    Release,
+    Status,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ControlRequest {
+    pub command: String,
+    pub owner: Option<String>,
+    pub holder: Option<ControlHolder>,
+    pub ttl_secs: Option<u64>,
+    /// Put the VNC server into view-only mode while an agent holds the lease.
+    pub block_vnc_input: Option<bool>,
+    /// Take over or release a lease held by another owner.
+    pub force: Option<bool>,
+}
+
+impl ControlRequest {
+    fn parse_command(&self) -> Option<ControlCommand> {
+        match self.command.as_str() {
+            "acquire" => Some(ControlCommand::Acquire),
+            "release" => Some(ControlCommand::Release),
+            "status" => Some(ControlCommand::Status),
+            _ => None,
+        }
+    }
+}
+
+fn lease_state() -> &'static Mutex<Option<Lease>> {
+    CONTROL_LEASE.get_or_init(|| Mutex::new(None))
+}
+
+fn now_ms() -> u128 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis())
+        .unwrap_or_default()
+}
+
+/// Switches x11vnc between view-only and normal mode through its remote
+/// control interface.
+fn set_vnc_view_only(view_only: bool) {
+    let mode = if view_only { "viewonly" } else { "noviewonly" };
+    log::info!("Setting VNC input mode: {}", mode);
+    match Command::new("x11vnc")
+        .env("DISPLAY", ":1")
+        .args(["-display", ":1", "-remote", mode])
+        .output()
+    {
+        Ok(output) if output.status.success() => {}
+        Ok(output) => log::warn!(
+            "x11vnc remote control failed: {}",
+            String::from_utf8_lossy(&output.stderr)
+        ),
+        Err(e) => log::warn!("Failed to execute x11vnc remote control: {}", e),
+    }
+}
+
+/// Drops the lease if its TTL has passed, restoring VNC input if it was blocked.
+fn expire_stale(lease: &mut Option<Lease>) {
+    if let Some(current) = lease.as_ref() {
+        if current.expires <= Instant::now() {
+            log::info!("Control lease of {} expired", current.owner);
+            if current.block_vnc_input {
+                set_vnc_view_only(false);
+            }
+            *lease = None;
+        }
+    }
+}
+
+fn lease_status(lease: &Option<Lease>) -> serde_json::Value {
+    match lease {
+        Some(current) => serde_json::json!({
+            "held": true,
+            "owner": current.owner,
+            "holder": current.holder,
+            "acquired_at_ms": current.acquired_at_ms,
+            "expires_in_ms": current.expires.saturating_duration_since(Instant::now()).as_millis() as u64,
+            "ttl_secs": current.ttl.as_secs(),
+            "block_vnc_input": current.block_vnc_input,
+        }),
+        None => serde_json::json!({ "held": false }),
+    }
+}
+
+/// Returns the owner of the lease if a human currently holds control.
+pub fn human_holder() -> Option<String> {
+    let mut lease = lease_state().lock().unwrap();
+    expire_stale(&mut lease);
+    lease
+        .as_ref()
+        .filter(|current| current.holder == ControlHolder::Human)
+        .map(|current| current.owner.clone())
+}
+
+/// Waits up to `wait` for a human-held lease to be released or to expire.
+/// Returns the human owner if control was not handed back in time.
+pub async fn wait_for_agent_control(wait: Duration) -> Result<(), String> {
+    let deadline = Instant::now() + wait;
+    loop {
+        match human_holder() {
+            None => return Ok(()),
+            Some(owner) if Instant::now() >= deadline => return Err(owner),
+            Some(_) => tokio::time::sleep(WAIT_POLL_INTERVAL).await,
+        }
+    }
+}
+
+/// Restores VNC input once a lease that blocked it runs out, unless it has
+/// been renewed or replaced in the meantime.
+fn spawn_expiry_watcher(generation: u64) {
+    actix_web::rt::spawn(async move {
+        loop {
+            let remaining = {
+                let mut lease = lease_state().lock().unwrap();
+                match lease.as_ref() {
+                    Some(current) if current.generation == generation => {
+                        let remaining = current.expires.saturating_duration_since(Instant::now());
+                        if remaining.is_zero() {
+                            expire_stale(&mut lease);
+                            return;
+                        }
+                        remaining
+                    }
+                    _ => return,
+                }
+            };
+            tokio::time::sleep(remaining).await;
+        }
+    });
+}
+
+pub async fn handle_control_action(req: web::Json<ControlRequest>) -> HttpResponse {
+    let mut lease = lease_state().lock().unwrap();
+    expire_stale(&mut lease);
+
+    match req.parse_command() {
+        Some(ControlCommand::Acquire) => {
+            let (Some(owner), Some(holder)) = (&req.owner, req.holder) else {
+                return HttpResponse::BadRequest().json(ActionResponse {
+                    r#type: String::from("error"),
+                    media_type: String::from("text/plain"),
+                    data: String::from("owner and holder are required for acquire command"),
+                });
+            };
+            let ttl_secs = req.ttl_secs.unwrap_or(DEFAULT_TTL_SECS);
+            if ttl_secs == 0 || ttl_secs > MAX_TTL_SECS {
+                return HttpResponse::BadRequest().json(ActionResponse {
+                    r#type: String::from("error"),
+                    media_type: String::from("text/plain"),
+                    data: format!("ttl_secs should be within range [1, {}]", MAX_TTL_SECS),
+                });
+            }
+
+            let renewal = lease.as_ref().is_some_and(|current| current.owner == *owner);
+            if let Some(current) = lease.as_ref() {
+                if !renewal && !req.force.unwrap_or(false) {
+                    return HttpResponse::Conflict().json(ActionResponse {
+                        r#type: String::from("error"),
+                        media_type: String::from("text/plain"),
+                        data: format!(
+                            "Control is held by {} ({}) for another {} seconds",
+                            current.owner,
+                            current.holder.as_str(),
+                            current.expires.saturating_duration_since(Instant::now()).as_secs()
+                        ),
+                    });
+                }
+            }
+
+            let was_blocked = lease.as_ref().is_some_and(|current| current.block_vnc_input);
+            let block_vnc_input = holder == ControlHolder::Agent && req.block_vnc_input.unwrap_or(false);
+            if block_vnc_input != was_blocked {
+                set_vnc_view_only(block_vnc_input);
+            }
+
+            let ttl = Duration::from_secs(ttl_secs);
+            let generation = lease.as_ref().map(|current| current.generation).unwrap_or(0) + 1;
+            let acquired_at_ms = match lease.as_ref() {
+                Some(current) if renewal && current.holder == holder => current.acquired_at_ms,
+                _ => now_ms(),
+            };
+            log::info!("Control lease granted to {} ({}) for {}s", owner, holder.as_str(), ttl_secs);
+            *lease = Some(Lease {
+                generation,
+                owner: owner.clone(),
+                holder,
+                acquired_at_ms,
+                expires: Instant::now() + ttl,
+                ttl,
+                block_vnc_input,
+            });
+            if block_vnc_input {
+                spawn_expiry_watcher(generation);
+            }
+
+            HttpResponse::Ok().json(ActionResponse {
+                r#type: String::from("success"),
+                media_type: String::from("application/json"),
+                data: lease_status(&lease).to_string(),
+            })
+        }
+        Some(ControlCommand::Release) => {
+            let Some(current) = lease.as_ref() else {
+                return HttpResponse::Ok().json(ActionResponse {
+                    r#type: String::from("success"),
+                    media_type: String::from("text/plain"),
+                    data: String::from("Control lease is not held"),
+                });
+            };
+            if req.owner.as_deref() != Some(current.owner.as_str()) && !req.force.unwrap_or(false) {
+                return HttpResponse::Conflict().json(ActionResponse {
+                    r#type: String::from("error"),
+                    media_type: String::from("text/plain"),
+                    data: format!("Control lease is held by {}", current.owner),
+                });
+            }
+
+            log::info!("Control lease of {} released", current.owner);
+            if current.block_vnc_input {
+                set_vnc_view_only(false);
+            }
+            *lease = None;
+            HttpResponse::Ok().json(ActionResponse {
+                r#type: String::from("success"),
+                media_type: String::from("text/plain"),
+                data: String::from("Control lease released"),
+            })
+        }
+        Some(ControlCommand::Status) => HttpResponse::Ok().json(ActionResponse {
+            r#type: String::from("success"),
+            media_type: String::from("application/json"),
+            data: lease_status(&lease).to_string(),
+        }),
+        None => {
+            log::warn!("Invalid control command received: {}", req.command);
+            HttpResponse::BadRequest().json(ActionResponse {
+                r#type: String::from("error"),
+                media_type: String::from("text/plain"),
+                data: String::from("Unsupported control command"),
+            })
+        }
+    }
+}
@@ -1,15 +1,43 @@
-use actix_web::{web, App, HttpServer, HttpResponse, Responder, get, post};
+use actix_web::{web, App, HttpRequest, HttpServer, HttpResponse, Responder, get, post, put};
 use serde::{Deserialize, Serialize};
 use std::process::Command;
 use std::fs;
+use std::path::Path;
 use base64::{Engine as _, engine::general_purpose};
+use actix_cors::Cors;
 use actix_web::middleware::Logger;
+use actix_web::http::StatusCode;
+use actix_web::http::header::{HeaderMap, CONTENT_LENGTH, CONTENT_TYPE};
 use tokio::time::{timeout, Duration};
 use std::process::Stdio;
 use tokio::io::{AsyncWriteExt, AsyncReadExt};
 use std::sync::Mutex;
 use std::sync::OnceLock;
 
+mod archive;
+mod batch;
+mod conflict;
+mod encoding;
+mod fileio;
+mod files;
+mod fsops;
+mod history;
+mod interference;
+mod lease;
+mod media;
+mod motion;
+mod notebook;
+mod patch;
+mod recorder;
+mod replace;
+mod sandbox;
+mod search;
+mod structured;
+mod view;
+mod watch;
+
+const DEFAULT_SETTLE_MS: u64 = 500;
+
 static BASH_SESSION: OnceLock<Mutex<Option<BashSession>>> = OnceLock::new();
 
 #[derive(Serialize)]
@@ -34,11 +62,19 @@ pub enum ComputerAction {
     CursorPosition,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ActionRequest {
     pub action: String,
     pub text: Option<String>,
     pub coordinate: Option<Vec<i32>>,
+    /// How long to wait for a human to hand back control before rejecting an input action.
+    pub control_wait_ms: Option<u64>,
+    /// Return a screenshot taken after the action together with its result.
+    pub return_screenshot: Option<bool>,
+    /// Delay before the post-action screenshot, so the UI can react to the input.
+    pub settle_ms: Option<u64>,
+    pub motion: Option<motion::MotionProfile>,
+    pub typing: Option<motion::TypingProfile>,
 }
 
 impl ActionRequest {
@@ -61,6 +97,7 @@ impl ActionRequest {
     }
 }
 
+#[allow(clippy::needless_borrows_for_generic_args)]
 fn get_cursor_position() -> Result<(i32, i32), String> {
     log::info!("Getting cursor position...");
     let output = Command::new("xdotool")
@@ -103,10 +140,28 @@ fn get_cursor_position() -> Result<(i32, i32), String> {
     Ok((x, y))
 }
 
-pub async fn handle_computer_action(req: web::Json<ActionRequest>) -> impl Responder {
+pub async fn handle_computer_action(req: web::Json<ActionRequest>) -> HttpResponse {
     log::info!("Processing computer action: {}", req.action);
+
+    let parsed_action = req.parse_action();
+    let wants_screenshot = !matches!(parsed_action, Some(ComputerAction::Screenshot) | None);
+
+    // Input actions must not collide with a human who holds the control lease
+    if let Some(action) = &parsed_action {
+        if !matches!(action, ComputerAction::Screenshot | ComputerAction::CursorPosition) {
+            let wait = Duration::from_millis(req.control_wait_ms.unwrap_or(0));
+            if let Err(owner) = lease::wait_for_agent_control(wait).await {
+                log::warn!("Rejecting {} action, control is held by {}", req.action, owner);
+                return HttpResponse::build(StatusCode::LOCKED).json(ActionResponse {
+                    r#type: String::from("error"),
+                    media_type: String::from("text/plain"),
+                    data: format!("Desktop control is held by human operator: {}", owner),
+                });
+            }
+        }
+    }
     
-    let result = match req.parse_action() {
+    let result = match parsed_action {
         Some(action) => {
             match action {
                 ComputerAction::Screenshot => {
@@ -153,7 +208,11 @@ pub async fn handle_computer_action(req: web::Json<ActionRequest>) -> impl Respo
                 ComputerAction::Type => {
                     // Process input text action
                     if let Some(text) = &req.text {
-                        match execute_xdotool(&["type", text]) {
+                        let typed = match &req.typing {
+                            Some(typing) => motion::type_text(text, typing).await,
+                            None => execute_xdotool(&["type", text]).map(|_| ()),
+                        };
+                        match typed {
                             Ok(_) => HttpResponse::Ok().json(ActionResponse {
                                 r#type: String::from("success"),
                                 media_type: String::from("text/plain"),
@@ -177,7 +236,12 @@ pub async fn handle_computer_action(req: web::Json<ActionRequest>) -> impl Respo
                     // 处理鼠标移动操作
                     if let Some(coords) = &req.coordinate {
                         if coords.len() == 2 {
-                            match execute_xdotool(&["mousemove", &coords[0].to_string(), &coords[1].to_string()]) {
+                            let moved = match &req.motion {
+                                Some(motion) => motion::move_pointer((coords[0], coords[1]), motion).await,
+                                None => execute_xdotool(&["mousemove", &coords[0].to_string(), &coords[1].to_string()])
+                                    .map(|_| ()),
+                            };
+                            match moved {
                                 Ok(_) => HttpResponse::Ok().json(ActionResponse {
                                     r#type: String::from("success"),
                                     media_type: String::from("text/plain"),
@@ -219,7 +283,13 @@ pub async fn handle_computer_action(req: web::Json<ActionRequest>) -> impl Respo
                     }
                 },
                 ComputerAction::LeftClickDrag => {
-                    match execute_xdotool(&["mousedown", "1"]) {
+                    // With a motion profile and target coordinate, drag there and release;
+                    // otherwise only press the button
+                    let dragged = match (req.coordinate.as_deref(), &req.motion) {
+                        (Some([x, y]), Some(motion)) => motion::drag((*x, *y), motion).await,
+                        _ => execute_xdotool(&["mousedown", "1"]).map(|_| ()),
+                    };
+                    match dragged {
                         Ok(_) => HttpResponse::Ok().json(ActionResponse {
                             r#type: String::from("success"),
                             media_type: String::from("text/plain"),
@@ -287,74 +357,91 @@ pub async fn handle_computer_action(req: web::Json<ActionRequest>) -> impl Respo
     };
     
     log::debug!("Computer action completed");
+
+    if req.return_screenshot.unwrap_or(false) && wants_screenshot && result.status().is_success() {
+        return attach_screenshot(result, req.settle_ms.unwrap_or(DEFAULT_SETTLE_MS)).await;
+    }
     result
 }
 
+/// Waits for the desktop to settle after an action and adds a fresh
+/// screenshot to the action's response.
+async fn attach_screenshot(response: HttpResponse, settle_ms: u64) -> HttpResponse {
+    tokio::time::sleep(Duration::from_millis(settle_ms)).await;
+    let extra = match capture_screenshot() {
+        Ok(image_data) => serde_json::json!({
+            "screenshot": ActionResponse {
+                r#type: String::from("base64"),
+                media_type: String::from("image/png"),
+                data: general_purpose::STANDARD.encode(&image_data),
+            }
+        }),
+        Err(e) => serde_json::json!({ "screenshot_error": e }),
+    };
+    extend_response(response, extra).await
+}
+
 fn take_screenshot() -> HttpResponse {
+    match capture_screenshot() {
+        Ok(image_data) => {
+            // Convert to base64
+            let base64_string = general_purpose::STANDARD.encode(&image_data);
+
+            log::info!("Screenshot taken successfully");
+            HttpResponse::Ok()
+                .content_type("application/json")
+                .json(ActionResponse {
+                    r#type: String::from("base64"),
+                    media_type: String::from("image/png"),
+                    data: base64_string,
+                })
+        }
+        Err(e) => HttpResponse::InternalServerError().json(ActionResponse {
+            r#type: String::from("error"),
+            media_type: String::from("text/plain"),
+            data: e,
+        }),
+    }
+}
+
+/// Captures the desktop with scrot and returns the raw PNG bytes.
+fn capture_screenshot() -> Result<Vec<u8>, String> {
     log::info!("Taking screenshot...");
     let screenshot_path = "/tmp/screenshot.png";
     
-    let result = Command::new("scrot")
+    let output = Command::new("scrot")
         .arg(screenshot_path)
         .env("DISPLAY", ":1")
-        .output();
-
-    match result {
-        Ok(output) => {
-            if !output.status.success() {
-                let error = String::from_utf8_lossy(&output.stderr);
-                log::error!("Screenshot command failed: {}", error);
-                return HttpResponse::InternalServerError().json(ActionResponse {
-                    r#type: String::from("error"),
-                    media_type: String::from("text/plain"),
-                    data: format!("Failed to take screenshot: {}", error),
-                });
-            }
-
-            // Read the screenshot file
-            match fs::read(screenshot_path) {
-                Ok(image_data) => {
-                    // Convert to base64
-                    let base64_string = general_purpose::STANDARD.encode(&image_data);
-                    
-                    // Clean up temporary file
-                    if let Err(e) = fs::remove_file(screenshot_path) {
-                        log::warn!("Failed to remove temporary screenshot file: {}", e);
-                    }
-                    
-                    log::info!("Screenshot taken successfully");
-                    HttpResponse::Ok()
-                        .content_type("application/json")
-                        .json(ActionResponse {
-                            r#type: String::from("base64"),
-                            media_type: String::from("image/png"),
-                            data: base64_string,
-                        })
-                }
-                Err(e) => {
-                    log::error!("Failed to read screenshot file: {}", e);
-                    HttpResponse::InternalServerError().json(ActionResponse {
-                        r#type: String::from("error"),
-                        media_type: String::from("text/plain"),
-                        data: format!("Failed to read screenshot file: {}", e),
-                    })
-                }
-            }
-        }
-        Err(e) => {
+        .output()
+        .map_err(|e| {
             log::error!("Failed to execute screenshot command: {}", e);
-            HttpResponse::InternalServerError().json(ActionResponse {
-                r#type: String::from("error"),
-                media_type: String::from("text/plain"),
-                data: format!("Failed to execute screenshot command: {}", e),
-            })
-        }
+            format!("Failed to execute screenshot command: {}", e)
+        })?;
+
+    if !output.status.success() {
+        let error = String::from_utf8_lossy(&output.stderr);
+        log::error!("Screenshot command failed: {}", error);
+        return Err(format!("Failed to take screenshot: {}", error));
     }
+
+    // Read the screenshot file
+    let image_data = fs::read(screenshot_path).map_err(|e| {
+        log::error!("Failed to read screenshot file: {}", e);
+        format!("Failed to read screenshot file: {}", e)
+    })?;
+
+    // Clean up temporary file
+    if let Err(e) = fs::remove_file(screenshot_path) {
+        log::warn!("Failed to remove temporary screenshot file: {}", e);
+    }
+
+    Ok(image_data)
 }
 
 fn execute_xdotool(args: &[&str]) -> Result<String, String> {
     log::debug!("Executing xdotool with args: {:?}", args);
     let start = std::time::Instant::now();
+    let _injection = interference::begin_injection();
     
     let result = Command::new("xdotool")
         .env("DISPLAY", ":1")
@@ -388,9 +475,27 @@ pub enum EditCommand {
     StrReplace,
     Insert,
     UndoEdit,
+    Redo,
+    History,
+    ApplyPatch,
+    GetKey,
+    SetKey,
+    DeleteKey,
+    ListCells,
+    ReplaceCell,
+    InsertCell,
+    DeleteCell,
+    ClearOutputs,
+    ReplaceInFiles,
+    Stat,
+    Mkdir,
+    Move,
+    Copy,
+    Delete,
+    Chmod,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct EditRequest {
     pub command: String,
     pub path: String,
@@ -399,6 +504,61 @@ pub struct EditRequest {
     pub old_str: Option<String>,
     pub new_str: Option<String>,
     pub insert_line: Option<i32>,
+    /// Replace every occurrence of `old_str` instead of requiring a unique match.
+    pub replace_all: Option<bool>,
+    /// Replace only the n-th (1-based) occurrence of `old_str`.
+    pub occurrence: Option<usize>,
+    /// How many levels deep to list when viewing a directory.
+    pub depth: Option<usize>,
+    /// Include hidden files and directories when viewing a directory or
+    /// replacing in files.
+    pub show_hidden: Option<bool>,
+    /// What mutating commands return: "snippet" (default), "diff" or "message".
+    pub response_format: Option<String>,
+    /// Unified diff for `apply_patch`; relative paths in it are resolved against `path`.
+    pub patch: Option<String>,
+    /// Content hash (the `ETag` of a view or edit) the file must still have;
+    /// the edit is rejected with a conflict otherwise.
+    pub expected_hash: Option<String>,
+    /// Show only the last n lines of the file, e.g. of a log.
+    pub tail: Option<usize>,
+    /// Most bytes of numbered lines a `view` returns; the server limit
+    /// (`CONSOLEY_VIEW_MAX_BYTES`) applies if this is larger or unset.
+    pub max_output: Option<usize>,
+    /// Also render the viewed PDF pages as images.
+    pub page_images: Option<bool>,
+    /// Key for `get_key`, `set_key` and `delete_key`: a JSON Pointer
+    /// (`/a/b/0`) or dotted path (`a.b.0`).
+    pub key: Option<String>,
+    /// JSON value `set_key` sets; unlike other fields, `null` is a value.
+    #[serde(default, deserialize_with = "present_value")]
+    pub value: Option<serde_json::Value>,
+    /// 0-based notebook cell index for the cell commands.
+    pub cell: Option<usize>,
+    /// "code", "markdown" or "raw" for `insert_cell` and `replace_cell`.
+    pub cell_type: Option<String>,
+    /// Regular expression `replace_in_files` replaces the matches of.
+    pub pattern: Option<String>,
+    /// Replacement for `pattern`; `$1` or `${name}` insert capture groups.
+    pub replacement: Option<String>,
+    /// Globs selecting the files `replace_in_files` changes, relative to
+    /// `path`. A leading `!` excludes.
+    pub globs: Option<Vec<String>>,
+    /// Return the diff of `replace_in_files` without writing anything.
+    pub dry_run: Option<bool>,
+    /// Let `replace_in_files` also change files excluded by .gitignore and
+    /// .ignore files.
+    pub no_ignore: Option<bool>,
+    /// Path `move` and `copy` create; it must not exist yet.
+    pub destination: Option<String>,
+    /// Mode for `chmod`: octal bits ("755") or symbolic ones ("u+x,go-w").
+    pub mode: Option<String>,
+    /// Let `mkdir` create missing parent directories and accept an existing one.
+    pub parents: Option<bool>,
+}
+
+fn present_value<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<Option<serde_json::Value>, D::Error> {
+    serde_json::Value::deserialize(deserializer).map(Some)
 }
 
 impl EditRequest {
@@ -409,24 +569,280 @@ impl EditRequest {
             "str_replace" => Some(EditCommand::StrReplace),
             "insert" => Some(EditCommand::Insert),
             "undo_edit" => Some(EditCommand::UndoEdit),
+            "redo" => Some(EditCommand::Redo),
+            "history" => Some(EditCommand::History),
+            "apply_patch" => Some(EditCommand::ApplyPatch),
+            "get_key" => Some(EditCommand::GetKey),
+            "set_key" => Some(EditCommand::SetKey),
+            "delete_key" => Some(EditCommand::DeleteKey),
+            "list_cells" => Some(EditCommand::ListCells),
+            "replace_cell" => Some(EditCommand::ReplaceCell),
+            "insert_cell" => Some(EditCommand::InsertCell),
+            "delete_cell" => Some(EditCommand::DeleteCell),
+            "clear_outputs" => Some(EditCommand::ClearOutputs),
+            "replace_in_files" => Some(EditCommand::ReplaceInFiles),
+            "stat" => Some(EditCommand::Stat),
+            "mkdir" => Some(EditCommand::Mkdir),
+            "move" => Some(EditCommand::Move),
+            "copy" => Some(EditCommand::Copy),
+            "delete" => Some(EditCommand::Delete),
+            "chmod" => Some(EditCommand::Chmod),
+            _ => None,
+        }
+    }
+
+    fn parse_response_format(&self) -> Option<view::ResponseFormat> {
+        match self.response_format.as_deref() {
+            None | Some("snippet") => Some(view::ResponseFormat::Snippet),
+            Some("diff") => Some(view::ResponseFormat::Diff),
+            Some("message") => Some(view::ResponseFormat::Message),
             _ => None,
         }
     }
 }
 
-pub async fn handle_edit_action(req: web::Json<EditRequest>) -> impl Responder {
+/// Appends the snippet or diff requested by `response_format` to the success
+/// message of a mutating edit.
+fn edit_result_message(req: &EditRequest, message: &str, old: &str, new: &str) -> String {
+    let format = req.parse_response_format().unwrap_or(view::ResponseFormat::Snippet);
+    let feedback = view::edit_feedback(&req.path, old, new, format);
+    if feedback.is_empty() {
+        message.to_string()
+    } else {
+        format!("{}. {}", message, feedback)
+    }
+}
+
+/// Returns the 1-based line numbers on which each occurrence of `needle` starts.
+fn match_lines(content: &str, needle: &str) -> Vec<usize> {
+    let mut lines = Vec::new();
+    let mut line = 1;
+    let mut last = 0;
+    for (index, _) in content.match_indices(needle) {
+        line += content[last..index].matches('\n').count();
+        last = index;
+        lines.push(line);
+    }
+    lines
+}
+
+/// Inserts `text` as new lines after line `line` (0 inserts at the top).
+/// Returns `None` if the file has fewer lines. Whether the file ends with a
+/// newline is kept.
+fn insert_text(content: &str, text: &str, line: usize) -> Option<String> {
+    let lines: Vec<&str> = content.split_inclusive('\n').collect();
+    if line > lines.len() {
+        return None;
+    }
+    let offset: usize = lines[..line].iter().map(|line| line.len()).sum();
+    let (before, after) = content.split_at(offset);
+    if after.is_empty() && !before.is_empty() && !before.ends_with('\n') {
+        return Some(format!("{}\n{}", before, text));
+    }
+    Some(format!("{}{}\n{}", before, text, after))
+}
+
+/// Mentions the detected format of files that are not plain UTF-8 with LF
+/// line endings, as they are written back in that format.
+fn with_format_note(message: &str, format: &encoding::TextFormat) -> String {
+    match format.describe() {
+        Some(description) => format!("{} (file kept as {})", message, description),
+        None => message.to_string(),
+    }
+}
+
+/// Replaces `old_str` in `content` following the str_replace rules: by default
+/// `old_str` must occur exactly once, `occurrence` picks one of several
+/// matches and `replace_all` replaces all of them. Returns the new content
+/// and the number of replacements.
+fn replace_matches(content: &str, old_str: &str, new_str: &str, req: &EditRequest) -> Result<(String, usize), String> {
+    if old_str.is_empty() {
+        return Err(String::from("old_str must not be empty"));
+    }
+
+    let lines = match_lines(content, old_str);
+    if lines.is_empty() {
+        return Err(format!(
+            "No replacement was performed, old_str did not appear verbatim in {}",
+            req.path
+        ));
+    }
+
+    if req.replace_all.unwrap_or(false) {
+        return Ok((content.replace(old_str, new_str), lines.len()));
+    }
+
+    let index = match req.occurrence {
+        Some(occurrence) if occurrence == 0 || occurrence > lines.len() => {
+            return Err(format!(
+                "Invalid occurrence {}: old_str occurs {} times, in lines {:?}",
+                occurrence, lines.len(), lines
+            ));
+        }
+        Some(occurrence) => occurrence - 1,
+        None if lines.len() > 1 => {
+            return Err(format!(
+                "No replacement was performed. Multiple occurrences of old_str in lines {:?}. \
+                 Please ensure it is unique, or set replace_all or occurrence",
+                lines
+            ));
+        }
+        None => 0,
+    };
+
+    let (start, _) = content.match_indices(old_str).nth(index).unwrap_or_default();
+    let mut new_content = String::with_capacity(content.len() + new_str.len());
+    new_content.push_str(&content[..start]);
+    new_content.push_str(new_str);
+    new_content.push_str(&content[start + old_str.len()..]);
+    Ok((new_content, 1))
+}
+
+pub async fn handle_edit_action(req: web::Json<EditRequest>) -> HttpResponse {
     log::info!("Received edit command: {} for path: {}", req.command, req.path);
     let start = std::time::Instant::now();
     
+    if req.parse_response_format().is_none() {
+        return HttpResponse::BadRequest().json(ActionResponse {
+            r#type: String::from("error"),
+            media_type: String::from("text/plain"),
+            data: String::from("response_format should be one of: snippet, diff, message"),
+        });
+    }
+
+    let access = match req.parse_command() {
+        Some(
+            EditCommand::View
+                | EditCommand::History
+                | EditCommand::ApplyPatch
+                | EditCommand::GetKey
+                | EditCommand::ListCells
+                | EditCommand::ReplaceInFiles
+                | EditCommand::Stat
+                | EditCommand::Copy,
+        )
+        | None => sandbox::Access::Read,
+        Some(_) => sandbox::Access::Write,
+    };
+    if let Err(e) = sandbox::check(&req.path, access) {
+        log::warn!("{}", e);
+        return HttpResponse::Forbidden().json(ActionResponse {
+            r#type: String::from("error"),
+            media_type: String::from("text/plain"),
+            data: e,
+        });
+    }
+
+    let mutating = matches!(
+        req.parse_command(),
+        Some(
+            EditCommand::Create
+                | EditCommand::StrReplace
+                | EditCommand::Insert
+                | EditCommand::UndoEdit
+                | EditCommand::Redo
+                | EditCommand::ApplyPatch
+                | EditCommand::SetKey
+                | EditCommand::DeleteKey
+                | EditCommand::ReplaceCell
+                | EditCommand::InsertCell
+                | EditCommand::DeleteCell
+                | EditCommand::ClearOutputs
+                | EditCommand::ReplaceInFiles
+                | EditCommand::Mkdir
+                | EditCommand::Move
+                | EditCommand::Copy
+                | EditCommand::Delete
+                | EditCommand::Chmod
+        )
+    );
+    let _edit_guard = mutating.then(conflict::lock_edits);
+    if let (Some(expected), true) = (&req.expected_hash, mutating) {
+        if matches!(req.parse_command(), Some(EditCommand::ApplyPatch | EditCommand::ReplaceInFiles)) {
+            return HttpResponse::BadRequest().json(ActionResponse {
+                r#type: String::from("error"),
+                media_type: String::from("text/plain"),
+                data: format!("expected_hash is not supported for {}, which may change several files", req.command),
+            });
+        }
+        let checked = history::read_current(&req.path).map(|current| conflict::check(&req.path, expected, current.as_deref()));
+        match checked {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => {
+                log::warn!("Rejected edit of {}: hash mismatch", req.path);
+                return HttpResponse::Conflict().json(ActionResponse {
+                    r#type: String::from("error"),
+                    media_type: String::from("text/plain"),
+                    data: e,
+                });
+            }
+            Err(e) => return HttpResponse::InternalServerError().json(ActionResponse {
+                r#type: String::from("error"),
+                media_type: String::from("text/plain"),
+                data: e,
+            }),
+        }
+    }
+
     let response = match req.parse_command() {
         Some(command) => {
             match command {
                 EditCommand::View => {
                     log::debug!("Processing view action for path: {}", req.path);
-                    match fs::read_to_string(&req.path) {
-                        Ok(content) => {
-                            let mut file_content = content;
-                            
+                    if Path::new(&req.path).is_dir() {
+                        let depth = req.depth.unwrap_or(view::DEFAULT_TREE_DEPTH);
+                        return match view::directory_tree(Path::new(&req.path), depth, req.show_hidden.unwrap_or(false)) {
+                            Ok(tree) => HttpResponse::Ok().json(ActionResponse {
+                                r#type: String::from("success"),
+                                media_type: String::from("text/plain"),
+                                data: tree,
+                            }),
+                            Err(e) => HttpResponse::InternalServerError().json(ActionResponse {
+                                r#type: String::from("error"),
+                                media_type: String::from("text/plain"),
+                                data: e,
+                            }),
+                        };
+                    }
+                    if let Some(response) = media::view_media(&req) {
+                        return response;
+                    }
+                    match fs::read(&req.path) {
+                        Ok(bytes) => {
+                            let (content, format) = match encoding::decode(&bytes) {
+                                Ok(decoded) => decoded,
+                                Err(e) => return HttpResponse::BadRequest().json(ActionResponse {
+                                    r#type: String::from("error"),
+                                    media_type: String::from("text/plain"),
+                                    data: e,
+                                }),
+                            };
+                            let lines: Vec<&str> = content.split('\n').collect();
+                            let mut first_line = 1;
+                            let mut selected_lines = &lines[..];
+
+                            if req.tail.is_some() && req.view_range.is_some() {
+                                return HttpResponse::BadRequest().json(ActionResponse {
+                                    r#type: String::from("error"),
+                                    media_type: String::from("text/plain"),
+                                    data: String::from("view_range and tail cannot be used together"),
+                                });
+                            }
+                            if let Some(tail) = req.tail {
+                                if tail == 0 {
+                                    return HttpResponse::BadRequest().json(ActionResponse {
+                                        r#type: String::from("error"),
+                                        media_type: String::from("text/plain"),
+                                        data: String::from("tail should be at least 1"),
+                                    });
+                                }
+                                // The empty "line" after a final newline does not count
+                                let end = if lines.len() > 1 && lines.last() == Some(&"") { lines.len() - 1 } else { lines.len() };
+                                let start = end.saturating_sub(tail);
+                                first_line = start + 1;
+                                selected_lines = &lines[start..end];
+                            }
+
                             // Handle view_range if present
                             if let Some(range) = &req.view_range {
                                 // First check array length
@@ -438,7 +854,6 @@ pub async fn handle_edit_action(req: web::Json<EditRequest>) -> impl Responder {
                                     });
                                 }
 
-                                let lines: Vec<&str> = file_content.split('\n').collect();
                                 let n_lines_file = lines.len();
                                 let init_line = range[0];
                                 let final_line = range[1];
@@ -474,20 +889,25 @@ pub async fn handle_edit_action(req: web::Json<EditRequest>) -> impl Responder {
                                 }
 
                                 // Extract the requested range
-                                let selected_lines = if final_line == -1 {
+                                first_line = init_line as usize;
+                                selected_lines = if final_line == -1 {
                                     &lines[(init_line - 1) as usize..]
                                 } else {
                                     &lines[(init_line - 1) as usize..final_line as usize]
                                 };
-                                
-                                file_content = selected_lines.join("\n");
                             }
 
-                            HttpResponse::Ok().json(ActionResponse {
+                            let max_bytes = req.max_output.map_or(view::max_view_bytes(), |max| max.min(view::max_view_bytes()));
+                            let mut data = view::paged_lines(selected_lines, first_line, max_bytes, req.tail.is_some());
+                            if let Some(description) = format.describe() {
+                                data = format!("[File is {}; it is shown decoded and edits keep this format]\n{}", description, data);
+                            }
+                            let response = HttpResponse::Ok().json(ActionResponse {
                                 r#type: String::from("success"),
                                 media_type: String::from("text/plain"),
-                                data: file_content,
-                            })
+                                data,
+                            });
+                            conflict::with_content_hash(response, &bytes)
                         },
                         Err(e) => {
                             if e.kind() == std::io::ErrorKind::NotFound {
@@ -510,21 +930,38 @@ pub async fn handle_edit_action(req: web::Json<EditRequest>) -> impl Responder {
                     log::info!("Creating file at: {}", req.path);
                     if let Some(text) = &req.file_text {
                         log::debug!("File content length: {}", text.len());
-                        match fs::write(&req.path, text) {
+                        let previous = match history::read_current(&req.path) {
+                            Ok(previous) => previous,
+                            Err(e) => return HttpResponse::InternalServerError().json(ActionResponse {
+                                r#type: String::from("error"),
+                                media_type: String::from("text/plain"),
+                                data: e,
+                            }),
+                        };
+                        match fileio::write_atomic(&req.path, text.as_bytes()) {
                             Ok(_) => {
                                 log::info!("File created successfully");
-                                HttpResponse::Ok().json(ActionResponse {
+                                let old_text = previous.as_deref().map(String::from_utf8_lossy).unwrap_or_default();
+                                let data = edit_result_message(
+                                    &req,
+                                    &format!("File created successfully at: {}", req.path),
+                                    &old_text,
+                                    text,
+                                );
+                                history::record(&req.path, "create", previous);
+                                let response = HttpResponse::Ok().json(ActionResponse {
                                     r#type: String::from("success"),
                                     media_type: String::from("text/plain"),
-                                    data: format!("File created successfully at: {}", req.path),
-                                })
+                                    data,
+                                });
+                                conflict::with_content_hash(response, text.as_bytes())
                             },
                             Err(e) => {
                                 log::error!("Failed to create file: {}", e);
                                 HttpResponse::InternalServerError().json(ActionResponse {
                                     r#type: String::from("error"),
                                     media_type: String::from("text/plain"),
-                                    data: format!("Failed to create file: {}", e),
+                                    data: e,
                                 })
                             }
                         }
@@ -540,30 +977,52 @@ pub async fn handle_edit_action(req: web::Json<EditRequest>) -> impl Responder {
                 EditCommand::StrReplace => {
                     log::info!("Performing string replacement in file: {}", req.path);
                     if let (Some(old_str), Some(new_str)) = (&req.old_str, &req.new_str) {
-                        match fs::read_to_string(&req.path) {
-                            Ok(content) => {
-                                let new_content = content.replace(old_str, new_str);
-                                // Create backup file
-                                let backup_path = format!("{}.bak", req.path);
-                                if let Err(e) = fs::write(&backup_path, &content) {
-                                    log::error!("Failed to create backup file: {}", e);
-                                    return HttpResponse::InternalServerError().json(ActionResponse {
+                        match fs::read(&req.path) {
+                            Ok(bytes) => {
+                                let (content, format) = match encoding::decode(&bytes) {
+                                    Ok(decoded) => decoded,
+                                    Err(e) => return HttpResponse::BadRequest().json(ActionResponse {
                                         r#type: String::from("error"),
                                         media_type: String::from("text/plain"),
-                                        data: format!("Failed to create backup: {}", e),
-                                    });
-                                }
-                                
-                                match fs::write(&req.path, new_content) {
-                                    Ok(_) => HttpResponse::Ok().json(ActionResponse {
-                                        r#type: String::from("success"),
+                                        data: e,
+                                    }),
+                                };
+                                let (new_content, replaced) = match replace_matches(&content, old_str, new_str, &req) {
+                                    Ok(result) => result,
+                                    Err(e) => return HttpResponse::BadRequest().json(ActionResponse {
+                                        r#type: String::from("error"),
                                         media_type: String::from("text/plain"),
-                                        data: String::from("String replacement completed successfully"),
+                                        data: e,
                                     }),
+                                };
+                                let encoded = match encoding::encode(&new_content, &format) {
+                                    Ok(encoded) => encoded,
+                                    Err(e) => return HttpResponse::BadRequest().json(ActionResponse {
+                                        r#type: String::from("error"),
+                                        media_type: String::from("text/plain"),
+                                        data: e,
+                                    }),
+                                };
+                                match fileio::write_atomic(&req.path, &encoded) {
+                                    Ok(_) => {
+                                        let message = if replaced == 1 {
+                                            String::from("String replacement completed successfully")
+                                        } else {
+                                            format!("String replacement completed successfully ({} occurrences replaced)", replaced)
+                                        };
+                                        let data = edit_result_message(&req, &with_format_note(&message, &format), &content, &new_content);
+                                        history::record(&req.path, "str_replace", Some(bytes));
+                                        let response = HttpResponse::Ok().json(ActionResponse {
+                                            r#type: String::from("success"),
+                                            media_type: String::from("text/plain"),
+                                            data,
+                                        });
+                                        conflict::with_content_hash(response, &encoded)
+                                    },
                                     Err(e) => HttpResponse::InternalServerError().json(ActionResponse {
                                         r#type: String::from("error"),
                                         media_type: String::from("text/plain"),
-                                        data: format!("Failed to write file: {}", e),
+                                        data: e,
                                     })
                                 }
                             },
@@ -584,35 +1043,41 @@ pub async fn handle_edit_action(req: web::Json<EditRequest>) -> impl Responder {
                 EditCommand::Insert => {
                     log::debug!("Processing insert action");
                     if let (Some(text), Some(line_num)) = (&req.file_text, &req.insert_line) {
-                        match fs::read_to_string(&req.path) {
-                            Ok(content) => {
-                                let mut lines: Vec<&str> = content.lines().collect();
-                                let line_idx = *line_num as usize;
-                                
-                                // Create backup file
-                                let backup_path = format!("{}.bak", req.path);
-                                if let Err(e) = fs::write(&backup_path, &content) {
-                                    log::error!("Failed to create backup file: {}", e);
-                                    return HttpResponse::InternalServerError().json(ActionResponse {
+                        match fs::read(&req.path) {
+                            Ok(bytes) => {
+                                let (content, format) = match encoding::decode(&bytes) {
+                                    Ok(decoded) => decoded,
+                                    Err(e) => return HttpResponse::BadRequest().json(ActionResponse {
                                         r#type: String::from("error"),
                                         media_type: String::from("text/plain"),
-                                        data: format!("Failed to create backup: {}", e),
-                                    });
-                                }
-
-                                if line_idx <= lines.len() {
-                                    lines.insert(line_idx, text);
-                                    let new_content = lines.join("\n");
-                                    match fs::write(&req.path, new_content) {
-                                        Ok(_) => HttpResponse::Ok().json(ActionResponse {
-                                            r#type: String::from("success"),
+                                        data: e,
+                                    }),
+                                };
+                                if let Some(new_content) = insert_text(&content, text, *line_num as usize) {
+                                    let encoded = match encoding::encode(&new_content, &format) {
+                                        Ok(encoded) => encoded,
+                                        Err(e) => return HttpResponse::BadRequest().json(ActionResponse {
+                                            r#type: String::from("error"),
                                             media_type: String::from("text/plain"),
-                                            data: String::from("Text inserted successfully"),
+                                            data: e,
                                         }),
+                                    };
+                                    match fileio::write_atomic(&req.path, &encoded) {
+                                        Ok(_) => {
+                                            let message = with_format_note("Text inserted successfully", &format);
+                                            let data = edit_result_message(&req, &message, &content, &new_content);
+                                            history::record(&req.path, "insert", Some(bytes));
+                                            let response = HttpResponse::Ok().json(ActionResponse {
+                                                r#type: String::from("success"),
+                                                media_type: String::from("text/plain"),
+                                                data,
+                                            });
+                                            conflict::with_content_hash(response, &encoded)
+                                        },
                                         Err(e) => HttpResponse::InternalServerError().json(ActionResponse {
                                             r#type: String::from("error"),
                                             media_type: String::from("text/plain"),
-                                            data: format!("Failed to write file: {}", e),
+                                            data: e,
                                         })
                                     }
                                 } else {
@@ -639,27 +1104,109 @@ pub async fn handle_edit_action(req: web::Json<EditRequest>) -> impl Responder {
                 },
                 EditCommand::UndoEdit => {
                     log::debug!("Processing undo edit action");
-                    let backup_path = format!("{}.bak", req.path);
-                    if fs::metadata(&backup_path).is_ok() {
-                        match fs::rename(&backup_path, &req.path) {
-                            Ok(_) => HttpResponse::Ok().json(ActionResponse {
+                    match history::undo(&req.path) {
+                        Ok(command) => conflict::with_current_hash(
+                            HttpResponse::Ok().json(ActionResponse {
                                 r#type: String::from("success"),
                                 media_type: String::from("text/plain"),
-                                data: String::from("Edit undone successfully"),
+                                data: format!("Edit undone successfully ({})", command),
                             }),
-                            Err(e) => HttpResponse::InternalServerError().json(ActionResponse {
-                                r#type: String::from("error"),
+                            &req.path,
+                        ),
+                        Err(e) => HttpResponse::BadRequest().json(ActionResponse {
+                            r#type: String::from("error"),
+                            media_type: String::from("text/plain"),
+                            data: e,
+                        })
+                    }
+                },
+                EditCommand::Redo => {
+                    log::debug!("Processing redo action");
+                    match history::redo(&req.path) {
+                        Ok(command) => conflict::with_current_hash(
+                            HttpResponse::Ok().json(ActionResponse {
+                                r#type: String::from("success"),
                                 media_type: String::from("text/plain"),
-                                data: format!("Failed to restore backup: {}", e),
-                            })
-                        }
-                    } else {
-                        HttpResponse::BadRequest().json(ActionResponse {
+                                data: format!("Edit redone successfully ({})", command),
+                            }),
+                            &req.path,
+                        ),
+                        Err(e) => HttpResponse::BadRequest().json(ActionResponse {
                             r#type: String::from("error"),
                             media_type: String::from("text/plain"),
-                            data: String::from("No backup file found to undo"),
+                            data: e,
                         })
                     }
+                },
+                EditCommand::History => {
+                    log::debug!("Processing history action");
+                    HttpResponse::Ok().json(ActionResponse {
+                        r#type: String::from("success"),
+                        media_type: String::from("application/json"),
+                        data: serde_json::to_string(&history::listing(&req.path)).unwrap_or_default(),
+                    })
+                },
+                EditCommand::ApplyPatch => {
+                    log::info!("Applying patch relative to: {}", req.path);
+                    let Some(patch_text) = &req.patch else {
+                        return HttpResponse::BadRequest().json(ActionResponse {
+                            r#type: String::from("error"),
+                            media_type: String::from("text/plain"),
+                            data: String::from("patch is required for apply_patch action"),
+                        });
+                    };
+                    match patch::apply_patch(&req.path, patch_text) {
+                        Ok(report) => HttpResponse::Ok().json(ActionResponse {
+                            r#type: String::from("success"),
+                            media_type: String::from("application/json"),
+                            data: serde_json::to_string(&report).unwrap_or_default(),
+                        }),
+                        Err(patch::PatchError::Rejected(report)) => HttpResponse::BadRequest().json(ActionResponse {
+                            r#type: String::from("error"),
+                            media_type: String::from("application/json"),
+                            data: serde_json::to_string(&report).unwrap_or_default(),
+                        }),
+                        Err(patch::PatchError::Invalid(e)) => HttpResponse::BadRequest().json(ActionResponse {
+                            r#type: String::from("error"),
+                            media_type: String::from("text/plain"),
+                            data: e,
+                        }),
+                        Err(patch::PatchError::Forbidden(e)) => HttpResponse::Forbidden().json(ActionResponse {
+                            r#type: String::from("error"),
+                            media_type: String::from("text/plain"),
+                            data: e,
+                        }),
+                        Err(patch::PatchError::Io(e)) => HttpResponse::InternalServerError().json(ActionResponse {
+                            r#type: String::from("error"),
+                            media_type: String::from("text/plain"),
+                            data: e,
+                        }),
+                    }
+                },
+                EditCommand::GetKey | EditCommand::SetKey | EditCommand::DeleteKey => {
+                    log::info!("Processing {} of {:?} in: {}", req.command, req.key, req.path);
+                    structured::handle_key_command(&req, command)
+                },
+                EditCommand::ListCells
+                | EditCommand::ReplaceCell
+                | EditCommand::InsertCell
+                | EditCommand::DeleteCell
+                | EditCommand::ClearOutputs => {
+                    log::info!("Processing {} of cell {:?} in notebook: {}", req.command, req.cell, req.path);
+                    notebook::handle_notebook_command(&req, command)
+                },
+                EditCommand::ReplaceInFiles => {
+                    log::info!("Replacing {:?} in files below: {}", req.pattern, req.path);
+                    replace::handle_replace_in_files(&req)
+                },
+                EditCommand::Stat
+                | EditCommand::Mkdir
+                | EditCommand::Move
+                | EditCommand::Copy
+                | EditCommand::Delete
+                | EditCommand::Chmod => {
+                    log::info!("Processing {} of: {}", req.command, req.path);
+                    fsops::handle_fs_command(&req, command)
                 }
             }
         },
@@ -690,16 +1237,152 @@ async fn health_check() -> impl Responder {
     response
 }
 
+/// Splits a handler response into its status code and JSON body so that it can
+/// be inspected or extended before being sent.
+pub(crate) async fn response_json(response: HttpResponse) -> (StatusCode, serde_json::Value) {
+    let status = response.status();
+    let body = actix_web::body::to_bytes(response.into_body())
+        .await
+        .unwrap_or_default();
+    let value = serde_json::from_slice(&body)
+        .unwrap_or_else(|_| serde_json::Value::String(String::from_utf8_lossy(&body).into_owned()));
+    (status, value)
+}
+
+/// Builds a JSON response with `body`, keeping the headers (such as the
+/// `ETag`) of the handler response it replaces.
+pub(crate) fn rebuild_response(status: StatusCode, headers: &HeaderMap, body: serde_json::Value) -> HttpResponse {
+    let mut response = HttpResponse::build(status).json(body);
+    for (name, value) in headers {
+        if name != CONTENT_TYPE && name != CONTENT_LENGTH {
+            response.headers_mut().append(name.clone(), value.clone());
+        }
+    }
+    response
+}
+
+/// Adds the fields of `extra` to the JSON object returned by a handler.
+pub(crate) async fn extend_response(response: HttpResponse, extra: serde_json::Value) -> HttpResponse {
+    let headers = response.headers().clone();
+    let (status, mut body) = response_json(response).await;
+    if let (Some(body), serde_json::Value::Object(extra)) = (body.as_object_mut(), extra) {
+        body.extend(extra);
+    }
+    rebuild_response(status, &headers, body)
+}
+
 #[post("/computer")]
 async fn computer_endpoint(req: web::Json<ActionRequest>) -> impl Responder {
     log::info!("Computer action received: {:?}", req);
-    handle_computer_action(req).await
+    let request = req.0.clone();
+    let mut response = handle_computer_action(req).await;
+
+    // Let the agent know it should re-screenshot before acting again
+    if interference::take_detected() {
+        response = extend_response(response, serde_json::json!({ "human_input_detected": true })).await;
+    }
+
+    if recorder::is_recording() {
+        response = recorder::record(recorder::RecordedEndpoint::Computer, &request, response).await;
+    }
+    response
+}
+
+#[get("/interference")]
+async fn interference_endpoint() -> impl Responder {
+    HttpResponse::Ok().json(ActionResponse {
+        r#type: String::from("success"),
+        media_type: String::from("application/json"),
+        data: interference::status().to_string(),
+    })
 }
 
 #[post("/edit")]
 async fn edit_endpoint(req: web::Json<EditRequest>) -> impl Responder {
     log::info!("Edit command received: {:?}", req);
-    handle_edit_action(req).await
+    if !recorder::is_recording() {
+        return handle_edit_action(req).await;
+    }
+
+    let request = req.0.clone();
+    let response = handle_edit_action(req).await;
+    recorder::record(recorder::RecordedEndpoint::Edit, &request, response).await
+}
+
+#[post("/edit/batch")]
+async fn edit_batch_endpoint(req: web::Json<batch::BatchRequest>) -> impl Responder {
+    log::info!("Edit batch received: {} edits", req.edits.len());
+    if !recorder::is_recording() {
+        return batch::handle_batch_action(req).await;
+    }
+
+    let request = req.0.clone();
+    let response = batch::handle_batch_action(req).await;
+    recorder::record(recorder::RecordedEndpoint::EditBatch, &request, response).await
+}
+
+#[post("/search")]
+async fn search_endpoint(req: web::Json<search::SearchRequest>) -> impl Responder {
+    log::info!("Search received: {:?}", req);
+    search::handle_search(req).await
+}
+
+#[get("/files")]
+async fn download_file_endpoint(req: HttpRequest, query: web::Query<files::FileQuery>) -> impl Responder {
+    log::info!("File download requested: {}", query.path);
+    files::handle_download(req, query).await
+}
+
+#[put("/files")]
+async fn upload_file_endpoint(
+    req: HttpRequest,
+    query: web::Query<files::FileQuery>,
+    payload: web::Payload,
+) -> impl Responder {
+    log::info!("File upload received: {}", query.path);
+    files::handle_put(req, query, payload).await
+}
+
+#[post("/files")]
+async fn upload_files_endpoint(query: web::Query<files::FileQuery>, multipart: actix_multipart::Multipart) -> impl Responder {
+    log::info!("Multipart upload received into: {}", query.path);
+    files::handle_multipart(query, multipart).await
+}
+
+#[get("/files/archive")]
+async fn download_archive_endpoint(req: HttpRequest, query: web::Query<archive::ArchiveQuery>) -> impl Responder {
+    log::info!("Archive download requested: {}", query.path);
+    archive::handle_export(req, query).await
+}
+
+#[get("/watch")]
+async fn watch_endpoint(query: web::Query<watch::WatchQuery>) -> impl Responder {
+    log::info!("Watch requested: {}", query.path);
+    watch::handle_watch(query).await
+}
+
+#[post("/files/archive")]
+async fn upload_archive_endpoint(query: web::Query<archive::ArchiveQuery>, payload: web::Payload) -> impl Responder {
+    log::info!("Archive upload received into: {}", query.path);
+    archive::handle_import(query, payload).await
+}
+
+#[post("/control")]
+async fn control_endpoint(req: web::Json<lease::ControlRequest>) -> impl Responder {
+    log::info!("Control command received: {:?}", req);
+    lease::handle_control_action(req).await
+}
+
+#[post("/recording")]
+async fn recording_endpoint(req: web::Json<recorder::RecordingRequest>) -> impl Responder {
+    log::info!("Recording command received: {:?}", req);
+    recorder::handle_recording_action(req).await
+}
+
+#[post("/replay")]
+async fn replay_endpoint(req: web::Json<recorder::ReplayRequest>) -> impl Responder {
+    log::info!("Replay request received: {:?}", req);
+    recorder::handle_replay(req).await
 }
 
 #[derive(Debug, Deserialize)]
@@ -725,6 +1408,7 @@ impl BashSession {
     
     async fn new() -> Result<Self, String> {
         let mut child = tokio::process::Command::new("/bin/bash")
+            .current_dir(sandbox::default_dir())
             .stdin(Stdio::piped())
             .stdout(Stdio::piped())
             .stderr(Stdio::piped())
@@ -824,6 +1508,27 @@ impl BashSession {
         }
     }
 
+    /// Moves the shell back to the default directory if it is in a directory
+    /// the sandbox policy does not allow writing to. Returns the error
+    /// describing the rejected directory.
+    async fn enforce_cwd(&mut self) -> Result<(), String> {
+        let Some(pid) = self.process.id() else {
+            return Ok(());
+        };
+        let Ok(cwd) = fs::read_link(format!("/proc/{}/cwd", pid)) else {
+            return Ok(());
+        };
+        let Err(e) = sandbox::check(&cwd.to_string_lossy(), sandbox::Access::Write) else {
+            return Ok(());
+        };
+
+        let default_dir = sandbox::default_dir();
+        log::warn!("{}; moving bash session back to {}", e, default_dir.display());
+        let quoted = default_dir.to_string_lossy().replace('\'', "'\\''");
+        self.execute(&format!("cd '{}'", quoted)).await?;
+        Err(format!("{}. The working directory was reset to {}", e, default_dir.display()))
+    }
+
     async fn stop(&mut self) -> Result<(), String> {
         // Send exit command
         self.stdin
@@ -851,6 +1556,7 @@ impl BashSession {
 
 
 #[post("/bash")]
+#[allow(clippy::await_holding_lock)]
 async fn bash_endpoint(req: web::Json<BashRequest>) -> impl Responder {
     log::info!("Received bash request");
     
@@ -904,6 +1610,16 @@ async fn bash_endpoint(req: web::Json<BashRequest>) -> impl Responder {
 
         // Execute command
         if let Some(session) = session_guard.as_mut() {
+            // The directory may have become forbidden since the last command,
+            // e.g. by being replaced with a symlink
+            if let Err(e) = session.enforce_cwd().await {
+                return HttpResponse::Forbidden().json(ActionResponse {
+                    r#type: String::from("error"),
+                    media_type: String::from("text/plain"),
+                    data: format!("{}. The command was not run", e),
+                });
+            }
+
             match session.execute(command).await {
                 Ok((stdout, stderr)) => {
                     let response = if stderr.is_empty() {
@@ -911,7 +1627,16 @@ async fn bash_endpoint(req: web::Json<BashRequest>) -> impl Responder {
                     } else {
                         format!("stdout:\n{}\nstderr:\n{}", stdout, stderr)
                     };
-                    
+
+                    // The command has run, so its output is returned along with the error
+                    if let Err(e) = session.enforce_cwd().await {
+                        return HttpResponse::Forbidden().json(ActionResponse {
+                            r#type: String::from("error"),
+                            media_type: String::from("text/plain"),
+                            data: if response.is_empty() { e } else { format!("{}\n{}", response, e) },
+                        });
+                    }
+
                     HttpResponse::Ok().json(ActionResponse {
                         r#type: String::from("success"),
                         media_type: String::from("text/plain"),
@@ -940,18 +1665,48 @@ async fn bash_endpoint(req: web::Json<BashRequest>) -> impl Responder {
     }
 }
 
+/// Lets the web UI call the API (e.g. to acquire the control lease) from the
+/// origins listed in `CONSOLEY_UI_ORIGINS`.
+fn ui_cors() -> Cors {
+    let origins = std::env::var("CONSOLEY_UI_ORIGINS")
+        .unwrap_or_else(|_| String::from("http://localhost:1420,http://127.0.0.1:1420"));
+    origins
+        .split(',')
+        .map(str::trim)
+        .filter(|origin| !origin.is_empty())
+        .fold(Cors::default(), |cors, origin| cors.allowed_origin(origin))
+        .allowed_methods(vec!["GET", "POST", "PUT"])
+        .allowed_headers(vec![actix_web::http::header::CONTENT_TYPE, actix_web::http::header::RANGE])
+        .expose_headers(vec![actix_web::http::header::ETAG])
+        .max_age(3600)
+}
+
 pub fn run(listener: std::net::TcpListener) -> std::io::Result<actix_web::dev::Server> {
     log::info!("=== Server starting ===");
+    interference::start_monitor();
     
     let server = HttpServer::new(move || {
         log::info!("=== Creating new worker ===");
         App::new()
             .wrap(Logger::default())
+            .wrap(ui_cors())
             .wrap(actix_web::middleware::NormalizePath::trim())
             .service(health_check)
             .service(computer_endpoint)
             .service(edit_endpoint)
+            .service(edit_batch_endpoint)
+            .service(search_endpoint)
+            .service(download_file_endpoint)
+            .service(upload_file_endpoint)
+            .service(upload_files_endpoint)
+            .service(download_archive_endpoint)
+            .service(upload_archive_endpoint)
+            .service(watch_endpoint)
             .service(bash_endpoint)
+            .service(interference_endpoint)
+            .service(control_endpoint)
+            .service(recording_endpoint)
+            .service(replay_endpoint)
             .app_data(web::JsonConfig::default().limit(4096 * 1024))
     })
     .keep_alive(actix_web::http::KeepAlive::Timeout(std::time::Duration::from_secs(60)))
@@ -1,4 +1,3 @@
-use env_logger;
 use std::net::TcpListener;
 
 #[actix_web::main]
@@ -0,0 +1,214 @@
+use actix_web::http::StatusCode;
+use actix_web::HttpResponse;
+use base64::{engine::general_purpose, Engine as _};
+use serde::Serialize;
+use std::fs::{self, File};
+use std::io::Read;
+use std::process::Command;
+
+use crate::{view, ActionResponse, EditRequest};
+
+/// Images are scaled down so their longer edge is at most this many pixels.
+const MAX_IMAGE_EDGE: u32 = 1568;
+/// Larger images are re-encoded; base64 adds a third on top of this.
+const MAX_IMAGE_BYTES: usize = 3 * 1024 * 1024;
+/// Page images rendered per request, as each one is large.
+const MAX_PAGE_IMAGES: usize = 10;
+
+type MediaError = (StatusCode, String);
+
+fn internal(e: String) -> MediaError {
+    (StatusCode::INTERNAL_SERVER_ERROR, e)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum MediaKind {
+    Image(&'static str),
+    Pdf,
+}
+
+/// Recognizes images and PDFs by their first bytes.
+fn detect(header: &[u8]) -> Option<MediaKind> {
+    if header.starts_with(b"\x89PNG\r\n\x1a\n") {
+        Some(MediaKind::Image("image/png"))
+    } else if header.starts_with(&[0xFF, 0xD8, 0xFF]) {
+        Some(MediaKind::Image("image/jpeg"))
+    } else if header.starts_with(b"GIF87a") || header.starts_with(b"GIF89a") {
+        Some(MediaKind::Image("image/gif"))
+    } else if header.len() >= 12 && &header[..4] == b"RIFF" && &header[8..12] == b"WEBP" {
+        Some(MediaKind::Image("image/webp"))
+    } else if header.starts_with(b"BM") && header.get(6..10) == Some(&[0, 0, 0, 0]) {
+        // The reserved header bytes keep text starting with "BM" from matching
+        Some(MediaKind::Image("image/bmp"))
+    } else if header.starts_with(b"%PDF-") {
+        Some(MediaKind::Pdf)
+    } else {
+        None
+    }
+}
+
+fn run(program: &str, args: &[&str]) -> Result<Vec<u8>, String> {
+    let output = Command::new(program)
+        .args(args)
+        .output()
+        .map_err(|e| format!("Failed to execute {}: {}", program, e))?;
+    if !output.status.success() {
+        return Err(format!("{} failed: {}", program, String::from_utf8_lossy(&output.stderr).trim()));
+    }
+    Ok(output.stdout)
+}
+
+fn image_size(path: &str) -> Option<(u32, u32)> {
+    let output = run("identify", &["-format", "%w %h", &format!("{}[0]", path)]).ok()?;
+    let output = String::from_utf8_lossy(&output);
+    let (width, height) = output.trim().split_once(' ')?;
+    Some((width.parse().ok()?, height.parse().ok()?))
+}
+
+/// Returns the image as sent to the client: as is if it is small enough and
+/// in a common format, otherwise scaled down (and, if still too large,
+/// converted to JPEG) with ImageMagick.
+fn prepare_image(path: &str, media_type: &'static str) -> Result<(Vec<u8>, &'static str), String> {
+    let content = fs::read(path).map_err(|e| format!("Failed to read file: {}", e))?;
+    let too_large = image_size(path).is_some_and(|(width, height)| width.max(height) > MAX_IMAGE_EDGE);
+    if !too_large && content.len() <= MAX_IMAGE_BYTES && media_type != "image/bmp" {
+        return Ok((content, media_type));
+    }
+
+    let source = format!("{}[0]", path);
+    let resize = format!("{}x{}>", MAX_IMAGE_EDGE, MAX_IMAGE_EDGE);
+    let png = run("convert", &[&source, "-resize", &resize, "-strip", "png:-"])?;
+    if png.len() <= MAX_IMAGE_BYTES {
+        return Ok((png, "image/png"));
+    }
+    let jpeg = run("convert", &[&source, "-resize", &resize, "-strip", "-quality", "85", "jpeg:-"])?;
+    Ok((jpeg, "image/jpeg"))
+}
+
+#[derive(Debug, Serialize)]
+struct PageImage {
+    media_type: String,
+    data: String,
+}
+
+#[derive(Debug, Serialize)]
+struct PdfPage {
+    page: usize,
+    text: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    image: Option<PageImage>,
+}
+
+#[derive(Debug, Serialize)]
+struct PdfView {
+    page_count: usize,
+    pages: Vec<PdfPage>,
+    /// Pages to request next if the output limit cut the requested range short.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    next_view_range: Option<[usize; 2]>,
+}
+
+fn page_count(path: &str) -> Result<usize, String> {
+    let info = run("pdfinfo", &[path])?;
+    String::from_utf8_lossy(&info)
+        .lines()
+        .find_map(|line| line.strip_prefix("Pages:"))
+        .and_then(|count| count.trim().parse().ok())
+        .ok_or_else(|| String::from("pdfinfo did not report a page count"))
+}
+
+fn render_page(path: &str, page: usize) -> Result<PageImage, String> {
+    let prefix = std::env::temp_dir().join(format!("consoley-page-{:016x}", rand::random::<u64>()));
+    let prefix = prefix.to_string_lossy().to_string();
+    let page = page.to_string();
+    let edge = MAX_IMAGE_EDGE.to_string();
+    let rendered = run("pdftoppm", &["-png", "-singlefile", "-f", &page, "-l", &page, "-scale-to", &edge, path, &prefix])
+        .and_then(|_| fs::read(format!("{}.png", prefix)).map_err(|e| format!("Failed to read rendered page: {}", e)));
+    let _ = fs::remove_file(format!("{}.png", prefix));
+    Ok(PageImage {
+        media_type: String::from("image/png"),
+        data: general_purpose::STANDARD.encode(rendered?),
+    })
+}
+
+/// Extracts the text of the pages in `view_range` (all by default), and
+/// renders them as images if `page_images` is set.
+fn view_pdf(req: &EditRequest) -> Result<PdfView, MediaError> {
+    let page_count = page_count(&req.path).map_err(internal)?;
+    let (first, last) = match req.view_range.as_deref() {
+        None => (1, page_count),
+        Some(&[first, last]) => {
+            let last = if last == -1 { page_count as i32 } else { last };
+            if first < 1 || last < first || last as usize > page_count {
+                return Err((
+                    StatusCode::BAD_REQUEST,
+                    format!("Invalid view_range: pages should be within [1, {}]", page_count),
+                ));
+            }
+            (first as usize, last as usize)
+        }
+        Some(_) => return Err((StatusCode::BAD_REQUEST, String::from("view_range should contain exactly 2 integers"))),
+    };
+    let page_images = req.page_images.unwrap_or(false);
+    if page_images && last - first >= MAX_PAGE_IMAGES {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!("At most {} pages can be rendered as images at once, use view_range to select pages", MAX_PAGE_IMAGES),
+        ));
+    }
+
+    let (first_arg, last_arg) = (first.to_string(), last.to_string());
+    let text = run("pdftotext", &["-layout", "-f", &first_arg, "-l", &last_arg, &req.path, "-"]).map_err(internal)?;
+    let text = String::from_utf8_lossy(&text);
+    // pdftotext ends every page with a form feed
+    let texts: Vec<&str> = text.split('\x0c').collect();
+
+    let max_bytes = req.max_output.map_or(view::max_view_bytes(), |max| max.min(view::max_view_bytes()));
+    let mut view = PdfView {
+        page_count,
+        pages: Vec::new(),
+        next_view_range: None,
+    };
+    let mut size = 0;
+    for page in first..=last {
+        let text = texts.get(page - first).copied().unwrap_or_default().trim_end().to_string();
+        size += text.len();
+        if size > max_bytes && !view.pages.is_empty() {
+            let shown = view.pages.len();
+            view.next_view_range = Some([page, (page + shown - 1).min(last)]);
+            break;
+        }
+        let image = if page_images { Some(render_page(&req.path, page).map_err(internal)?) } else { None };
+        view.pages.push(PdfPage { page, text, image });
+    }
+    Ok(view)
+}
+
+/// Views images and PDFs, which `view` cannot show as text. Returns `None`
+/// for other files.
+pub fn view_media(req: &EditRequest) -> Option<HttpResponse> {
+    let mut header = [0u8; 16];
+    let read = File::open(&req.path).and_then(|mut file| file.read(&mut header)).ok()?;
+    let result = match detect(&header[..read])? {
+        MediaKind::Image(media_type) => prepare_image(&req.path, media_type)
+            .map(|(image, media_type)| ActionResponse {
+                r#type: String::from("base64"),
+                media_type: media_type.to_string(),
+                data: general_purpose::STANDARD.encode(image),
+            })
+            .map_err(internal),
+        MediaKind::Pdf => view_pdf(req).map(|view| ActionResponse {
+            r#type: String::from("success"),
+            media_type: String::from("application/json"),
+            data: serde_json::to_string(&view).unwrap_or_default(),
+        }),
+    };
+    Some(match result {
+        Ok(response) => HttpResponse::Ok().json(response),
+        Err((status, e)) => HttpResponse::build(status).json(ActionResponse {
+            r#type: String::from("error"),
+            media_type: String::from("text/plain"),
+            data: e,
+        }),
+    })
+}
@@ -0,0 +1,146 @@
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use tokio::time::Duration;
+
+use crate::{execute_xdotool, get_cursor_position};
+
+const DEFAULT_MOTION_DURATION_MS: u64 = 400;
+const MAX_MOTION_DURATION_MS: u64 = 10_000;
+const MAX_TYPING_DELAY_MS: u64 = 1_000;
+/// Upper bound for the random offset of intermediate points, in pixels.
+const MAX_JITTER: f64 = 20.0;
+/// Interval between intermediate pointer positions, roughly one frame at 60 Hz.
+const MOTION_STEP_MS: u64 = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MotionCurve {
+    Linear,
+    Bezier,
+}
+
+/// How the pointer travels to its target for `mouse_move` and `left_click_drag`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MotionProfile {
+    pub profile: MotionCurve,
+    pub duration_ms: Option<u64>,
+    /// Maximum random offset in pixels applied to intermediate points.
+    pub jitter: Option<f64>,
+}
+
+/// Per-character timing for the `type` action.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TypingProfile {
+    pub delay_ms: u64,
+    /// Maximum random deviation from `delay_ms` for each character.
+    pub variance_ms: Option<u64>,
+}
+
+fn cubic_bezier(p0: (f64, f64), p1: (f64, f64), p2: (f64, f64), p3: (f64, f64), t: f64) -> (f64, f64) {
+    let u = 1.0 - t;
+    let x = u * u * u * p0.0 + 3.0 * u * u * t * p1.0 + 3.0 * u * t * t * p2.0 + t * t * t * p3.0;
+    let y = u * u * u * p0.1 + 3.0 * u * u * t * p1.1 + 3.0 * u * t * t * p2.1 + t * t * t * p3.1;
+    (x, y)
+}
+
+/// Computes the intermediate pointer positions from `start` to `end`. The
+/// last point is always exactly `end`.
+fn motion_path(start: (i32, i32), end: (i32, i32), profile: &MotionProfile, steps: usize) -> Vec<(i32, i32)> {
+    let mut rng = rand::thread_rng();
+    let p0 = (start.0 as f64, start.1 as f64);
+    let p3 = (end.0 as f64, end.1 as f64);
+    let (dx, dy) = (p3.0 - p0.0, p3.1 - p0.1);
+    let distance = (dx * dx + dy * dy).sqrt();
+
+    // Control points are pushed sideways from the straight line by up to a
+    // fifth of the distance, which gives a slightly curved, hand-like arc.
+    let (p1, p2) = if profile.profile == MotionCurve::Bezier && distance > 0.0 {
+        let normal = (-dy / distance, dx / distance);
+        let bend = distance * 0.2;
+        let offset1 = rng.gen_range(-bend..=bend);
+        let offset2 = rng.gen_range(-bend..=bend);
+        (
+            (p0.0 + dx / 3.0 + normal.0 * offset1, p0.1 + dy / 3.0 + normal.1 * offset1),
+            (p0.0 + dx * 2.0 / 3.0 + normal.0 * offset2, p0.1 + dy * 2.0 / 3.0 + normal.1 * offset2),
+        )
+    } else {
+        (p0, p3)
+    };
+    let jitter = profile.jitter.unwrap_or(0.0).abs().min(MAX_JITTER);
+
+    (1..=steps)
+        .map(|step| {
+            if step == steps {
+                return end;
+            }
+            let t = step as f64 / steps as f64;
+            let (x, y) = match profile.profile {
+                MotionCurve::Linear => (p0.0 + dx * t, p0.1 + dy * t),
+                MotionCurve::Bezier => {
+                    // Ease in and out so the pointer accelerates and slows down
+                    let eased = t * t * (3.0 - 2.0 * t);
+                    cubic_bezier(p0, p1, p2, p3, eased)
+                }
+            };
+            let (jx, jy) = if jitter > 0.0 {
+                (rng.gen_range(-jitter..=jitter), rng.gen_range(-jitter..=jitter))
+            } else {
+                (0.0, 0.0)
+            };
+            ((x + jx).round() as i32, (y + jy).round() as i32)
+        })
+        .collect()
+}
+
+/// Moves the pointer to `target` along the given profile, spread over its duration.
+pub async fn move_pointer(target: (i32, i32), profile: &MotionProfile) -> Result<(), String> {
+    let duration_ms = profile
+        .duration_ms
+        .unwrap_or(DEFAULT_MOTION_DURATION_MS)
+        .min(MAX_MOTION_DURATION_MS);
+    let start = get_cursor_position()?;
+    let steps = (duration_ms / MOTION_STEP_MS).max(1) as usize;
+    let step_delay = Duration::from_millis(duration_ms / steps as u64);
+    log::debug!(
+        "Moving pointer from {:?} to {:?} ({:?}, {}ms, {} steps)",
+        start, target, profile.profile, duration_ms, steps
+    );
+
+    for (x, y) in motion_path(start, target, profile, steps) {
+        execute_xdotool(&["mousemove", &x.to_string(), &y.to_string()])?;
+        tokio::time::sleep(step_delay).await;
+    }
+    Ok(())
+}
+
+/// Presses the left button, moves to `target` along `profile` and releases it
+/// again.
+pub async fn drag(target: (i32, i32), profile: &MotionProfile) -> Result<(), String> {
+    execute_xdotool(&["mousedown", "1"])?;
+    let moved = move_pointer(target, profile).await;
+    // Always release the button, even if the move failed
+    let released = execute_xdotool(&["mouseup", "1"]).map(|_| ());
+    moved.and(released)
+}
+
+/// Types `text` one character at a time with randomized delays. The delays
+/// are applied here so that they are not distorted by HTTP latency.
+pub async fn type_text(text: &str, profile: &TypingProfile) -> Result<(), String> {
+    let delay_ms = profile.delay_ms.min(MAX_TYPING_DELAY_MS);
+    let variance_ms = profile.variance_ms.unwrap_or(0).min(delay_ms);
+    log::debug!("Typing {} characters ({}ms +/- {}ms)", text.chars().count(), delay_ms, variance_ms);
+
+    let mut buffer = [0u8; 4];
+    for (index, ch) in text.chars().enumerate() {
+        if index > 0 {
+            let delay = if variance_ms > 0 {
+                rand::thread_rng().gen_range(delay_ms - variance_ms..=delay_ms + variance_ms)
+            } else {
+                delay_ms
+            };
+            tokio::time::sleep(Duration::from_millis(delay)).await;
+        }
+        execute_xdotool(&["type", "--delay", "0", "--", ch.encode_utf8(&mut buffer)])?;
+    }
+    Ok(())
+}
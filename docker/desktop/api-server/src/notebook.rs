@@ -0,0 +1,294 @@
+use actix_web::http::StatusCode;
+use actix_web::HttpResponse;
+use serde::Serialize;
+use serde_json::{json, Value as JsonValue};
+use std::fs;
+
+use crate::{conflict, edit_result_message, encoding, fileio, history, structured, ActionResponse, EditCommand, EditRequest};
+
+/// Outputs are cut to this many characters in cell listings.
+const MAX_OUTPUT_CHARS: usize = 1000;
+const CELL_TYPES: [&str; 3] = ["code", "markdown", "raw"];
+
+#[derive(Debug, Serialize)]
+struct OutputSummary {
+    output_type: String,
+    text: String,
+}
+
+#[derive(Debug, Serialize)]
+struct CellSummary {
+    index: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    id: Option<String>,
+    cell_type: String,
+    source: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    execution_count: Option<i64>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    outputs: Vec<OutputSummary>,
+}
+
+#[derive(Debug, Serialize)]
+struct NotebookListing {
+    nbformat: u64,
+    nbformat_minor: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    language: Option<String>,
+    cells: Vec<CellSummary>,
+}
+
+/// Sources and stream outputs are stored as a string or a list of lines.
+fn multiline_text(value: &JsonValue) -> String {
+    match value {
+        JsonValue::String(text) => text.clone(),
+        JsonValue::Array(lines) => lines.iter().filter_map(JsonValue::as_str).collect(),
+        _ => String::new(),
+    }
+}
+
+/// Stores text as a list of lines, as Jupyter does.
+fn source_lines(text: &str) -> JsonValue {
+    text.split_inclusive('\n').map(JsonValue::from).collect()
+}
+
+fn truncate_output(text: String) -> String {
+    match text.char_indices().nth(MAX_OUTPUT_CHARS) {
+        Some((end, _)) => format!("{}… [{} more characters]", &text[..end], text[end..].chars().count()),
+        None => text,
+    }
+}
+
+/// The text of an output; rich outputs show their plain text and list the
+/// other formats they have.
+fn output_text(output: &JsonValue) -> String {
+    let text = match output["output_type"].as_str() {
+        Some("stream") => multiline_text(&output["text"]),
+        Some("execute_result" | "display_data") => {
+            let Some(data) = output["data"].as_object() else {
+                return String::new();
+            };
+            let mut text = data.get("text/plain").map(multiline_text).unwrap_or_default();
+            for media_type in data.keys().filter(|media_type| *media_type != "text/plain") {
+                if !text.is_empty() && !text.ends_with('\n') {
+                    text.push('\n');
+                }
+                text.push_str(&format!("[{}]", media_type));
+            }
+            text
+        }
+        Some("error") => format!(
+            "{}: {}",
+            output["ename"].as_str().unwrap_or_default(),
+            output["evalue"].as_str().unwrap_or_default()
+        ),
+        _ => String::new(),
+    };
+    truncate_output(text)
+}
+
+fn list_cells(notebook: &JsonValue, cells: &[JsonValue]) -> NotebookListing {
+    NotebookListing {
+        nbformat: notebook["nbformat"].as_u64().unwrap_or_default(),
+        nbformat_minor: notebook["nbformat_minor"].as_u64().unwrap_or_default(),
+        language: notebook["metadata"]["language_info"]["name"]
+            .as_str()
+            .or_else(|| notebook["metadata"]["kernelspec"]["language"].as_str())
+            .map(String::from),
+        cells: cells
+            .iter()
+            .enumerate()
+            .map(|(index, cell)| CellSummary {
+                index,
+                id: cell["id"].as_str().map(String::from),
+                cell_type: cell["cell_type"].as_str().unwrap_or_default().to_string(),
+                source: multiline_text(&cell["source"]),
+                execution_count: cell["execution_count"].as_i64(),
+                outputs: cell["outputs"]
+                    .as_array()
+                    .into_iter()
+                    .flatten()
+                    .map(|output| OutputSummary {
+                        output_type: output["output_type"].as_str().unwrap_or_default().to_string(),
+                        text: output_text(output),
+                    })
+                    .collect(),
+            })
+            .collect(),
+    }
+}
+
+/// Makes `cell` a cell of `cell_type`, adding or removing the fields only
+/// code cells have.
+fn set_cell_type(cell: &mut JsonValue, cell_type: &str) {
+    let Some(fields) = cell.as_object_mut() else {
+        return;
+    };
+    fields.insert(String::from("cell_type"), JsonValue::from(cell_type));
+    if cell_type == "code" {
+        fields.insert(String::from("execution_count"), JsonValue::Null);
+        fields.insert(String::from("outputs"), json!([]));
+    } else {
+        fields.shift_remove("execution_count");
+        fields.shift_remove("outputs");
+    }
+}
+
+fn new_cell(cell_type: &str, source: &str, with_id: bool) -> JsonValue {
+    let mut cell = json!({"cell_type": cell_type});
+    if cell_type == "code" {
+        cell["execution_count"] = JsonValue::Null;
+    }
+    if with_id {
+        // Cell ids are required from nbformat 4.5 on
+        cell["id"] = JsonValue::from(format!("{:016x}", rand::random::<u64>()));
+    }
+    cell["metadata"] = json!({});
+    if cell_type == "code" {
+        cell["outputs"] = json!([]);
+    }
+    cell["source"] = source_lines(source);
+    cell
+}
+
+fn clear_outputs(cell: &mut JsonValue) {
+    cell["outputs"] = json!([]);
+    cell["execution_count"] = JsonValue::Null;
+}
+
+fn cell_index(req: &EditRequest, len: usize) -> Result<usize, String> {
+    match req.cell {
+        None => Err(format!("cell is required for {} action", req.command)),
+        Some(cell) if cell >= len => Err(format!("Invalid cell {}: the notebook has {} cells", cell, len)),
+        Some(cell) => Ok(cell),
+    }
+}
+
+fn cell_type(req: &EditRequest) -> Result<Option<&str>, String> {
+    match req.cell_type.as_deref() {
+        Some(cell_type) if !CELL_TYPES.contains(&cell_type) => Err(format!("cell_type should be one of: {}", CELL_TYPES.join(", "))),
+        cell_type => Ok(cell_type),
+    }
+}
+
+fn source(req: &EditRequest) -> Result<&str, String> {
+    req.file_text.as_deref().ok_or_else(|| format!("file_text is required for {} action", req.command))
+}
+
+/// Applies a mutating notebook command to the cells. Returns the message
+/// describing the change.
+fn edit_cells(req: &EditRequest, command: &EditCommand, cells: &mut Vec<JsonValue>, with_ids: bool) -> Result<String, String> {
+    match command {
+        EditCommand::ReplaceCell => {
+            let index = cell_index(req, cells.len())?;
+            let (source, cell_type) = (source(req)?, cell_type(req)?);
+            let cell = &mut cells[index];
+            if let Some(cell_type) = cell_type.filter(|cell_type| cell["cell_type"] != *cell_type) {
+                set_cell_type(cell, cell_type);
+            } else if cell["cell_type"] == "code" {
+                // The outputs belong to the old source
+                clear_outputs(cell);
+            }
+            cell["source"] = source_lines(source);
+            Ok(format!("Cell {} replaced", index))
+        }
+        EditCommand::InsertCell => {
+            let index = req.cell.unwrap_or(cells.len());
+            if index > cells.len() {
+                return Err(format!("Invalid cell {}: the notebook has {} cells", index, cells.len()));
+            }
+            let cell = new_cell(cell_type(req)?.unwrap_or("code"), source(req)?, with_ids);
+            cells.insert(index, cell);
+            Ok(format!("Cell inserted at index {}", index))
+        }
+        EditCommand::DeleteCell => {
+            let index = cell_index(req, cells.len())?;
+            cells.remove(index);
+            Ok(format!("Cell {} deleted", index))
+        }
+        _ => {
+            if req.cell.is_some() {
+                let index = cell_index(req, cells.len())?;
+                if cells[index]["cell_type"] != "code" {
+                    return Err(format!("Cell {} is not a code cell", index));
+                }
+                clear_outputs(&mut cells[index]);
+                return Ok(format!("Outputs of cell {} cleared", index));
+            }
+            let mut cleared = 0;
+            for cell in cells.iter_mut().filter(|cell| cell["cell_type"] == "code") {
+                clear_outputs(cell);
+                cleared += 1;
+            }
+            Ok(format!("Outputs of {} code cells cleared", cleared))
+        }
+    }
+}
+
+fn error_response(status: StatusCode, e: String) -> HttpResponse {
+    HttpResponse::build(status).json(ActionResponse {
+        r#type: String::from("error"),
+        media_type: String::from("text/plain"),
+        data: e,
+    })
+}
+
+/// Handles `list_cells`, `replace_cell`, `insert_cell`, `delete_cell` and
+/// `clear_outputs` on Jupyter notebooks (nbformat 4). Everything but the
+/// changed cells, including notebook and cell metadata, is written back as
+/// it was.
+pub fn handle_notebook_command(req: &EditRequest, command: EditCommand) -> HttpResponse {
+    let bytes = match fs::read(&req.path) {
+        Ok(bytes) => bytes,
+        Err(e) => return error_response(StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to read file: {}", e)),
+    };
+    let (content, text_format) = match encoding::decode(&bytes) {
+        Ok(decoded) => decoded,
+        Err(e) => return error_response(StatusCode::BAD_REQUEST, e),
+    };
+    let mut notebook: JsonValue = match serde_json::from_str(&content) {
+        Ok(notebook) => notebook,
+        Err(e) => return error_response(StatusCode::BAD_REQUEST, format!("Failed to parse {} as a notebook: {}", req.path, e)),
+    };
+    if notebook["nbformat"].as_u64() != Some(4) || !notebook["cells"].is_array() {
+        return error_response(StatusCode::BAD_REQUEST, format!("{} is not a Jupyter notebook in nbformat 4", req.path));
+    }
+    let with_ids = notebook["nbformat_minor"].as_u64().is_some_and(|minor| minor >= 5);
+
+    if matches!(command, EditCommand::ListCells) {
+        let cells = notebook["cells"].as_array().map(Vec::as_slice).unwrap_or_default();
+        let response = HttpResponse::Ok().json(ActionResponse {
+            r#type: String::from("success"),
+            media_type: String::from("application/json"),
+            data: serde_json::to_string(&list_cells(&notebook, cells)).unwrap_or_default(),
+        });
+        return conflict::with_content_hash(response, &bytes);
+    }
+
+    let Some(cells) = notebook["cells"].as_array_mut() else {
+        return error_response(StatusCode::BAD_REQUEST, format!("{} is not a Jupyter notebook in nbformat 4", req.path));
+    };
+    let message = match edit_cells(req, &command, cells, with_ids) {
+        Ok(message) => message,
+        Err(e) => return error_response(StatusCode::BAD_REQUEST, e),
+    };
+    let new_content = match structured::json_text(&notebook, &content) {
+        Ok(new_content) => new_content,
+        Err(e) => return error_response(StatusCode::INTERNAL_SERVER_ERROR, e),
+    };
+    let encoded = match encoding::encode(&new_content, &text_format) {
+        Ok(encoded) => encoded,
+        Err(e) => return error_response(StatusCode::BAD_REQUEST, e),
+    };
+    if let Err(e) = fileio::write_atomic(&req.path, &encoded) {
+        return error_response(StatusCode::INTERNAL_SERVER_ERROR, e);
+    }
+    let data = edit_result_message(req, &message, &content, &new_content);
+    history::record(&req.path, &req.command, Some(bytes));
+    let response = HttpResponse::Ok().json(ActionResponse {
+        r#type: String::from("success"),
+        media_type: String::from("text/plain"),
+        data,
+    });
+    conflict::with_content_hash(response, &encoded)
+}
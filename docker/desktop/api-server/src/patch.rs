@@ -0,0 +1,466 @@
+use serde::Serialize;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use crate::encoding::{self, TextFormat};
+use crate::{fileio, fsops, history, sandbox};
+
+/// Number of context lines that may be ignored at each end of a hunk when it
+/// does not apply as is, like the default fuzz factor of GNU patch.
+const MAX_FUZZ: usize = 2;
+
+#[derive(Debug)]
+struct Hunk {
+    old_start: usize,
+    /// Lines as they appear in the patch, each prefixed with ' ', '-' or '+'.
+    lines: Vec<String>,
+    /// The new side ends without a trailing newline.
+    no_newline_at_end: bool,
+}
+
+impl Hunk {
+    fn old_lines(&self) -> Vec<&str> {
+        self.lines
+            .iter()
+            .filter(|line| !line.starts_with('+'))
+            .map(|line| &line[1..])
+            .collect()
+    }
+
+    fn new_lines(&self) -> Vec<&str> {
+        self.lines
+            .iter()
+            .filter(|line| !line.starts_with('-'))
+            .map(|line| &line[1..])
+            .collect()
+    }
+
+    /// Number of leading and trailing context lines.
+    fn context(&self) -> (usize, usize) {
+        let leading = self.lines.iter().take_while(|line| line.starts_with(' ')).count();
+        let trailing = self.lines.iter().rev().take_while(|line| line.starts_with(' ')).count();
+        (leading, trailing)
+    }
+}
+
+#[derive(Debug)]
+struct FilePatch {
+    /// `None` for `/dev/null`, i.e. a newly created file.
+    old_path: Option<String>,
+    /// `None` for `/dev/null`, i.e. a deleted file.
+    new_path: Option<String>,
+    hunks: Vec<Hunk>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct HunkStatus {
+    /// 1-based index of the hunk within its file section.
+    pub hunk: usize,
+    pub old_start: usize,
+    /// "applied", "failed" or "skipped" (not attempted after an earlier failure).
+    pub status: String,
+    /// Lines between where the hunk claimed to apply and where it did.
+    pub offset: isize,
+    /// Context lines that had to be ignored for the hunk to apply.
+    pub fuzz: usize,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct FileReport {
+    pub path: String,
+    /// "create", "modify" or "delete".
+    pub action: String,
+    pub hunks: Vec<HunkStatus>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PatchReport {
+    pub applied: bool,
+    pub files: Vec<FileReport>,
+}
+
+#[derive(Debug)]
+pub enum PatchError {
+    /// The patch could not be parsed.
+    Invalid(String),
+    /// At least one hunk did not apply; nothing was written.
+    Rejected(PatchReport),
+    /// The patch touches a file the sandbox policy does not allow writing.
+    Forbidden(String),
+    /// Reading or writing a file failed; any files already written were restored.
+    Io(String),
+}
+
+/// Strips the timestamp git and diff append after a tab, and reports
+/// `/dev/null` as `None`.
+fn header_path(line: &str, prefix: &str) -> Option<String> {
+    let path = line[prefix.len()..].split('\t').next().unwrap_or_default().trim();
+    (path != "/dev/null").then(|| path.to_string())
+}
+
+/// Parses "@@ -l,s +l,s @@" and returns the old start and the old and new
+/// line counts.
+fn parse_hunk_header(line: &str) -> Option<(usize, usize, usize)> {
+    let ranges = line.strip_prefix("@@ ")?.split(" @@").next()?;
+    let mut parts = ranges.split(' ');
+    let range = |part: Option<&str>, sign: char| -> Option<(usize, usize)> {
+        let part = part?.strip_prefix(sign)?;
+        match part.split_once(',') {
+            Some((start, count)) => Some((start.parse().ok()?, count.parse().ok()?)),
+            None => Some((part.parse().ok()?, 1)),
+        }
+    };
+    let (old_start, old_count) = range(parts.next(), '-')?;
+    let (_, new_count) = range(parts.next(), '+')?;
+    Some((old_start, old_count, new_count))
+}
+
+fn parse(patch: &str) -> Result<Vec<FilePatch>, String> {
+    let lines: Vec<&str> = patch.lines().collect();
+    let mut files: Vec<FilePatch> = Vec::new();
+    let mut i = 0;
+
+    while i < lines.len() {
+        let line = lines[i];
+        if line.starts_with("--- ") && lines.get(i + 1).is_some_and(|next| next.starts_with("+++ ")) {
+            files.push(FilePatch {
+                old_path: header_path(line, "--- "),
+                new_path: header_path(lines[i + 1], "+++ "),
+                hunks: Vec::new(),
+            });
+            i += 2;
+        } else if line.starts_with("@@ ") {
+            let file = files
+                .last_mut()
+                .ok_or_else(|| format!("Hunk without file header at line {}", i + 1))?;
+            let (old_start, mut old_left, mut new_left) =
+                parse_hunk_header(line).ok_or_else(|| format!("Invalid hunk header at line {}: {}", i + 1, line))?;
+            let mut hunk = Hunk {
+                old_start,
+                lines: Vec::new(),
+                no_newline_at_end: false,
+            };
+            i += 1;
+            while old_left > 0 || new_left > 0 {
+                let Some(&body) = lines.get(i) else {
+                    return Err(format!("Hunk at line {} ends prematurely", i));
+                };
+                // Some tools drop the space of empty context lines
+                let body = if body.is_empty() { " " } else { body };
+                match body.as_bytes()[0] {
+                    b' ' if old_left > 0 && new_left > 0 => {
+                        old_left -= 1;
+                        new_left -= 1;
+                    }
+                    b'-' if old_left > 0 => old_left -= 1,
+                    b'+' if new_left > 0 => new_left -= 1,
+                    b'\\' => {
+                        i += 1;
+                        continue;
+                    }
+                    _ => return Err(format!("Unexpected line {} in hunk: {}", i + 1, body)),
+                }
+                hunk.lines.push(body.to_string());
+                i += 1;
+            }
+            // "\ No newline at end of file" after a line of the new side
+            if lines.get(i).is_some_and(|next| next.starts_with('\\'))
+                && hunk.lines.last().is_some_and(|last| !last.starts_with('-'))
+            {
+                hunk.no_newline_at_end = true;
+            }
+            file.hunks.push(hunk);
+        } else {
+            // `diff --git`, `index` and similar lines carry nothing we need
+            i += 1;
+        }
+    }
+
+    if files.is_empty() {
+        return Err(String::from("No file headers (--- / +++) found in patch"));
+    }
+    Ok(files)
+}
+
+/// Resolves a path from a patch header against `base`, dropping the `a/` and
+/// `b/` prefixes git adds.
+fn resolve_path(base: &Path, path: &str) -> PathBuf {
+    let path = path
+        .strip_prefix("a/")
+        .or_else(|| path.strip_prefix("b/"))
+        .filter(|_| !Path::new(path).is_absolute())
+        .unwrap_or(path);
+    base.join(path)
+}
+
+/// Finds where `old` occurs in `lines`, starting the search at `expected` and
+/// moving outwards, but never before `min_start`.
+fn find_hunk(lines: &[&str], old: &[&str], expected: usize, min_start: usize) -> Option<usize> {
+    if old.len() > lines.len() {
+        return None;
+    }
+    let last_start = lines.len() - old.len();
+    let matches = |start: usize| start >= min_start && start <= last_start && lines[start..start + old.len()] == *old;
+    let expected = expected.clamp(min_start, last_start.max(min_start));
+    (0..=lines.len()).find_map(|distance| {
+        if matches(expected + distance) {
+            Some(expected + distance)
+        } else if distance <= expected && matches(expected - distance) {
+            Some(expected - distance)
+        } else {
+            None
+        }
+    })
+}
+
+/// Applies the hunks of one file section to `content`. Returns the new
+/// content, or `None` if any hunk failed, together with the per-hunk status.
+fn apply_hunks(content: &str, hunks: &[Hunk]) -> (Option<String>, Vec<HunkStatus>) {
+    let mut lines: Vec<&str> = content.lines().collect();
+    let mut trailing_newline = content.is_empty() || content.ends_with('\n');
+    let mut statuses = Vec::new();
+    let mut failed = false;
+    // Shift between the old line numbers in the patch and the current lines
+    let mut delta: isize = 0;
+    let mut min_start = 0;
+
+    for (index, hunk) in hunks.iter().enumerate() {
+        let mut status = HunkStatus {
+            hunk: index + 1,
+            old_start: hunk.old_start,
+            status: String::from("skipped"),
+            offset: 0,
+            fuzz: 0,
+        };
+        if failed {
+            statuses.push(status);
+            continue;
+        }
+
+        let old = hunk.old_lines();
+        let new = hunk.new_lines();
+        // Fuzz is only for hunks that change something; dropping context from
+        // a context-only hunk would leave nothing to match
+        let (leading, trailing) = if old.len() == hunk.lines.len() && new.len() == hunk.lines.len() {
+            (0, 0)
+        } else {
+            hunk.context()
+        };
+        // An empty old side (e.g. a new file) has start 0 instead of 1
+        let claimed = hunk.old_start.saturating_sub(1) + usize::from(old.is_empty() && hunk.old_start > 0);
+        let expected = (claimed as isize + delta).max(0) as usize;
+
+        let found = (0..=MAX_FUZZ).find_map(|fuzz| {
+            let (skip_start, skip_end) = (fuzz.min(leading), fuzz.min(trailing));
+            if fuzz > 0 && skip_start + skip_end == 0 {
+                return None;
+            }
+            let old = &old[skip_start..old.len() - skip_end];
+            find_hunk(&lines, old, expected + skip_start, min_start)
+                .map(|start| (fuzz, skip_start, skip_end, start))
+        });
+        let Some((fuzz, skip_start, skip_end, start)) = found else {
+            status.status = String::from("failed");
+            failed = true;
+            statuses.push(status);
+            continue;
+        };
+
+        let replacement: Vec<&str> = new[skip_start..new.len() - skip_end].to_vec();
+        let removed = old.len() - skip_start - skip_end;
+        let at_end = start + removed == lines.len();
+        status.status = String::from("applied");
+        // A fuzzed match may start before the skipped context lines
+        status.offset = start as isize - skip_start as isize - (claimed as isize + delta);
+        status.fuzz = fuzz;
+        statuses.push(status);
+
+        delta += replacement.len() as isize - removed as isize;
+        lines.splice(start..start + removed, replacement.iter().copied());
+        min_start = start + replacement.len();
+        if at_end && skip_end == 0 {
+            trailing_newline = !hunk.no_newline_at_end;
+        }
+    }
+
+    if failed {
+        return (None, statuses);
+    }
+    let mut result = lines.join("\n");
+    if trailing_newline && !lines.is_empty() {
+        result.push('\n');
+    }
+    (Some(result), statuses)
+}
+
+/// A file touched by the patch, with the content it should end up with.
+struct PendingWrite {
+    path: String,
+    previous: Option<Vec<u8>>,
+    content: Option<String>,
+    format: TextFormat,
+}
+
+/// Applies a unified diff that may touch several files. Paths in the patch
+/// are resolved against `base`. Either every hunk applies and all files are
+/// written, or nothing is changed.
+pub fn apply_patch(base: &str, patch: &str) -> Result<PatchReport, PatchError> {
+    let file_patches = parse(patch).map_err(PatchError::Invalid)?;
+    let base = Path::new(base);
+
+    let mut pending: Vec<PendingWrite> = Vec::new();
+    let mut index_by_path: HashMap<String, usize> = HashMap::new();
+    let mut report = PatchReport {
+        applied: true,
+        files: Vec::new(),
+    };
+
+    for file_patch in &file_patches {
+        let (path, action) = match (&file_patch.old_path, &file_patch.new_path) {
+            (None, None) => return Err(PatchError::Invalid(String::from("Patch file header names no file"))),
+            (None, Some(new)) => (resolve_path(base, new), "create"),
+            (Some(old), None) => (resolve_path(base, old), "delete"),
+            (Some(old), Some(new)) => {
+                let (old, new) = (resolve_path(base, old), resolve_path(base, new));
+                if old != new {
+                    return Err(PatchError::Invalid(format!(
+                        "Renaming files is not supported ({} -> {})",
+                        old.display(),
+                        new.display()
+                    )));
+                }
+                (new, "modify")
+            }
+        };
+        let path = path.to_string_lossy().to_string();
+        sandbox::check(&path, sandbox::Access::Write).map_err(PatchError::Forbidden)?;
+
+        // Later sections for the same file apply on top of earlier ones
+        let slot = match index_by_path.get(&path) {
+            Some(&slot) => slot,
+            None => {
+                let previous = history::read_current(&path).map_err(PatchError::Io)?;
+                let decoded = previous
+                    .as_deref()
+                    .map(encoding::decode)
+                    .transpose()
+                    .map_err(|e| PatchError::Invalid(format!("Cannot patch {}: {}", path, e)))?;
+                let (content, format) = decoded.map_or((None, TextFormat::default()), |(text, format)| (Some(text), format));
+                pending.push(PendingWrite {
+                    path: path.clone(),
+                    previous,
+                    content,
+                    format,
+                });
+                index_by_path.insert(path.clone(), pending.len() - 1);
+                pending.len() - 1
+            }
+        };
+        let current = &mut pending[slot];
+
+        match (&current.content, action) {
+            (Some(_), "create") => {
+                return Err(PatchError::Invalid(format!("Cannot create {}: file already exists", path)));
+            }
+            (None, "modify" | "delete") => {
+                return Err(PatchError::Invalid(format!("Cannot patch {}: file does not exist", path)));
+            }
+            _ => {}
+        }
+
+        let (content, hunks) = apply_hunks(current.content.as_deref().unwrap_or_default(), &file_patch.hunks);
+        let mut error = None;
+        match content {
+            Some(content) if action == "delete" && !content.is_empty() => {
+                error = Some(String::from("File is not empty after removing the patched lines"));
+            }
+            Some(_) if action == "delete" => current.content = None,
+            Some(content) => current.content = Some(content),
+            None => {
+                let failed = hunks.iter().find(|hunk| hunk.status == "failed").map_or(0, |hunk| hunk.hunk);
+                error = Some(format!("Hunk {} does not apply", failed));
+            }
+        }
+        report.applied &= error.is_none();
+        report.files.push(FileReport {
+            path,
+            action: action.to_string(),
+            hunks,
+            error,
+        });
+    }
+
+    if !report.applied {
+        return Err(PatchError::Rejected(report));
+    }
+
+    let mut encoded = Vec::new();
+    for write in &pending {
+        let content = write.content.as_deref().map(|content| encoding::encode(content, &write.format)).transpose();
+        encoded.push(content.map_err(|e| PatchError::Invalid(format!("Cannot patch {}: {}", write.path, e)))?);
+    }
+    // Directories created for new files, removed again on rollback
+    let mut created_dirs = Vec::new();
+    for (index, (write, content)) in pending.iter().zip(&encoded).enumerate() {
+        let result = match content {
+            // New files may live in directories the patch introduces
+            Some(content) if write.previous.is_none() => {
+                let dirs = Path::new(&write.path).parent().map(fsops::missing_dirs).unwrap_or_default();
+                fsops::create_dirs(&dirs).and_then(|_| {
+                    created_dirs.push(dirs);
+                    fileio::write_atomic(&write.path, content)
+                })
+            }
+            Some(content) => fileio::write_atomic(&write.path, content),
+            None => history::restore(&write.path, &None),
+        };
+        if let Err(e) = result {
+            log::error!("Failed to write {} while applying patch, rolling back: {}", write.path, e);
+            for written in &pending[..index] {
+                if let Err(e) = history::restore(&written.path, &written.previous) {
+                    log::error!("Failed to roll back {}: {}", written.path, e);
+                }
+            }
+            for dirs in created_dirs.iter().rev() {
+                if let Err(e) = fsops::remove_dirs(dirs) {
+                    log::error!("Failed to roll back directories: {}", e);
+                }
+            }
+            return Err(PatchError::Io(format!("Failed to write {}: {}", write.path, e)));
+        }
+    }
+    for write in pending {
+        history::record(&write.path, "apply_patch", write.previous);
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn apply(content: &str, patch: &str) -> (Option<String>, Vec<HunkStatus>) {
+        let files = parse(patch).unwrap();
+        apply_hunks(content, &files[0].hunks)
+    }
+
+    #[test]
+    fn fuzzed_match_before_skipped_context() {
+        let (content, statuses) = apply("b\nc\n", "--- a/f\n+++ b/f\n@@ -1,3 +1,3 @@\n a\n-b\n+B\n c\n");
+        assert_eq!(content.as_deref(), Some("B\nc\n"));
+        assert_eq!(statuses[0].fuzz, 1);
+        assert_eq!(statuses[0].offset, -1);
+    }
+
+    #[test]
+    fn context_only_hunk_is_not_fuzzed() {
+        let (content, statuses) = apply("x\ny\n", "--- a/f\n+++ b/f\n@@ -1,1 +1,1 @@\n q\n");
+        assert_eq!(content, None);
+        assert_eq!(statuses[0].status, "failed");
+
+        let (content, _) = apply("x\ny\n", "--- a/f\n+++ b/f\n@@ -2,1 +2,1 @@\n y\n");
+        assert_eq!(content.as_deref(), Some("x\ny\n"));
+    }
+}
@@ -0,0 +1,480 @@
+use actix_web::{web, HttpResponse};
+use actix_web::http::StatusCode;
+use base64::{Engine as _, engine::general_purpose};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::process::Command;
+use std::sync::{Mutex, OnceLock};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use tokio::time::Duration;
+
+use crate::{sandbox, ActionRequest, ActionResponse, EditRequest};
+
+const RECORDING_VERSION: u32 = 1;
+const DEFAULT_SCREENSHOT_THRESHOLD: f64 = 0.99;
+
+static RECORDER: OnceLock<Mutex<Option<ActiveRecording>>> = OnceLock::new();
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RecordedEndpoint {
+    Computer,
+    Edit,
+    EditBatch,
+}
+
+/// One handled request as stored in a recording file.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecordedStep {
+    pub index: usize,
+    /// Wall-clock time the request was handled, in milliseconds since the Unix epoch.
+    pub timestamp_ms: u128,
+    /// Time since the recording was started, used to reproduce pacing on replay.
+    pub offset_ms: u64,
+    pub endpoint: RecordedEndpoint,
+    pub request: serde_json::Value,
+    pub status: u16,
+    pub response: serde_json::Value,
+    /// Base64 PNG of the desktop taken right after the step was handled.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub screenshot: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Recording {
+    pub version: u32,
+    pub started_at_ms: u128,
+    pub steps: Vec<RecordedStep>,
+}
+
+struct ActiveRecording {
+    path: String,
+    capture_screenshots: bool,
+    started: Instant,
+    recording: Recording,
+}
+
+#[derive(Debug)]
+pub enum RecordingCommand {
+    Start,
+    Stop,
+    Status,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RecordingRequest {
+    pub command: String,
+    pub path: Option<String>,
+    pub capture_screenshots: Option<bool>,
+}
+
+impl RecordingRequest {
+    fn parse_command(&self) -> Option<RecordingCommand> {
+        match self.command.as_str() {
+            "start" => Some(RecordingCommand::Start),
+            "stop" => Some(RecordingCommand::Stop),
+            "status" => Some(RecordingCommand::Status),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ReplayRequest {
+    pub path: String,
+    /// Playback speed relative to the original run; 2.0 replays twice as fast.
+    pub speed: Option<f64>,
+    pub dry_run: Option<bool>,
+    pub compare_screenshots: Option<bool>,
+    /// Minimum similarity (0.0 - 1.0) for a replayed screenshot to count as a match.
+    pub screenshot_threshold: Option<f64>,
+}
+
+#[derive(Debug, Serialize)]
+struct ReplayStepResult {
+    index: usize,
+    endpoint: RecordedEndpoint,
+    summary: String,
+    delay_ms: u64,
+    original_status: u16,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    status: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    status_matches: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    screenshot_similarity: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    screenshot_matches: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct ReplayReport {
+    path: String,
+    dry_run: bool,
+    speed: f64,
+    total_steps: usize,
+    mismatches: usize,
+    steps: Vec<ReplayStepResult>,
+}
+
+fn now_ms() -> u128 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis())
+        .unwrap_or_default()
+}
+
+fn recorder() -> &'static Mutex<Option<ActiveRecording>> {
+    RECORDER.get_or_init(|| Mutex::new(None))
+}
+
+pub fn is_recording() -> bool {
+    recorder().lock().unwrap().is_some()
+}
+
+/// Appends a handled request to the active recording and returns the response
+/// unchanged. Does nothing if no recording is active.
+pub async fn record<T: Serialize>(
+    endpoint: RecordedEndpoint,
+    request: &T,
+    response: HttpResponse,
+) -> HttpResponse {
+    let headers = response.headers().clone();
+    let (status, body) = crate::response_json(response).await;
+
+    let capture = recorder()
+        .lock()
+        .unwrap()
+        .as_ref()
+        .map(|active| active.capture_screenshots);
+    if let Some(capture_screenshots) = capture {
+        let screenshot = if capture_screenshots {
+            match crate::capture_screenshot() {
+                Ok(image_data) => Some(general_purpose::STANDARD.encode(&image_data)),
+                Err(e) => {
+                    log::warn!("Failed to capture screenshot for recording: {}", e);
+                    None
+                }
+            }
+        } else {
+            None
+        };
+
+        let mut guard = recorder().lock().unwrap();
+        if let Some(active) = guard.as_mut() {
+            let step = RecordedStep {
+                index: active.recording.steps.len(),
+                timestamp_ms: now_ms(),
+                offset_ms: active.started.elapsed().as_millis() as u64,
+                endpoint,
+                request: serde_json::to_value(request).unwrap_or_default(),
+                status: status.as_u16(),
+                response: body.clone(),
+                screenshot,
+            };
+            log::debug!("Recorded step {} ({:?})", step.index, endpoint);
+            active.recording.steps.push(step);
+        }
+    }
+
+    crate::rebuild_response(status, &headers, body)
+}
+
+pub async fn handle_recording_action(req: web::Json<RecordingRequest>) -> HttpResponse {
+    let mut guard = recorder().lock().unwrap();
+
+    match req.parse_command() {
+        Some(RecordingCommand::Start) => {
+            let Some(path) = &req.path else {
+                return HttpResponse::BadRequest().json(ActionResponse {
+                    r#type: String::from("error"),
+                    media_type: String::from("text/plain"),
+                    data: String::from("path is required for start command"),
+                });
+            };
+            if let Err(e) = sandbox::check(path, sandbox::Access::Write) {
+                return HttpResponse::Forbidden().json(ActionResponse {
+                    r#type: String::from("error"),
+                    media_type: String::from("text/plain"),
+                    data: e,
+                });
+            }
+            if let Some(active) = guard.as_ref() {
+                return HttpResponse::Conflict().json(ActionResponse {
+                    r#type: String::from("error"),
+                    media_type: String::from("text/plain"),
+                    data: format!("A recording to {} is already in progress", active.path),
+                });
+            }
+
+            log::info!("Starting recording to {}", path);
+            *guard = Some(ActiveRecording {
+                path: path.clone(),
+                capture_screenshots: req.capture_screenshots.unwrap_or(false),
+                started: Instant::now(),
+                recording: Recording {
+                    version: RECORDING_VERSION,
+                    started_at_ms: now_ms(),
+                    steps: Vec::new(),
+                },
+            });
+            HttpResponse::Ok().json(ActionResponse {
+                r#type: String::from("success"),
+                media_type: String::from("text/plain"),
+                data: format!("Recording started, steps will be saved to: {}", path),
+            })
+        }
+        Some(RecordingCommand::Stop) => {
+            let Some(active) = guard.take() else {
+                return HttpResponse::BadRequest().json(ActionResponse {
+                    r#type: String::from("error"),
+                    media_type: String::from("text/plain"),
+                    data: String::from("No recording in progress"),
+                });
+            };
+
+            let steps = active.recording.steps.len();
+            // Checked again, as the path may have been replaced by a symlink since the start
+            let written = sandbox::check(&active.path, sandbox::Access::Write).and_then(|_| {
+                serde_json::to_string_pretty(&active.recording)
+                    .map_err(|e| e.to_string())
+                    .and_then(|json| fs::write(&active.path, json).map_err(|e| e.to_string()))
+            });
+            match written {
+                Ok(_) => {
+                    log::info!("Recording with {} steps saved to {}", steps, active.path);
+                    HttpResponse::Ok().json(ActionResponse {
+                        r#type: String::from("success"),
+                        media_type: String::from("text/plain"),
+                        data: format!("Recording with {} steps saved to: {}", steps, active.path),
+                    })
+                }
+                Err(e) => {
+                    log::error!("Failed to save recording: {}", e);
+                    HttpResponse::InternalServerError().json(ActionResponse {
+                        r#type: String::from("error"),
+                        media_type: String::from("text/plain"),
+                        data: format!("Failed to save recording: {}", e),
+                    })
+                }
+            }
+        }
+        Some(RecordingCommand::Status) => {
+            let status = match guard.as_ref() {
+                Some(active) => serde_json::json!({
+                    "recording": true,
+                    "path": active.path,
+                    "capture_screenshots": active.capture_screenshots,
+                    "steps": active.recording.steps.len(),
+                    "elapsed_ms": active.started.elapsed().as_millis() as u64,
+                }),
+                None => serde_json::json!({ "recording": false }),
+            };
+            HttpResponse::Ok().json(ActionResponse {
+                r#type: String::from("success"),
+                media_type: String::from("application/json"),
+                data: status.to_string(),
+            })
+        }
+        None => {
+            log::warn!("Invalid recording command received: {}", req.command);
+            HttpResponse::BadRequest().json(ActionResponse {
+                r#type: String::from("error"),
+                media_type: String::from("text/plain"),
+                data: String::from("Unsupported recording command"),
+            })
+        }
+    }
+}
+
+fn summarize(step: &RecordedStep) -> String {
+    let field = |name: &str| {
+        step.request
+            .get(name)
+            .and_then(|v| v.as_str())
+            .unwrap_or("?")
+            .to_string()
+    };
+    match step.endpoint {
+        RecordedEndpoint::Computer => match step.request.get("coordinate") {
+            Some(coordinate) if !coordinate.is_null() => format!("{} {}", field("action"), coordinate),
+            _ => field("action"),
+        },
+        RecordedEndpoint::Edit => format!("{} {}", field("command"), field("path")),
+        RecordedEndpoint::EditBatch => {
+            let edits = step.request.get("edits").and_then(|v| v.as_array()).map_or(0, |v| v.len());
+            format!("batch of {} edits", edits)
+        }
+    }
+}
+
+/// Compares two PNG screenshots with ImageMagick and returns their similarity
+/// in the range 0.0 (completely different) to 1.0 (identical).
+fn compare_screenshots(original: &[u8], replayed: &[u8], index: usize) -> Result<f64, String> {
+    let prefix = format!("/tmp/consoley-replay-{}-{}", std::process::id(), index);
+    let original_path = format!("{}-original.png", prefix);
+    let replayed_path = format!("{}-replayed.png", prefix);
+    fs::write(&original_path, original).map_err(|e| e.to_string())?;
+    fs::write(&replayed_path, replayed).map_err(|e| e.to_string())?;
+
+    let output = Command::new("compare")
+        .args(["-metric", "RMSE", &original_path, &replayed_path, "null:"])
+        .output();
+
+    let _ = fs::remove_file(&original_path);
+    let _ = fs::remove_file(&replayed_path);
+
+    let output = output.map_err(|e| format!("Failed to execute compare: {}", e))?;
+    // compare exits with 1 when the images differ and 2 on errors; the metric
+    // is printed on stderr as "<absolute> (<normalized>)".
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    if output.status.code() == Some(2) {
+        return Err(format!("compare failed: {}", stderr.trim()));
+    }
+    stderr
+        .split('(')
+        .nth(1)
+        .and_then(|rest| rest.split(')').next())
+        .and_then(|value| value.trim().parse::<f64>().ok())
+        .map(|distance| (1.0 - distance).clamp(0.0, 1.0))
+        .ok_or_else(|| format!("Failed to parse compare output: {}", stderr.trim()))
+}
+
+async fn replay_step(step: &RecordedStep) -> Result<StatusCode, String> {
+    let response = match step.endpoint {
+        RecordedEndpoint::Computer => {
+            let request: ActionRequest = serde_json::from_value(step.request.clone())
+                .map_err(|e| format!("Invalid recorded request: {}", e))?;
+            crate::handle_computer_action(web::Json(request)).await
+        }
+        RecordedEndpoint::Edit => {
+            let request: EditRequest = serde_json::from_value(step.request.clone())
+                .map_err(|e| format!("Invalid recorded request: {}", e))?;
+            crate::handle_edit_action(web::Json(request)).await
+        }
+        RecordedEndpoint::EditBatch => {
+            let request: crate::batch::BatchRequest = serde_json::from_value(step.request.clone())
+                .map_err(|e| format!("Invalid recorded request: {}", e))?;
+            crate::batch::handle_batch_action(web::Json(request)).await
+        }
+    };
+    Ok(response.status())
+}
+
+pub async fn handle_replay(req: web::Json<ReplayRequest>) -> HttpResponse {
+    let speed = req.speed.unwrap_or(1.0);
+    if !speed.is_finite() || speed <= 0.0 {
+        return HttpResponse::BadRequest().json(ActionResponse {
+            r#type: String::from("error"),
+            media_type: String::from("text/plain"),
+            data: String::from("speed must be a positive number"),
+        });
+    }
+
+    if let Err(e) = sandbox::check(&req.path, sandbox::Access::Read) {
+        return HttpResponse::Forbidden().json(ActionResponse {
+            r#type: String::from("error"),
+            media_type: String::from("text/plain"),
+            data: e,
+        });
+    }
+
+    let recording: Recording = match fs::read_to_string(&req.path)
+        .map_err(|e| format!("Failed to read recording: {}", e))
+        .and_then(|json| serde_json::from_str(&json).map_err(|e| format!("Invalid recording file: {}", e)))
+    {
+        Ok(recording) => recording,
+        Err(e) => {
+            log::error!("{}", e);
+            return HttpResponse::BadRequest().json(ActionResponse {
+                r#type: String::from("error"),
+                media_type: String::from("text/plain"),
+                data: e,
+            });
+        }
+    };
+
+    let dry_run = req.dry_run.unwrap_or(false);
+    let compare = req.compare_screenshots.unwrap_or(false);
+    let threshold = req.screenshot_threshold.unwrap_or(DEFAULT_SCREENSHOT_THRESHOLD);
+    log::info!(
+        "Replaying {} steps from {} (speed: {}, dry_run: {})",
+        recording.steps.len(), req.path, speed, dry_run
+    );
+
+    let mut results = Vec::with_capacity(recording.steps.len());
+    let mut previous_offset = 0;
+    for step in &recording.steps {
+        let delay_ms = (step.offset_ms.saturating_sub(previous_offset) as f64 / speed) as u64;
+        previous_offset = step.offset_ms;
+
+        let mut result = ReplayStepResult {
+            index: step.index,
+            endpoint: step.endpoint,
+            summary: summarize(step),
+            delay_ms,
+            original_status: step.status,
+            status: None,
+            status_matches: None,
+            screenshot_similarity: None,
+            screenshot_matches: None,
+            error: None,
+        };
+
+        if dry_run {
+            results.push(result);
+            continue;
+        }
+
+        tokio::time::sleep(Duration::from_millis(delay_ms)).await;
+        match replay_step(step).await {
+            Ok(status) => {
+                result.status = Some(status.as_u16());
+                result.status_matches = Some(status.as_u16() == step.status);
+            }
+            Err(e) => result.error = Some(e),
+        }
+
+        if let (true, Some(original)) = (compare, &step.screenshot) {
+            let comparison = general_purpose::STANDARD
+                .decode(original)
+                .map_err(|e| format!("Invalid recorded screenshot: {}", e))
+                .and_then(|original| {
+                    let replayed = crate::capture_screenshot()?;
+                    compare_screenshots(&original, &replayed, step.index)
+                });
+            match comparison {
+                Ok(similarity) => {
+                    result.screenshot_similarity = Some(similarity);
+                    result.screenshot_matches = Some(similarity >= threshold);
+                }
+                Err(e) => result.error = Some(e),
+            }
+        }
+
+        results.push(result);
+    }
+
+    let mismatches = results
+        .iter()
+        .filter(|r| {
+            r.error.is_some() || r.status_matches == Some(false) || r.screenshot_matches == Some(false)
+        })
+        .count();
+    let report = ReplayReport {
+        path: req.path.clone(),
+        dry_run,
+        speed,
+        total_steps: results.len(),
+        mismatches,
+        steps: results,
+    };
+
+    HttpResponse::Ok().json(ActionResponse {
+        r#type: String::from("success"),
+        media_type: String::from("application/json"),
+        data: serde_json::to_string(&report).unwrap_or_default(),
+    })
+}
@@ -0,0 +1,198 @@
+use actix_web::http::StatusCode;
+use actix_web::HttpResponse;
+use regex::Regex;
+use serde::Serialize;
+use std::fs;
+use std::path::Path;
+
+use crate::encoding::{self, TextFormat};
+use crate::{fileio, history, sandbox, search, view, ActionResponse, EditRequest};
+
+#[derive(Debug, Serialize)]
+pub struct FileReplacement {
+    pub path: String,
+    pub replacements: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ReplaceReport {
+    /// False for a dry run.
+    pub applied: bool,
+    pub files_searched: usize,
+    pub replacements: usize,
+    pub files: Vec<FileReplacement>,
+    /// Unified diff of every change, returned by dry runs.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub diff: Option<String>,
+}
+
+/// A file the pattern matched in, with its content after the replacement.
+struct ChangedFile {
+    path: String,
+    original: Vec<u8>,
+    text: String,
+    content: String,
+    format: TextFormat,
+    replacements: usize,
+}
+
+/// Reads and decodes a file to replace in; `None` for files that are too
+/// large or not text.
+fn read_text(path: &Path) -> Option<(Vec<u8>, String, TextFormat)> {
+    if fs::metadata(path).ok()?.len() > search::MAX_FILE_BYTES {
+        return None;
+    }
+    let bytes = fs::read(path).ok()?;
+    let (text, format) = encoding::decode(&bytes).ok()?;
+    Some((bytes, text, format))
+}
+
+/// Finds the files below `root` the regex matches in and computes their new
+/// content. Returns them with the number of files searched.
+fn find_changes(req: &EditRequest, regex: &Regex, replacement: &str) -> Result<(Vec<ChangedFile>, usize), String> {
+    let mut changed = Vec::new();
+    let mut files_searched = 0;
+    let globs = req.globs.as_deref().unwrap_or_default();
+    let walker = search::walk(
+        Path::new(&req.path),
+        globs,
+        req.show_hidden.unwrap_or(false),
+        req.no_ignore.unwrap_or(false),
+    )?;
+    for entry in walker {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(e) => {
+                log::debug!("Skipping entry in replace_in_files: {}", e);
+                continue;
+            }
+        };
+        if !entry.file_type().is_some_and(|file_type| file_type.is_file()) {
+            continue;
+        }
+        let Some((original, text, format)) = read_text(entry.path()) else {
+            continue;
+        };
+        files_searched += 1;
+        let replacements = regex.find_iter(&text).count();
+        if replacements == 0 {
+            continue;
+        }
+        let content = regex.replace_all(&text, replacement).into_owned();
+        if content != text {
+            changed.push(ChangedFile {
+                path: entry.path().to_string_lossy().to_string(),
+                original,
+                text,
+                content,
+                format,
+                replacements,
+            });
+        }
+    }
+    Ok((changed, files_searched))
+}
+
+/// Writes every changed file. If a write fails, the files written before it
+/// are restored.
+fn write_all(changed: &[ChangedFile], encoded: &[Vec<u8>]) -> Result<(), String> {
+    for (index, (file, content)) in changed.iter().zip(encoded).enumerate() {
+        if let Err(e) = fileio::write_atomic(&file.path, content) {
+            log::error!("Failed to write {} in replace_in_files, rolling back: {}", file.path, e);
+            for written in &changed[..index] {
+                if let Err(e) = history::restore(&written.path, &Some(written.original.clone())) {
+                    log::error!("Failed to roll back {}: {}", written.path, e);
+                }
+            }
+            return Err(format!("Failed to write {}: {}", file.path, e));
+        }
+    }
+    Ok(())
+}
+
+fn error_response(status: StatusCode, e: String) -> HttpResponse {
+    HttpResponse::build(status).json(ActionResponse {
+        r#type: String::from("error"),
+        media_type: String::from("text/plain"),
+        data: e,
+    })
+}
+
+/// Replaces the matches of the regex `pattern` with `replacement` (which may
+/// refer to capture groups as `$1` or `${name}`) in the files below `path`
+/// selected by `globs`, skipping hidden and ignored files unless `show_hidden`
+/// or `no_ignore` is set. A dry run only returns the diff; otherwise either
+/// every file is written or none, and each gets its own undo entry.
+pub fn handle_replace_in_files(req: &EditRequest) -> HttpResponse {
+    let (Some(pattern), Some(replacement)) = (&req.pattern, &req.replacement) else {
+        return error_response(
+            StatusCode::BAD_REQUEST,
+            String::from("pattern and replacement are required for replace_in_files action"),
+        );
+    };
+    let regex = match Regex::new(pattern) {
+        Ok(regex) => regex,
+        Err(e) => return error_response(StatusCode::BAD_REQUEST, format!("Invalid pattern: {}", e)),
+    };
+    let (changed, files_searched) = match find_changes(req, &regex, replacement) {
+        Ok(found) => found,
+        Err(e) => return error_response(StatusCode::BAD_REQUEST, e),
+    };
+    if changed.is_empty() {
+        let mut skipped = Vec::new();
+        if !req.show_hidden.unwrap_or(false) {
+            skipped.push("hidden files (set show_hidden to include them)");
+        }
+        if !req.no_ignore.unwrap_or(false) {
+            skipped.push("ignored files (set no_ignore to include them)");
+        }
+        let mut message = format!("No replacement was performed, the pattern changed none of the {} files searched", files_searched);
+        if !skipped.is_empty() {
+            message.push_str(&format!("; skipped {}", skipped.join(" and ")));
+        }
+        return error_response(StatusCode::BAD_REQUEST, message);
+    }
+
+    let mut encoded = Vec::new();
+    for file in &changed {
+        if let Err(e) = sandbox::check(&file.path, sandbox::Access::Write) {
+            return error_response(StatusCode::FORBIDDEN, e);
+        }
+        match encoding::encode(&file.content, &file.format) {
+            Ok(content) => encoded.push(content),
+            Err(e) => return error_response(StatusCode::BAD_REQUEST, format!("Cannot replace in {}: {}", file.path, e)),
+        }
+    }
+
+    let dry_run = req.dry_run.unwrap_or(false);
+    let mut report = ReplaceReport {
+        applied: !dry_run,
+        files_searched,
+        replacements: changed.iter().map(|file| file.replacements).sum(),
+        files: changed
+            .iter()
+            .map(|file| FileReplacement {
+                path: file.path.clone(),
+                replacements: file.replacements,
+            })
+            .collect(),
+        diff: None,
+    };
+    if dry_run {
+        report.diff = Some(changed.iter().map(|file| view::file_diff(&file.path, &file.text, &file.content)).collect());
+    } else {
+        if let Err(e) = write_all(&changed, &encoded) {
+            return error_response(StatusCode::INTERNAL_SERVER_ERROR, e);
+        }
+        for file in changed {
+            history::record(&file.path, "replace_in_files", Some(file.original));
+        }
+        log::info!("Replaced {} matches in {} files", report.replacements, report.files.len());
+    }
+
+    HttpResponse::Ok().json(ActionResponse {
+        r#type: String::from("success"),
+        media_type: String::from("application/json"),
+        data: serde_json::to_string(&report).unwrap_or_default(),
+    })
+}
@@ -0,0 +1,129 @@
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use std::sync::OnceLock;
+
+use crate::fileio;
+
+/// Paths that may be accessed at all, unless configured otherwise.
+const DEFAULT_ALLOWED: &str = "/";
+/// The server's own source (rebuilt by `cargo watch` on change) and the
+/// supervisor configuration must not be edited by agents.
+const DEFAULT_READ_ONLY: &str = "/app/api-server,/etc/supervisor";
+
+static POLICY: OnceLock<Policy> = OnceLock::new();
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Access {
+    Read,
+    Write,
+}
+
+/// Which paths the edit endpoint and bash sessions may use. Configured with
+/// comma separated path prefixes in `CONSOLEY_SANDBOX_ALLOW`,
+/// `CONSOLEY_SANDBOX_DENY` and `CONSOLEY_SANDBOX_READ_ONLY`.
+#[derive(Debug)]
+struct Policy {
+    allowed: Vec<PathBuf>,
+    denied: Vec<PathBuf>,
+    read_only: Vec<PathBuf>,
+}
+
+fn prefixes(var: &str, default: &str) -> Vec<PathBuf> {
+    std::env::var(var)
+        .unwrap_or_else(|_| default.to_string())
+        .split(',')
+        .map(str::trim)
+        .filter(|prefix| !prefix.is_empty())
+        .filter_map(|prefix| match canonicalize(Path::new(prefix)) {
+            Ok(path) => Some(path),
+            Err(e) => {
+                log::warn!("Ignoring sandbox prefix {} from {}: {}", prefix, var, e);
+                None
+            }
+        })
+        .collect()
+}
+
+fn policy() -> &'static Policy {
+    POLICY.get_or_init(|| {
+        let policy = Policy {
+            allowed: prefixes("CONSOLEY_SANDBOX_ALLOW", DEFAULT_ALLOWED),
+            denied: prefixes("CONSOLEY_SANDBOX_DENY", ""),
+            read_only: prefixes("CONSOLEY_SANDBOX_READ_ONLY", DEFAULT_READ_ONLY),
+        };
+        log::info!("Sandbox policy: {:?}", policy);
+        policy
+    })
+}
+
+/// Resolves `path` to the location it really refers to: symlinks are followed
+/// (including a dangling link as the last component) and `.` and `..` are
+/// applied. Parts that do not exist yet are resolved lexically.
+pub fn canonicalize(path: &Path) -> Result<PathBuf, String> {
+    if !path.is_absolute() {
+        return Err(format!("{} is not an absolute path", path.display()));
+    }
+    let path = fileio::resolve_symlinks(path)?;
+
+    // Canonicalize the longest existing ancestor and apply the rest lexically
+    let mut missing = Vec::new();
+    let mut existing = path.as_path();
+    let mut resolved = loop {
+        if let Ok(base) = fs::canonicalize(existing) {
+            break base;
+        }
+        match (existing.parent(), existing.components().next_back()) {
+            (Some(parent), Some(last)) => {
+                missing.push(last);
+                existing = parent;
+            }
+            _ => break PathBuf::from("/"),
+        }
+    };
+    for component in missing.into_iter().rev() {
+        match component {
+            Component::ParentDir => {
+                resolved.pop();
+            }
+            Component::Normal(name) => resolved.push(name),
+            Component::RootDir | Component::CurDir | Component::Prefix(_) => {}
+        }
+    }
+    Ok(resolved)
+}
+
+/// Checks `path` against the policy and returns its canonical form, or a
+/// "forbidden" error explaining which rule rejected it.
+pub fn check(path: &str, access: Access) -> Result<PathBuf, String> {
+    let resolved = canonicalize(Path::new(path)).map_err(|e| format!("Access to {} is forbidden: {}", path, e))?;
+    let policy = policy();
+    let shown = if resolved == Path::new(path) {
+        path.to_string()
+    } else {
+        format!("{} (resolves to {})", path, resolved.display())
+    };
+
+    if let Some(prefix) = policy.denied.iter().find(|prefix| resolved.starts_with(prefix)) {
+        return Err(format!("Access to {} is forbidden: {} is denied by the sandbox policy", shown, prefix.display()));
+    }
+    if !policy.allowed.iter().any(|prefix| resolved.starts_with(prefix)) {
+        return Err(format!("Access to {} is forbidden: it is outside the allowed directories", shown));
+    }
+    if access == Access::Write {
+        if let Some(prefix) = policy.read_only.iter().find(|prefix| resolved.starts_with(prefix)) {
+            return Err(format!("Access to {} is forbidden: {} is read-only", shown, prefix.display()));
+        }
+    }
+    Ok(resolved)
+}
+
+/// Directory new bash sessions start in: the home directory if the policy
+/// allows writing to it, otherwise the first allowed directory.
+pub fn default_dir() -> PathBuf {
+    std::env::var("HOME")
+        .ok()
+        .filter(|home| check(home, Access::Write).is_ok())
+        .map(PathBuf::from)
+        .or_else(|| policy().allowed.first().cloned())
+        .unwrap_or_else(|| PathBuf::from("/"))
+}
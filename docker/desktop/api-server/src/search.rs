@@ -0,0 +1,257 @@
+use actix_web::{web, HttpResponse};
+use ignore::overrides::OverrideBuilder;
+use ignore::{Walk, WalkBuilder};
+use regex::{Regex, RegexBuilder};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::Read;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use crate::{sandbox, ActionResponse};
+
+const DEFAULT_MAX_RESULTS: usize = 200;
+const MAX_RESULTS_LIMIT: usize = 5000;
+const DEFAULT_TIMEOUT_MS: u64 = 10_000;
+const MAX_TIMEOUT_MS: u64 = 60_000;
+const MAX_CONTEXT_LINES: usize = 10;
+/// Files larger than this are not searched for content.
+pub const MAX_FILE_BYTES: u64 = 10 * 1024 * 1024;
+/// Files with a NUL byte in this many leading bytes are treated as binary.
+const BINARY_CHECK_BYTES: usize = 8192;
+/// Longer lines are cut in results, e.g. for minified files.
+const MAX_LINE_CHARS: usize = 500;
+
+#[derive(Debug, Deserialize)]
+pub struct SearchRequest {
+    /// Directory to search in.
+    pub path: String,
+    /// Regular expression to search file contents for. Without it the
+    /// matching files are listed.
+    pub pattern: Option<String>,
+    /// Globs selecting files, relative to `path`. A leading `!` excludes.
+    pub globs: Option<Vec<String>>,
+    /// Treat `pattern` as a plain string.
+    pub literal: Option<bool>,
+    pub case_insensitive: Option<bool>,
+    /// Lines of context before and after each match.
+    pub context: Option<usize>,
+    /// Include hidden files and directories.
+    pub hidden: Option<bool>,
+    /// Also search files excluded by .gitignore and .ignore files.
+    pub no_ignore: Option<bool>,
+    pub max_results: Option<usize>,
+    pub timeout_ms: Option<u64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SearchMatch {
+    pub path: String,
+    /// 1-based line number.
+    pub line: usize,
+    /// 1-based character column of the first match on the line.
+    pub column: usize,
+    pub text: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub before: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub after: Vec<String>,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct SearchReport {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub files: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub matches: Option<Vec<SearchMatch>>,
+    pub files_searched: usize,
+    /// More results exist than `max_results`.
+    pub truncated: bool,
+    /// The search stopped at the timeout; results are incomplete.
+    pub timed_out: bool,
+}
+
+fn build_regex(req: &SearchRequest, pattern: &str) -> Result<Regex, String> {
+    let pattern = if req.literal.unwrap_or(false) {
+        regex::escape(pattern)
+    } else {
+        pattern.to_string()
+    };
+    RegexBuilder::new(&pattern)
+        .case_insensitive(req.case_insensitive.unwrap_or(false))
+        .build()
+        .map_err(|e| format!("Invalid pattern: {}", e))
+}
+
+fn truncate_line(line: &str) -> String {
+    match line.char_indices().nth(MAX_LINE_CHARS) {
+        Some((end, _)) => format!("{}…", &line[..end]),
+        None => line.to_string(),
+    }
+}
+
+/// Reads a file for searching, or `None` if it is too large or binary.
+fn read_text(path: &Path) -> Option<String> {
+    let mut file = fs::File::open(path).ok()?;
+    if file.metadata().ok()?.len() > MAX_FILE_BYTES {
+        return None;
+    }
+    let mut content = Vec::new();
+    file.read_to_end(&mut content).ok()?;
+    if content[..content.len().min(BINARY_CHECK_BYTES)].contains(&0) {
+        return None;
+    }
+    Some(String::from_utf8_lossy(&content).into_owned())
+}
+
+/// Adds the matching lines of `content` to `matches`. Returns false once
+/// `limit` is exceeded.
+fn search_content(path: &str, content: &str, regex: &Regex, context: usize, limit: usize, matches: &mut Vec<SearchMatch>) -> bool {
+    let lines: Vec<&str> = content.lines().collect();
+    for (index, line) in lines.iter().enumerate() {
+        let Some(found) = regex.find(line) else {
+            continue;
+        };
+        if matches.len() == limit {
+            return false;
+        }
+        let before = index.saturating_sub(context);
+        let after = (index + 1 + context).min(lines.len());
+        matches.push(SearchMatch {
+            path: path.to_string(),
+            line: index + 1,
+            column: line[..found.start()].chars().count() + 1,
+            text: truncate_line(line),
+            before: lines[before..index].iter().map(|line| truncate_line(line)).collect(),
+            after: lines[index + 1..after].iter().map(|line| truncate_line(line)).collect(),
+        });
+    }
+    true
+}
+
+/// Walks `root` in name order, skipping what `globs` exclude, hidden files
+/// unless `hidden`, files ignored by .gitignore and .ignore files unless
+/// `no_ignore`, and paths the sandbox policy does not let us read.
+pub fn walk(root: &Path, globs: &[String], hidden: bool, no_ignore: bool) -> Result<Walk, String> {
+    let mut overrides = OverrideBuilder::new(root);
+    for glob in globs {
+        overrides.add(glob).map_err(|e| format!("Invalid glob {}: {}", glob, e))?;
+    }
+    let overrides = overrides.build().map_err(|e| format!("Invalid globs: {}", e))?;
+    let respect_ignore = !no_ignore;
+    Ok(WalkBuilder::new(root)
+        .hidden(!hidden)
+        .git_ignore(respect_ignore)
+        .git_exclude(respect_ignore)
+        .git_global(respect_ignore)
+        .ignore(respect_ignore)
+        .parents(respect_ignore)
+        // .gitignore files apply outside of git repositories too
+        .require_git(false)
+        .follow_links(false)
+        .overrides(overrides)
+        .sort_by_file_name(|a, b| a.cmp(b))
+        .filter_entry(|entry| sandbox::check(&entry.path().to_string_lossy(), sandbox::Access::Read).is_ok())
+        .build())
+}
+
+fn search(req: &SearchRequest, root: &Path, regex: Option<&Regex>) -> Result<SearchReport, String> {
+    let limit = req.max_results.unwrap_or(DEFAULT_MAX_RESULTS).clamp(1, MAX_RESULTS_LIMIT);
+    let timeout = Duration::from_millis(req.timeout_ms.unwrap_or(DEFAULT_TIMEOUT_MS).min(MAX_TIMEOUT_MS));
+    let context = req.context.unwrap_or(0).min(MAX_CONTEXT_LINES);
+    let deadline = Instant::now() + timeout;
+
+    let walker = walk(root, req.globs.as_deref().unwrap_or_default(), req.hidden.unwrap_or(false), req.no_ignore.unwrap_or(false))?;
+
+    let mut report = SearchReport::default();
+    let mut files = Vec::new();
+    let mut matches = Vec::new();
+    for entry in walker {
+        if Instant::now() > deadline {
+            report.timed_out = true;
+            break;
+        }
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(e) => {
+                log::debug!("Skipping entry in search: {}", e);
+                continue;
+            }
+        };
+        if !entry.file_type().is_some_and(|file_type| file_type.is_file()) {
+            continue;
+        }
+        let path = entry.path().to_string_lossy().to_string();
+        report.files_searched += 1;
+
+        let Some(regex) = regex else {
+            if files.len() == limit {
+                report.truncated = true;
+                break;
+            }
+            files.push(path);
+            continue;
+        };
+        let Some(content) = read_text(entry.path()) else {
+            continue;
+        };
+        if !search_content(&path, &content, regex, context, limit, &mut matches) {
+            report.truncated = true;
+            break;
+        }
+    }
+
+    if regex.is_some() {
+        report.matches = Some(matches);
+    } else {
+        report.files = Some(files);
+    }
+    Ok(report)
+}
+
+/// Lists the files below `path` matching the globs, or searches them for
+/// `pattern`. Files ignored by git, hidden files and paths the sandbox policy
+/// forbids are skipped.
+pub async fn handle_search(req: web::Json<SearchRequest>) -> HttpResponse {
+    let root = match sandbox::check(&req.path, sandbox::Access::Read) {
+        Ok(root) => root,
+        Err(e) => {
+            return HttpResponse::Forbidden().json(ActionResponse {
+                r#type: String::from("error"),
+                media_type: String::from("text/plain"),
+                data: e,
+            })
+        }
+    };
+    if !root.is_dir() {
+        return HttpResponse::BadRequest().json(ActionResponse {
+            r#type: String::from("error"),
+            media_type: String::from("text/plain"),
+            data: format!("{} is not a directory", req.path),
+        });
+    }
+
+    let result = web::block(move || {
+        let regex = req.pattern.as_deref().map(|pattern| build_regex(&req, pattern)).transpose()?;
+        search(&req, &root, regex.as_ref())
+    })
+    .await;
+
+    match result {
+        Ok(Ok(report)) => HttpResponse::Ok().json(ActionResponse {
+            r#type: String::from("success"),
+            media_type: String::from("application/json"),
+            data: serde_json::to_string(&report).unwrap_or_default(),
+        }),
+        Ok(Err(e)) => HttpResponse::BadRequest().json(ActionResponse {
+            r#type: String::from("error"),
+            media_type: String::from("text/plain"),
+            data: e,
+        }),
+        Err(e) => HttpResponse::InternalServerError().json(ActionResponse {
+            r#type: String::from("error"),
+            media_type: String::from("text/plain"),
+            data: e.to_string(),
+        }),
+    }
+}
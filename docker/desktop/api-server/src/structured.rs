@@ -0,0 +1,556 @@
+use actix_web::http::StatusCode;
+use actix_web::HttpResponse;
+use serde::Serialize;
+use serde_json::Value as JsonValue;
+use serde_yaml::Value as YamlValue;
+use std::fs;
+use std::path::Path;
+use toml_edit::{DocumentMut, InlineTable, Item, Table, Value as TomlValue};
+
+use crate::{conflict, edit_result_message, encoding, fileio, history, with_format_note, ActionResponse, EditCommand, EditRequest};
+
+type KeyError = (StatusCode, String);
+
+fn invalid(e: String) -> KeyError {
+    (StatusCode::BAD_REQUEST, e)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum DocumentFormat {
+    Json,
+    Yaml,
+    Toml,
+}
+
+impl DocumentFormat {
+    fn from_path(path: &str) -> Option<DocumentFormat> {
+        let extension = Path::new(path).extension()?.to_str()?.to_ascii_lowercase();
+        match extension.as_str() {
+            "json" => Some(DocumentFormat::Json),
+            "yaml" | "yml" => Some(DocumentFormat::Yaml),
+            "toml" => Some(DocumentFormat::Toml),
+            _ => None,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            DocumentFormat::Json => "JSON",
+            DocumentFormat::Yaml => "YAML",
+            DocumentFormat::Toml => "TOML",
+        }
+    }
+}
+
+/// A key given as JSON Pointer (`/a/b/0`) or dotted path (`a.b.0`). Numeric
+/// segments index arrays; `-` refers to the end of an array, to append to.
+struct KeyPath {
+    segments: Vec<String>,
+    pointer: bool,
+}
+
+impl KeyPath {
+    fn parse(key: &str) -> Result<KeyPath, String> {
+        if key.is_empty() {
+            return Ok(KeyPath { segments: Vec::new(), pointer: true });
+        }
+        if let Some(pointer) = key.strip_prefix('/') {
+            let segments = pointer.split('/').map(|segment| segment.replace("~1", "/").replace("~0", "~")).collect();
+            return Ok(KeyPath { segments, pointer: true });
+        }
+        let segments: Vec<String> = key.split('.').map(String::from).collect();
+        if segments.iter().any(String::is_empty) {
+            return Err(format!("Invalid key {:?}: empty segment in dotted path", key));
+        }
+        Ok(KeyPath { segments, pointer: false })
+    }
+
+    /// Shows the first `depth` segments the way the key was given.
+    fn display(&self, depth: usize) -> String {
+        let segments = &self.segments[..depth];
+        if segments.is_empty() {
+            String::from("the document root")
+        } else if self.pointer {
+            segments.iter().map(|segment| format!("/{}", segment.replace('~', "~0").replace('/', "~1"))).collect()
+        } else {
+            segments.join(".")
+        }
+    }
+}
+
+/// Position `segment` refers to in an array of `len` items.
+fn array_index(segment: &str, len: usize) -> Result<usize, String> {
+    if segment == "-" {
+        return Ok(len);
+    }
+    if segment.is_empty() || !segment.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(format!("{:?} is not an array index", segment));
+    }
+    segment.parse().map_err(|_| format!("{:?} is not an array index", segment))
+}
+
+/// Navigation and changes shared by the parsed JSON, YAML and TOML documents.
+trait Tree: Sized {
+    fn from_json(value: &JsonValue) -> Result<Self, String>;
+    fn to_json(&self) -> Result<JsonValue, String>;
+    fn empty_map() -> Self;
+    fn is_map(&self) -> bool;
+    /// The child at `segment`, `None` if it does not exist. Fails if `self`
+    /// is not a map or array, or `segment` is not an index into an array.
+    fn child(&mut self, segment: &str) -> Result<Option<&mut Self>, String>;
+    /// Sets the child at `segment`; an index one past the end appends.
+    fn insert(&mut self, segment: &str, value: Self) -> Result<(), String>;
+    /// Removes the child at `segment`. Returns false if it does not exist.
+    fn remove(&mut self, segment: &str) -> Result<bool, String>;
+}
+
+fn set_in_array<T>(items: &mut Vec<T>, segment: &str, value: T) -> Result<(), String> {
+    let index = array_index(segment, items.len())?;
+    match index.cmp(&items.len()) {
+        std::cmp::Ordering::Less => items[index] = value,
+        std::cmp::Ordering::Equal => items.push(value),
+        std::cmp::Ordering::Greater => return Err(format!("index {} is past the end of the array ({} items)", index, items.len())),
+    }
+    Ok(())
+}
+
+fn remove_from_array<T>(items: &mut Vec<T>, segment: &str) -> Result<bool, String> {
+    let index = array_index(segment, items.len())?;
+    Ok(index < items.len() && {
+        items.remove(index);
+        true
+    })
+}
+
+impl Tree for JsonValue {
+    fn from_json(value: &JsonValue) -> Result<Self, String> {
+        Ok(value.clone())
+    }
+
+    fn to_json(&self) -> Result<JsonValue, String> {
+        Ok(self.clone())
+    }
+
+    fn empty_map() -> Self {
+        JsonValue::Object(serde_json::Map::new())
+    }
+
+    fn is_map(&self) -> bool {
+        self.is_object()
+    }
+
+    fn child(&mut self, segment: &str) -> Result<Option<&mut Self>, String> {
+        match self {
+            JsonValue::Object(map) => Ok(map.get_mut(segment)),
+            JsonValue::Array(items) => {
+                let index = array_index(segment, items.len())?;
+                Ok(items.get_mut(index))
+            }
+            _ => Err(String::from("it is not an object or array")),
+        }
+    }
+
+    fn insert(&mut self, segment: &str, value: Self) -> Result<(), String> {
+        match self {
+            JsonValue::Object(map) => {
+                map.insert(segment.to_string(), value);
+                Ok(())
+            }
+            JsonValue::Array(items) => set_in_array(items, segment, value),
+            _ => Err(String::from("it is not an object or array")),
+        }
+    }
+
+    fn remove(&mut self, segment: &str) -> Result<bool, String> {
+        match self {
+            JsonValue::Object(map) => Ok(map.shift_remove(segment).is_some()),
+            JsonValue::Array(items) => remove_from_array(items, segment),
+            _ => Err(String::from("it is not an object or array")),
+        }
+    }
+}
+
+impl Tree for YamlValue {
+    fn from_json(value: &JsonValue) -> Result<Self, String> {
+        serde_yaml::to_value(value).map_err(|e| e.to_string())
+    }
+
+    fn to_json(&self) -> Result<JsonValue, String> {
+        serde_json::to_value(self).map_err(|e| format!("The value cannot be shown as JSON: {}", e))
+    }
+
+    fn empty_map() -> Self {
+        YamlValue::Mapping(serde_yaml::Mapping::new())
+    }
+
+    fn is_map(&self) -> bool {
+        self.is_mapping()
+    }
+
+    fn child(&mut self, segment: &str) -> Result<Option<&mut Self>, String> {
+        match self {
+            YamlValue::Mapping(map) => Ok(map.get_mut(segment)),
+            YamlValue::Sequence(items) => {
+                let index = array_index(segment, items.len())?;
+                Ok(items.get_mut(index))
+            }
+            _ => Err(String::from("it is not a mapping or sequence")),
+        }
+    }
+
+    fn insert(&mut self, segment: &str, value: Self) -> Result<(), String> {
+        match self {
+            YamlValue::Mapping(map) => {
+                map.insert(YamlValue::String(segment.to_string()), value);
+                Ok(())
+            }
+            YamlValue::Sequence(items) => set_in_array(items, segment, value),
+            _ => Err(String::from("it is not a mapping or sequence")),
+        }
+    }
+
+    fn remove(&mut self, segment: &str) -> Result<bool, String> {
+        match self {
+            YamlValue::Mapping(map) => Ok(map.shift_remove(segment).is_some()),
+            YamlValue::Sequence(items) => remove_from_array(items, segment),
+            _ => Err(String::from("it is not a mapping or sequence")),
+        }
+    }
+}
+
+fn json_to_toml(value: &JsonValue) -> Result<TomlValue, String> {
+    match value {
+        JsonValue::Null => Err(String::from("TOML has no null value")),
+        JsonValue::Bool(b) => Ok(TomlValue::from(*b)),
+        JsonValue::Number(n) => n
+            .as_i64()
+            .map(TomlValue::from)
+            .or_else(|| n.as_f64().map(TomlValue::from))
+            .ok_or_else(|| format!("{} does not fit in a TOML number", n)),
+        JsonValue::String(s) => Ok(TomlValue::from(s.as_str())),
+        JsonValue::Array(items) => items.iter().map(json_to_toml).collect::<Result<toml_edit::Array, _>>().map(TomlValue::Array),
+        JsonValue::Object(map) => map
+            .iter()
+            .map(|(key, value)| json_to_toml(value).map(|value| (key.as_str(), value)))
+            .collect::<Result<InlineTable, _>>()
+            .map(TomlValue::InlineTable),
+    }
+}
+
+fn toml_to_json(value: &TomlValue) -> JsonValue {
+    match value {
+        TomlValue::String(s) => JsonValue::from(s.value().as_str()),
+        TomlValue::Integer(i) => JsonValue::from(*i.value()),
+        TomlValue::Float(f) => JsonValue::from(*f.value()),
+        TomlValue::Boolean(b) => JsonValue::from(*b.value()),
+        TomlValue::Datetime(d) => JsonValue::from(d.value().to_string()),
+        TomlValue::Array(items) => items.iter().map(toml_to_json).collect(),
+        TomlValue::InlineTable(table) => table.iter().map(|(key, value)| (key.to_string(), toml_to_json(value))).collect(),
+    }
+}
+
+fn toml_item_to_json(item: &Item) -> JsonValue {
+    match item {
+        Item::None => JsonValue::Null,
+        Item::Value(value) => toml_to_json(value),
+        Item::Table(table) => toml_table_to_json(table),
+        Item::ArrayOfTables(tables) => tables.iter().map(toml_table_to_json).collect(),
+    }
+}
+
+fn toml_table_to_json(table: &Table) -> JsonValue {
+    table.iter().map(|(key, item)| (key.to_string(), toml_item_to_json(item))).collect()
+}
+
+impl Tree for Item {
+    fn from_json(value: &JsonValue) -> Result<Self, String> {
+        json_to_toml(value).map(Item::Value)
+    }
+
+    fn to_json(&self) -> Result<JsonValue, String> {
+        Ok(toml_item_to_json(self))
+    }
+
+    fn empty_map() -> Self {
+        // Only gets a header once it has values of its own
+        let mut table = Table::new();
+        table.set_implicit(true);
+        Item::Table(table)
+    }
+
+    fn is_map(&self) -> bool {
+        self.is_table_like()
+    }
+
+    fn child(&mut self, segment: &str) -> Result<Option<&mut Self>, String> {
+        let len = match self {
+            Item::ArrayOfTables(tables) => Some(tables.len()),
+            Item::Value(TomlValue::Array(items)) => Some(items.len()),
+            _ => None,
+        };
+        if let Some(len) = len {
+            let index = array_index(segment, len)?;
+            return Ok(self.get_mut(index));
+        }
+        match self.as_table_like_mut() {
+            Some(table) => Ok(table.get_mut(segment)),
+            None => Err(String::from("it is not a table or array")),
+        }
+    }
+
+    fn insert(&mut self, segment: &str, value: Self) -> Result<(), String> {
+        match self {
+            Item::ArrayOfTables(tables) => {
+                let table = value.into_table().map_err(|_| String::from("an array of tables can only hold tables"))?;
+                let (index, len) = (array_index(segment, tables.len())?, tables.len());
+                match tables.get_mut(index) {
+                    Some(existing) => *existing = table,
+                    None if index == len => tables.push(table),
+                    None => return Err(format!("index {} is past the end of the array ({} items)", index, len)),
+                }
+                Ok(())
+            }
+            Item::Value(TomlValue::Array(items)) => {
+                let value = value.into_value().map_err(|_| String::from("the value cannot be put in an array"))?;
+                let index = array_index(segment, items.len())?;
+                match index.cmp(&items.len()) {
+                    // Keeps the comments and spacing around the replaced value
+                    std::cmp::Ordering::Less => drop(items.replace(index, value)),
+                    std::cmp::Ordering::Equal => items.push(value),
+                    std::cmp::Ordering::Greater => {
+                        return Err(format!("index {} is past the end of the array ({} items)", index, items.len()))
+                    }
+                }
+                Ok(())
+            }
+            Item::Table(table) => {
+                // Objects set in a standard table get a [section] of their own
+                let mut value = match value {
+                    Item::Value(TomlValue::InlineTable(inline)) => Item::Table(inline.into_table()),
+                    value => value,
+                };
+                if let (Some(Item::Value(old)), Item::Value(new)) = (table.get(segment), &mut value) {
+                    *new.decor_mut() = old.decor().clone();
+                }
+                table.insert(segment, value);
+                Ok(())
+            }
+            Item::Value(TomlValue::InlineTable(table)) => {
+                let mut value = value.into_value().map_err(|_| String::from("the value cannot be put in an inline table"))?;
+                if let Some(old) = table.get(segment) {
+                    *value.decor_mut() = old.decor().clone();
+                }
+                table.insert(segment, value);
+                Ok(())
+            }
+            _ => Err(String::from("it is not a table or array")),
+        }
+    }
+
+    fn remove(&mut self, segment: &str) -> Result<bool, String> {
+        match self {
+            Item::ArrayOfTables(tables) => {
+                let index = array_index(segment, tables.len())?;
+                Ok(index < tables.len() && {
+                    tables.remove(index);
+                    true
+                })
+            }
+            Item::Value(TomlValue::Array(items)) => {
+                let index = array_index(segment, items.len())?;
+                Ok(index < items.len() && {
+                    items.remove(index);
+                    true
+                })
+            }
+            _ => match self.as_table_like_mut() {
+                Some(table) => Ok(table.remove(segment).is_some()),
+                None => Err(String::from("it is not a table or array")),
+            },
+        }
+    }
+}
+
+enum Operation<'a> {
+    Get,
+    Set(&'a JsonValue),
+    Delete,
+}
+
+/// Runs `operation` on the key `path` of `root`. Maps missing on the way to
+/// a key being set are created. Returns the value for `Get`.
+fn apply<T: Tree>(root: &mut T, path: &KeyPath, operation: &Operation) -> Result<Option<JsonValue>, KeyError> {
+    let Some((last, parents)) = path.segments.split_last() else {
+        return match operation {
+            Operation::Get => root.to_json().map(Some).map_err(invalid),
+            _ => Err(invalid(String::from("key must not be empty for set_key and delete_key"))),
+        };
+    };
+    let create = matches!(operation, Operation::Set(_));
+    let cannot_resolve = |depth: usize, e: String| invalid(format!("Cannot resolve {}: {}", path.display(depth), e));
+    let not_found = |depth: usize| (StatusCode::NOT_FOUND, format!("Key {} not found", path.display(depth)));
+
+    let mut node = root;
+    for (depth, segment) in parents.iter().enumerate() {
+        if create && node.is_map() && node.child(segment).map_err(|e| cannot_resolve(depth + 1, e))?.is_none() {
+            node.insert(segment, T::empty_map()).map_err(|e| cannot_resolve(depth + 1, e))?;
+        }
+        node = node
+            .child(segment)
+            .map_err(|e| cannot_resolve(depth + 1, e))?
+            .ok_or_else(|| not_found(depth + 1))?;
+    }
+
+    let depth = path.segments.len();
+    match operation {
+        Operation::Get => {
+            let value = node.child(last).map_err(|e| cannot_resolve(depth, e))?.ok_or_else(|| not_found(depth))?;
+            value.to_json().map(Some).map_err(invalid)
+        }
+        Operation::Set(value) => {
+            let value = T::from_json(value).map_err(|e| invalid(format!("Invalid value for {}: {}", path.display(depth), e)))?;
+            node.insert(last, value).map_err(|e| cannot_resolve(depth, e))?;
+            Ok(None)
+        }
+        Operation::Delete => {
+            if !node.remove(last).map_err(|e| cannot_resolve(depth, e))? {
+                return Err(not_found(depth));
+            }
+            Ok(None)
+        }
+    }
+}
+
+/// Serializes JSON with the indentation of `original`, compact if it was on
+/// one line, and with its trailing newline.
+pub fn json_text(value: &JsonValue, original: &str) -> Result<String, String> {
+    let indent = original.lines().skip(1).find_map(|line| {
+        let trimmed = line.trim_start();
+        let width = line.len() - trimmed.len();
+        (width > 0 && !trimmed.is_empty()).then(|| &line[..width])
+    });
+    let mut text = if indent.is_none() && !original.trim().contains('\n') {
+        serde_json::to_string(value).map_err(|e| e.to_string())?
+    } else {
+        let formatter = serde_json::ser::PrettyFormatter::with_indent(indent.unwrap_or("  ").as_bytes());
+        let mut output = Vec::new();
+        let mut serializer = serde_json::Serializer::with_formatter(&mut output, formatter);
+        value.serialize(&mut serializer).map_err(|e| e.to_string())?;
+        String::from_utf8(output).map_err(|e| e.to_string())?
+    };
+    if original.ends_with('\n') {
+        text.push('\n');
+    }
+    Ok(text)
+}
+
+/// Parses `content`, runs `operation` and returns the value read by `Get`,
+/// or the new content of the file.
+fn edit_document(format: DocumentFormat, content: &str, path: &KeyPath, operation: &Operation) -> Result<(Option<JsonValue>, String), KeyError> {
+    let parse_error = |e: String| invalid(format!("Failed to parse the file as {}: {}", format.name(), e));
+    match format {
+        DocumentFormat::Json => {
+            let mut document: JsonValue = serde_json::from_str(content).map_err(|e| parse_error(e.to_string()))?;
+            let value = apply(&mut document, path, operation)?;
+            Ok((value, json_text(&document, content).map_err(invalid)?))
+        }
+        DocumentFormat::Yaml => {
+            let mut document: YamlValue = serde_yaml::from_str(content).map_err(|e| parse_error(e.to_string()))?;
+            if document.is_null() && matches!(operation, Operation::Set(_)) {
+                // An empty file
+                document = YamlValue::empty_map();
+            }
+            let value = apply(&mut document, path, operation)?;
+            Ok((value, serde_yaml::to_string(&document).map_err(|e| invalid(e.to_string()))?))
+        }
+        DocumentFormat::Toml => {
+            let mut document: DocumentMut = content.parse().map_err(|e: toml_edit::TomlError| parse_error(e.to_string()))?;
+            let value = apply(document.as_item_mut(), path, operation)?;
+            Ok((value, document.to_string()))
+        }
+    }
+}
+
+fn error_response(status: StatusCode, e: String) -> HttpResponse {
+    HttpResponse::build(status).json(ActionResponse {
+        r#type: String::from("error"),
+        media_type: String::from("text/plain"),
+        data: e,
+    })
+}
+
+/// Handles `get_key`, `set_key` and `delete_key` on JSON, YAML and TOML
+/// files, chosen by extension. TOML files keep their comments and layout;
+/// JSON keeps its key order and indentation, while YAML is written back in
+/// a normalized layout without comments.
+pub fn handle_key_command(req: &EditRequest, command: EditCommand) -> HttpResponse {
+    let Some(key) = &req.key else {
+        return error_response(StatusCode::BAD_REQUEST, format!("key is required for {} action", req.command));
+    };
+    let Some(format) = DocumentFormat::from_path(&req.path) else {
+        return error_response(
+            StatusCode::BAD_REQUEST,
+            format!("{} is not a JSON, YAML or TOML file (.json, .yaml, .yml or .toml)", req.path),
+        );
+    };
+    let path = match KeyPath::parse(key) {
+        Ok(path) => path,
+        Err(e) => return error_response(StatusCode::BAD_REQUEST, e),
+    };
+    let operation = match command {
+        EditCommand::GetKey => Operation::Get,
+        EditCommand::DeleteKey => Operation::Delete,
+        _ => match &req.value {
+            Some(value) => Operation::Set(value),
+            None => return error_response(StatusCode::BAD_REQUEST, String::from("value is required for set_key action")),
+        },
+    };
+
+    let bytes = match fs::read(&req.path) {
+        Ok(bytes) => bytes,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            return error_response(StatusCode::BAD_REQUEST, format!("File not found: {}", req.path))
+        },
+        Err(e) => return error_response(StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to read file: {}", e)),
+    };
+    let (content, text_format) = match encoding::decode(&bytes) {
+        Ok(decoded) => decoded,
+        Err(e) => return error_response(StatusCode::BAD_REQUEST, e),
+    };
+    let (value, new_content) = match edit_document(format, &content, &path, &operation) {
+        Ok(result) => result,
+        Err((status, e)) => return error_response(status, e),
+    };
+
+    if let Some(value) = value {
+        let response = HttpResponse::Ok().json(ActionResponse {
+            r#type: String::from("success"),
+            media_type: String::from("application/json"),
+            data: serde_json::to_string_pretty(&value).unwrap_or_default(),
+        });
+        return conflict::with_content_hash(response, &bytes);
+    }
+
+    let encoded = match encoding::encode(&new_content, &text_format) {
+        Ok(encoded) => encoded,
+        Err(e) => return error_response(StatusCode::BAD_REQUEST, e),
+    };
+    if let Err(e) = fileio::write_atomic(&req.path, &encoded) {
+        return error_response(StatusCode::INTERNAL_SERVER_ERROR, e);
+    }
+    let mut message = match operation {
+        Operation::Delete => format!("Key {} deleted", key),
+        _ => format!("Key {} set", key),
+    };
+    if format == DocumentFormat::Yaml {
+        message.push_str(" (YAML is written back in a normalized layout, without comments)");
+    }
+    let data = edit_result_message(req, &with_format_note(&message, &text_format), &content, &new_content);
+    history::record(&req.path, &req.command, Some(bytes));
+    let response = HttpResponse::Ok().json(ActionResponse {
+        r#type: String::from("success"),
+        media_type: String::from("text/plain"),
+        data,
+    });
+    conflict::with_content_hash(response, &encoded)
+}
@@ -0,0 +1,228 @@
+use similar::{DiffTag, TextDiff};
+use std::fs;
+use std::path::Path;
+
+pub const DEFAULT_TREE_DEPTH: usize = 2;
+/// Directory listings stop after this many entries to keep responses small.
+const MAX_TREE_ENTRIES: usize = 1000;
+
+/// Renders lines like `cat -n`, numbering them from `first_line`.
+pub fn numbered_lines(lines: &[&str], first_line: usize) -> String {
+    lines
+        .iter()
+        .enumerate()
+        .map(|(index, line)| numbered_line(first_line + index, line))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Size of `view` output unless `CONSOLEY_VIEW_MAX_BYTES` says otherwise.
+const DEFAULT_VIEW_MAX_BYTES: usize = 64 * 1024;
+
+/// Most bytes of numbered lines a `view` returns; requests may ask for less.
+pub fn max_view_bytes() -> usize {
+    std::env::var("CONSOLEY_VIEW_MAX_BYTES")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(DEFAULT_VIEW_MAX_BYTES)
+}
+
+fn numbered_line(number: usize, line: &str) -> String {
+    format!("{:6}\t{}", number, line)
+}
+
+/// Cuts a line that alone exceeds the output size.
+fn truncate_line(number: usize, line: &str, max_bytes: usize) -> String {
+    let marker = " … [line truncated]";
+    let budget = max_bytes.saturating_sub(numbered_line(number, "").len() + marker.len());
+    let end = (0..=budget.min(line.len())).rev().find(|&end| line.is_char_boundary(end)).unwrap_or(0);
+    numbered_line(number, &format!("{}{}", &line[..end], marker))
+}
+
+/// Renders `lines` (numbered from `first_line`) like `numbered_lines`, but
+/// stops at `max_bytes`. Pages start at the first line, or for `from_end`
+/// (tail mode) end at the last one. A truncated page ends with a note on
+/// how many lines are left and which `view_range` shows the next page.
+pub fn paged_lines(lines: &[&str], first_line: usize, max_bytes: usize, from_end: bool) -> String {
+    let mut shown: Vec<String> = Vec::new();
+    let mut size = 0;
+    let order: Box<dyn Iterator<Item = (usize, &&str)>> = if from_end {
+        Box::new(lines.iter().enumerate().rev())
+    } else {
+        Box::new(lines.iter().enumerate())
+    };
+    for (index, line) in order {
+        let rendered = numbered_line(first_line + index, line);
+        if size + rendered.len() + 1 > max_bytes {
+            if shown.is_empty() {
+                shown.push(truncate_line(first_line + index, line, max_bytes));
+            }
+            break;
+        }
+        size += rendered.len() + 1;
+        shown.push(rendered);
+    }
+    if from_end {
+        shown.reverse();
+    }
+
+    let count = shown.len();
+    let mut result = shown.join("\n");
+    if count == lines.len() {
+        return result;
+    }
+    let last_line = first_line + lines.len() - 1;
+    if from_end {
+        let start = last_line + 1 - count;
+        result = format!(
+            "[Output truncated to {} bytes: showing lines {}-{}, {} earlier lines not shown. Use view_range [{}, {}] to see more.]\n{}",
+            max_bytes,
+            start,
+            last_line,
+            start - first_line,
+            start.saturating_sub(count).max(first_line),
+            start - 1,
+            result
+        );
+    } else {
+        let end = first_line + count - 1;
+        result.push_str(&format!(
+            "\n[Output truncated to {} bytes: showing lines {}-{}, {} more lines not shown. Use view_range [{}, {}] to see more.]",
+            max_bytes,
+            first_line,
+            end,
+            last_line - end,
+            end + 1,
+            (end + count).min(last_line)
+        ));
+    }
+    result
+}
+
+fn walk(dir: &Path, depth: usize, max_depth: usize, show_hidden: bool, output: &mut Vec<String>) -> Result<(), String> {
+    let mut entries: Vec<_> = fs::read_dir(dir)
+        .map_err(|e| format!("Failed to read directory {}: {}", dir.display(), e))?
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| show_hidden || !entry.file_name().to_string_lossy().starts_with('.'))
+        .collect();
+    entries.sort_by_key(|entry| entry.file_name());
+
+    for entry in entries {
+        if output.len() >= MAX_TREE_ENTRIES {
+            return Ok(());
+        }
+        let path = entry.path();
+        // Symlinked directories are listed but not followed, to avoid cycles
+        let is_dir = entry.file_type().map(|t| t.is_dir()).unwrap_or(false);
+        let indent = "  ".repeat(depth);
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if is_dir {
+            output.push(format!("{}{}/", indent, name));
+            if depth + 1 < max_depth {
+                // Unreadable subdirectories are listed without their contents
+                if let Err(e) = walk(&path, depth + 1, max_depth, show_hidden, output) {
+                    log::debug!("{}", e);
+                }
+            }
+        } else {
+            output.push(format!("{}{}", indent, name));
+        }
+    }
+    Ok(())
+}
+
+/// Lists the files and directories below `dir` up to `max_depth` levels deep.
+pub fn directory_tree(dir: &Path, max_depth: usize, show_hidden: bool) -> Result<String, String> {
+    let mut output = Vec::new();
+    walk(dir, 0, max_depth.max(1), show_hidden, &mut output)?;
+
+    let hidden = if show_hidden { "including" } else { "excluding" };
+    let mut listing = format!(
+        "Here's the files and directories up to {} levels deep in {}, {} hidden items:\n{}",
+        max_depth.max(1), dir.display(), hidden, output.join("\n")
+    );
+    if output.len() >= MAX_TREE_ENTRIES {
+        listing.push_str(&format!("\n... (listing truncated after {} entries)", MAX_TREE_ENTRIES));
+    }
+    Ok(listing)
+}
+
+/// Lines of unchanged context shown around an edit in snippets and diffs.
+const SNIPPET_CONTEXT_LINES: usize = 4;
+/// Snippets of large changes (e.g. creating a big file) are cut off here.
+const MAX_SNIPPET_LINES: usize = 60;
+
+/// What a mutating edit command returns besides its success message.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ResponseFormat {
+    /// Numbered lines around the change.
+    Snippet,
+    /// Unified diff between the old and new content.
+    Diff,
+    /// Only the success message.
+    Message,
+}
+
+/// Returns the 0-based range of lines in `new` that differ from `old`, or
+/// `None` if the contents are equal.
+fn changed_lines(old: &str, new: &str) -> Option<(usize, usize)> {
+    let diff = TextDiff::from_lines(old, new);
+    diff.ops()
+        .iter()
+        .filter(|op| op.tag() != DiffTag::Equal)
+        .map(|op| op.new_range())
+        .fold(None, |range, changed| match range {
+            None => Some((changed.start, changed.end)),
+            Some((start, end)) => Some((start.min(changed.start), end.max(changed.end))),
+        })
+}
+
+fn snippet(path: &str, old: &str, new: &str) -> String {
+    let lines: Vec<&str> = new.split('\n').collect();
+    let Some((start, end)) = changed_lines(old, new) else {
+        return String::from("The file content is unchanged.");
+    };
+
+    let first = start.saturating_sub(SNIPPET_CONTEXT_LINES);
+    let last = (end.max(start + 1) + SNIPPET_CONTEXT_LINES).min(lines.len());
+    let shown = last.saturating_sub(first).min(MAX_SNIPPET_LINES);
+    let mut result = format!(
+        "Here's the result of running `cat -n` on a snippet of {}:\n{}",
+        path,
+        numbered_lines(&lines[first..first + shown], first + 1)
+    );
+    if first + shown < last {
+        result.push_str(&format!("\n... ({} more diff lines not shown)", last - first - shown));
+    }
+    result
+}
+
+/// Unified diff from `old` to `new` with `a/` and `b/` headers; empty if
+/// they are equal.
+pub fn file_diff(path: &str, old: &str, new: &str) -> String {
+    let path = path.trim_start_matches('/');
+    TextDiff::from_lines(old, new)
+        .unified_diff()
+        .context_radius(SNIPPET_CONTEXT_LINES)
+        .header(&format!("a/{}", path), &format!("b/{}", path))
+        .to_string()
+}
+
+fn unified_diff(path: &str, old: &str, new: &str) -> String {
+    let patch = file_diff(path, old, new);
+    if patch.is_empty() {
+        String::from("The file content is unchanged.")
+    } else {
+        format!("Unified diff of the change:\n{}", patch)
+    }
+}
+
+/// Describes the change from `old` to `new` in the requested format; empty
+/// for `ResponseFormat::Message`.
+pub fn edit_feedback(path: &str, old: &str, new: &str, format: ResponseFormat) -> String {
+    match format {
+        ResponseFormat::Snippet => snippet(path, old, new),
+        ResponseFormat::Diff => unified_diff(path, old, new),
+        ResponseFormat::Message => String::new(),
+    }
+}
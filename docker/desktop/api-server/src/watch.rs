@@ -0,0 +1,521 @@
+use actix_web::http::StatusCode;
+use actix_web::web::Bytes;
+use actix_web::{web, HttpResponse};
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::ffi::CString;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::os::fd::{AsRawFd, FromRawFd};
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+
+use crate::files::error_response;
+use crate::{conflict, sandbox, search};
+
+const DEFAULT_DEBOUNCE_MS: u64 = 200;
+const MAX_DEBOUNCE_MS: u64 = 10_000;
+/// Directories watched per subscription, as inotify watches are a limited
+/// system resource.
+const MAX_WATCHES: usize = 10_000;
+/// Larger files are reported without a content hash.
+const MAX_HASH_BYTES: u64 = 64 * 1024 * 1024;
+/// A comment is sent this often, so a closed connection is noticed.
+const KEEPALIVE: Duration = Duration::from_secs(15);
+const WATCH_MASK: u32 = libc::IN_CREATE
+    | libc::IN_MODIFY
+    | libc::IN_CLOSE_WRITE
+    | libc::IN_DELETE
+    | libc::IN_MOVED_FROM
+    | libc::IN_MOVED_TO
+    | libc::IN_DELETE_SELF
+    | libc::IN_MOVE_SELF
+    | libc::IN_ONLYDIR;
+
+#[derive(Debug, Deserialize)]
+pub struct WatchQuery {
+    /// File or directory to watch.
+    pub path: String,
+    /// Comma separated globs, relative to `path`, of the files to report.
+    pub globs: Option<String>,
+    /// Also watch subdirectories (default true).
+    pub recursive: Option<bool>,
+    /// Quiet time after the last change to a path before it is reported.
+    pub debounce_ms: Option<u64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum ChangeKind {
+    Create,
+    Modify,
+    Delete,
+    Rename,
+}
+
+impl ChangeKind {
+    fn name(&self) -> &'static str {
+        match self {
+            ChangeKind::Create => "create",
+            ChangeKind::Modify => "modify",
+            ChangeKind::Delete => "delete",
+            ChangeKind::Rename => "rename",
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct WatchEvent {
+    kind: ChangeKind,
+    path: String,
+    /// Old path of a renamed file.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    from: Option<String>,
+    is_dir: bool,
+    /// SHA-256 of the new content, as in the `ETag` of `/edit`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    hash: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct WatchReady {
+    path: String,
+    watches: usize,
+    /// Not every directory could be watched.
+    truncated: bool,
+}
+
+/// A change waiting for its path to be quiet for the debounce time.
+struct Pending {
+    kind: ChangeKind,
+    from: Option<PathBuf>,
+    is_dir: bool,
+    at: Instant,
+}
+
+struct RawEvent {
+    path: PathBuf,
+    mask: u32,
+    cookie: u32,
+}
+
+/// An inotify instance and the directories it watches.
+struct Inotify {
+    file: File,
+    watches: HashMap<i32, PathBuf>,
+}
+
+impl Inotify {
+    fn new() -> io::Result<Inotify> {
+        // SAFETY: a plain syscall; the returned descriptor is owned by the File
+        let fd = unsafe { libc::inotify_init1(libc::IN_CLOEXEC | libc::IN_NONBLOCK) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Inotify {
+            file: unsafe { File::from_raw_fd(fd) },
+            watches: HashMap::new(),
+        })
+    }
+
+    fn add(&mut self, dir: &Path) -> io::Result<()> {
+        let path = CString::new(dir.as_os_str().as_bytes()).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        // SAFETY: the path is a valid NUL-terminated string for the duration of the call
+        let wd = unsafe { libc::inotify_add_watch(self.file.as_raw_fd(), path.as_ptr(), WATCH_MASK) };
+        if wd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        self.watches.insert(wd, dir.to_path_buf());
+        Ok(())
+    }
+
+    /// Waits up to `timeout` for events to read. Returns false on timeout.
+    fn wait(&self, timeout: Duration) -> io::Result<bool> {
+        let mut poll = libc::pollfd {
+            fd: self.file.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        // SAFETY: `poll` is a valid pollfd for the duration of the call
+        let ready = unsafe { libc::poll(&mut poll, 1, timeout.as_millis().min(i32::MAX as u128) as i32) };
+        match ready {
+            0 => Ok(false),
+            n if n > 0 => Ok(true),
+            _ => match io::Error::last_os_error() {
+                e if e.kind() == io::ErrorKind::Interrupted => Ok(false),
+                e => Err(e),
+            },
+        }
+    }
+
+    /// Reads the queued events, resolving their paths. Watches the kernel
+    /// dropped are forgotten.
+    fn read(&mut self) -> io::Result<Vec<RawEvent>> {
+        let mut events = Vec::new();
+        let mut buffer = [0u8; 64 * 1024];
+        loop {
+            let read = match self.file.read(&mut buffer) {
+                Ok(read) => read,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) => return Err(e),
+            };
+            let mut offset = 0;
+            // struct inotify_event: wd, mask, cookie, len, then len bytes of name
+            while offset + 16 <= read {
+                let field = |at: usize| u32::from_ne_bytes(buffer[offset + at..offset + at + 4].try_into().unwrap());
+                let (wd, mask, cookie, len) = (field(0) as i32, field(4), field(8), field(12) as usize);
+                let name = &buffer[offset + 16..(offset + 16 + len).min(read)];
+                let name = &name[..name.iter().position(|&b| b == 0).unwrap_or(name.len())];
+                offset += 16 + len;
+
+                if mask & libc::IN_Q_OVERFLOW != 0 {
+                    events.push(RawEvent {
+                        path: PathBuf::new(),
+                        mask,
+                        cookie,
+                    });
+                    continue;
+                }
+                let Some(dir) = self.watches.get(&wd) else {
+                    continue;
+                };
+                let path = if name.is_empty() { dir.clone() } else { dir.join(std::ffi::OsStr::from_bytes(name)) };
+                if mask & libc::IN_IGNORED != 0 {
+                    self.watches.remove(&wd);
+                }
+                events.push(RawEvent { path, mask, cookie });
+            }
+            if read < buffer.len() / 2 {
+                break;
+            }
+        }
+        Ok(events)
+    }
+}
+
+struct Watcher {
+    inotify: Inotify,
+    /// The watched file or directory.
+    root: PathBuf,
+    root_is_file: bool,
+    globs: GlobSet,
+    recursive: bool,
+    debounce: Duration,
+    truncated: bool,
+    pending: HashMap<PathBuf, Pending>,
+    /// Moved-out paths by cookie, waiting for the matching moved-in event.
+    moves: HashMap<u32, (PathBuf, bool, Instant)>,
+    /// Paths known to exist, to tell a file renamed over another from a new one.
+    known: HashSet<PathBuf>,
+}
+
+impl Watcher {
+    /// Watches `dir` and the directories below it that are not hidden,
+    /// ignored by git or forbidden. Returns the entries found below `dir`.
+    fn watch_tree(&mut self, dir: &Path) -> Vec<(PathBuf, bool)> {
+        let mut found = Vec::new();
+        let walker = match search::walk(dir, &[], false, false) {
+            Ok(walker) => walker.filter_map(Result::ok),
+            Err(e) => {
+                log::warn!("Cannot watch {}: {}", dir.display(), e);
+                return found;
+            }
+        };
+        for entry in walker {
+            let is_dir = entry.file_type().is_some_and(|file_type| file_type.is_dir());
+            if entry.depth() > 0 {
+                found.push((entry.path().to_path_buf(), is_dir));
+            }
+            if !is_dir {
+                continue;
+            }
+            if self.inotify.watches.len() >= MAX_WATCHES {
+                self.truncated = true;
+                continue;
+            }
+            if let Err(e) = self.inotify.add(entry.path()) {
+                log::warn!("Cannot watch {}: {}", entry.path().display(), e);
+                self.truncated = true;
+            }
+        }
+        found
+    }
+
+    fn record(&mut self, path: PathBuf, kind: ChangeKind, is_dir: bool) {
+        let now = Instant::now();
+        if kind == ChangeKind::Delete {
+            self.known.remove(&path);
+        } else {
+            self.known.insert(path.clone());
+        }
+        let merged = match (self.pending.get(&path).map(|pending| pending.kind), kind) {
+            // The client never saw it
+            (Some(ChangeKind::Create), ChangeKind::Delete) => {
+                self.pending.remove(&path);
+                return;
+            }
+            (Some(ChangeKind::Create), ChangeKind::Modify) => ChangeKind::Create,
+            (Some(ChangeKind::Rename), ChangeKind::Modify) => ChangeKind::Rename,
+            (Some(ChangeKind::Delete), ChangeKind::Create) => ChangeKind::Modify,
+            (_, kind) => kind,
+        };
+        let from = self.pending.get_mut(&path).and_then(|pending| pending.from.take()).filter(|_| merged == ChangeKind::Rename);
+        self.pending.insert(path, Pending { kind: merged, from, is_dir, at: now });
+    }
+
+    fn record_rename(&mut self, from: PathBuf, to: PathBuf, is_dir: bool) {
+        self.known.remove(&from);
+        match self.pending.remove(&from) {
+            // A new file renamed into place, as editors save atomically
+            Some(pending) if pending.kind == ChangeKind::Create => {
+                let kind = if self.known.contains(&to) { ChangeKind::Modify } else { ChangeKind::Create };
+                self.record(to, kind, is_dir);
+            }
+            _ => {
+                self.known.insert(to.clone());
+                let pending = Pending {
+                    kind: ChangeKind::Rename,
+                    from: Some(from),
+                    is_dir,
+                    at: Instant::now(),
+                };
+                self.pending.insert(to, pending);
+            }
+        }
+    }
+
+    /// Updates the pending changes with an event. Returns false once the
+    /// watched path itself is gone.
+    fn handle(&mut self, event: RawEvent) -> bool {
+        let is_dir = event.mask & libc::IN_ISDIR != 0;
+        if event.mask & (libc::IN_DELETE_SELF | libc::IN_MOVE_SELF) != 0 {
+            if !self.root_is_file && event.path == self.root {
+                self.record(event.path, ChangeKind::Delete, true);
+                return false;
+            }
+            return true;
+        }
+        if event.mask & libc::IN_CREATE != 0 {
+            self.record(event.path.clone(), ChangeKind::Create, is_dir);
+        } else if event.mask & (libc::IN_MODIFY | libc::IN_CLOSE_WRITE) != 0 {
+            self.record(event.path.clone(), ChangeKind::Modify, is_dir);
+        } else if event.mask & libc::IN_DELETE != 0 {
+            self.record(event.path.clone(), ChangeKind::Delete, is_dir);
+        } else if event.mask & libc::IN_MOVED_FROM != 0 {
+            self.moves.insert(event.cookie, (event.path.clone(), is_dir, Instant::now()));
+        } else if event.mask & libc::IN_MOVED_TO != 0 {
+            match self.moves.remove(&event.cookie) {
+                Some((from, _, _)) => self.record_rename(from, event.path.clone(), is_dir),
+                None => self.record(event.path.clone(), ChangeKind::Create, is_dir),
+            }
+        }
+        // Files created in a new directory before it is watched are reported too
+        if is_dir && self.recursive && !self.root_is_file && event.mask & (libc::IN_CREATE | libc::IN_MOVED_TO) != 0 {
+            for (path, is_dir) in self.watch_tree(&event.path) {
+                self.record(path, ChangeKind::Create, is_dir);
+            }
+        }
+        true
+    }
+
+    fn reported(&self, path: &Path) -> bool {
+        if self.root_is_file {
+            return path == self.root;
+        }
+        let relative = path.strip_prefix(&self.root).unwrap_or(path);
+        (self.globs.is_empty() || self.globs.is_match(relative))
+            && sandbox::check(&path.to_string_lossy(), sandbox::Access::Read).is_ok()
+    }
+
+    fn event(&self, path: PathBuf, pending: Pending) -> Option<WatchEvent> {
+        let shown = self.reported(&path) || pending.from.as_deref().is_some_and(|from| self.reported(from));
+        if !shown {
+            return None;
+        }
+        let hash = match pending.kind {
+            ChangeKind::Delete => None,
+            _ if pending.is_dir => None,
+            _ => fs::metadata(&path)
+                .ok()
+                .filter(|metadata| metadata.is_file() && metadata.len() <= MAX_HASH_BYTES)
+                .and_then(|_| fs::read(&path).ok())
+                .map(|content| conflict::content_hash(&content)),
+        };
+        Some(WatchEvent {
+            kind: pending.kind,
+            path: path.to_string_lossy().to_string(),
+            from: pending.from.map(|from| from.to_string_lossy().to_string()),
+            is_dir: pending.is_dir,
+            hash,
+        })
+    }
+
+    /// Time until the next pending change is due.
+    fn next_due(&self) -> Option<Duration> {
+        let pending = self.pending.values().map(|pending| pending.at);
+        let moves = self.moves.values().map(|(_, _, at)| *at);
+        pending.chain(moves).min().map(|at| (at + self.debounce).saturating_duration_since(Instant::now()))
+    }
+
+    /// Takes the changes whose path has been quiet for the debounce time.
+    /// Moves without a matching moved-in event left the watched tree.
+    fn take_due(&mut self) -> Vec<WatchEvent> {
+        let now = Instant::now();
+        let expired: Vec<u32> = self
+            .moves
+            .iter()
+            .filter(|(_, (_, _, at))| now.duration_since(*at) >= self.debounce)
+            .map(|(cookie, _)| *cookie)
+            .collect();
+        for cookie in expired {
+            if let Some((path, is_dir, _)) = self.moves.remove(&cookie) {
+                self.record(path, ChangeKind::Delete, is_dir);
+            }
+        }
+
+        let mut due: Vec<(PathBuf, Instant)> = self
+            .pending
+            .iter()
+            .filter(|(_, pending)| now.duration_since(pending.at) >= self.debounce)
+            .map(|(path, pending)| (path.clone(), pending.at))
+            .collect();
+        due.sort_by_key(|(_, at)| *at);
+        due.into_iter()
+            .filter_map(|(path, _)| {
+                let pending = self.pending.remove(&path)?;
+                self.event(path, pending)
+            })
+            .collect()
+    }
+
+    /// Sends debounced events until the client disconnects or the watched
+    /// path disappears.
+    fn run(mut self, tx: mpsc::Sender<Result<Bytes, io::Error>>) {
+        let send = |text: String| tx.blocking_send(Ok(Bytes::from(text))).is_ok();
+        let ready = WatchReady {
+            path: self.root.to_string_lossy().to_string(),
+            watches: self.inotify.watches.len(),
+            truncated: self.truncated,
+        };
+        if !send(format!("event: ready\ndata: {}\n\n", serde_json::to_string(&ready).unwrap_or_default())) {
+            return;
+        }
+
+        let mut watching = true;
+        let mut last_sent = Instant::now();
+        while !tx.is_closed() {
+            let timeout = self.next_due().map_or(KEEPALIVE, |due| due.min(KEEPALIVE));
+            match self.inotify.wait(timeout).and_then(|ready| if ready { self.inotify.read() } else { Ok(Vec::new()) }) {
+                Ok(events) => {
+                    for event in events {
+                        if event.mask & libc::IN_Q_OVERFLOW != 0 {
+                            // Changes were lost; the client has to look again
+                            if !send(String::from("event: overflow\ndata: {}\n\n")) {
+                                return;
+                            }
+                            continue;
+                        }
+                        watching &= self.handle(event);
+                    }
+                }
+                Err(e) => {
+                    log::error!("Failed to read file events for {}: {}", self.root.display(), e);
+                    return;
+                }
+            }
+
+            for event in self.take_due() {
+                let data = serde_json::to_string(&event).unwrap_or_default();
+                if !send(format!("event: {}\ndata: {}\n\n", event.kind.name(), data)) {
+                    return;
+                }
+                last_sent = Instant::now();
+            }
+            if !watching && self.pending.is_empty() && self.moves.is_empty() {
+                log::info!("Watched path {} is gone, ending watch", self.root.display());
+                return;
+            }
+            if last_sent.elapsed() >= KEEPALIVE {
+                if !send(String::from(": keepalive\n\n")) {
+                    return;
+                }
+                last_sent = Instant::now();
+            }
+        }
+    }
+}
+
+fn glob_set(globs: Option<&str>) -> Result<GlobSet, String> {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in globs.unwrap_or_default().split(',').map(str::trim).filter(|p| !p.is_empty()) {
+        builder.add(Glob::new(pattern).map_err(|e| format!("Invalid glob {}: {}", pattern, e))?);
+    }
+    builder.build().map_err(|e| format!("Invalid globs: {}", e))
+}
+
+/// Streams changes to `path` as server-sent events until the client
+/// disconnects. Directories are watched rather than files, so files
+/// replaced by renaming a new file over them keep being reported.
+pub async fn handle_watch(query: web::Query<WatchQuery>) -> HttpResponse {
+    let root = match sandbox::check(&query.path, sandbox::Access::Read) {
+        Ok(root) => root,
+        Err(e) => return error_response((StatusCode::FORBIDDEN, e)),
+    };
+    if !root.exists() {
+        return error_response((StatusCode::NOT_FOUND, format!("{} does not exist", query.path)));
+    }
+    let globs = match glob_set(query.globs.as_deref()) {
+        Ok(globs) => globs,
+        Err(e) => return error_response((StatusCode::BAD_REQUEST, e)),
+    };
+    let debounce = Duration::from_millis(query.debounce_ms.unwrap_or(DEFAULT_DEBOUNCE_MS).min(MAX_DEBOUNCE_MS));
+    let recursive = query.recursive.unwrap_or(true);
+
+    let watcher = web::block(move || -> Result<Watcher, String> {
+        let inotify = Inotify::new().map_err(|e| format!("Failed to start watching: {}", e))?;
+        let root_is_file = !root.is_dir();
+        let mut watcher = Watcher {
+            inotify,
+            root,
+            root_is_file,
+            globs,
+            recursive,
+            debounce,
+            truncated: false,
+            pending: HashMap::new(),
+            moves: HashMap::new(),
+            known: HashSet::new(),
+        };
+        if root_is_file || !recursive {
+            let dir = if root_is_file { watcher.root.parent().unwrap_or(Path::new("/")) } else { &watcher.root };
+            let dir = dir.to_path_buf();
+            watcher.inotify.add(&dir).map_err(|e| format!("Failed to watch {}: {}", dir.display(), e))?;
+            watcher.known = fs::read_dir(&dir).into_iter().flatten().filter_map(|entry| Some(entry.ok()?.path())).collect();
+        } else {
+            let found = watcher.watch_tree(&watcher.root.clone());
+            watcher.known = found.into_iter().map(|(path, _)| path).collect();
+            if watcher.inotify.watches.is_empty() {
+                return Err(format!("Failed to watch {}", watcher.root.display()));
+            }
+        }
+        Ok(watcher)
+    })
+    .await;
+    let watcher = match watcher {
+        Ok(Ok(watcher)) => watcher,
+        Ok(Err(e)) => return error_response((StatusCode::INTERNAL_SERVER_ERROR, e)),
+        Err(e) => return error_response((StatusCode::INTERNAL_SERVER_ERROR, e.to_string())),
+    };
+    log::info!("Watching {} with {} watches", watcher.root.display(), watcher.inotify.watches.len());
+
+    let (tx, rx) = mpsc::channel::<Result<Bytes, io::Error>>(64);
+    std::thread::spawn(move || watcher.run(tx));
+    let stream = futures_util::stream::unfold(rx, |mut rx| async move { rx.recv().await.map(|item| (item, rx)) });
+    HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header(("Cache-Control", "no-cache"))
+        .streaming(stream)
+}
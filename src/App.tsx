@@ -3,6 +3,36 @@ import * as Switch from "@radix-ui/react-switch";
 
 import './App.css';
 
+// The API server listens on port 8090 of the desktop host; VITE_API_BASE_URL
+// overrides this when the UI is served from elsewhere
+const API_HOST = window.location.protocol.startsWith('http') && window.location.hostname !== 'tauri.localhost'
+  ? window.location.hostname
+  : 'localhost';
+const API_BASE_URL: string = import.meta.env.VITE_API_BASE_URL ?? `http://${API_HOST}:8090`;
+const CONTROL_OWNER = 'consoley-ui';
+const CONTROL_TTL_SECS = 30;
+
+// Acquire, renew or release the server-side control lease for the human operator.
+// Resolves to the response status, or undefined if the server is unreachable.
+const requestControl = async (command: 'acquire' | 'release', force = false) => {
+  try {
+    const response = await fetch(`${API_BASE_URL}/control`, {
+      method: 'POST',
+      headers: { 'Content-Type': 'application/json' },
+      body: JSON.stringify({
+        command,
+        owner: CONTROL_OWNER,
+        holder: 'human',
+        ttl_secs: CONTROL_TTL_SECS,
+        force,
+      }),
+    });
+    return response.status;
+  } catch (error) {
+    console.error(`Failed to ${command} control lease:`, error);
+  }
+};
+
 function App() {
   const [status, setStatus] = useState<string>('stopped');
   const [loading, setLoading] = useState<boolean>(false);
@@ -92,6 +122,28 @@ function App() {
     };
   }, [status, loading]);
 
+  // Hold the control lease while human operation is allowed, so the API server
+  // rejects agent input instead of letting it collide with the human
+  useEffect(() => {
+    if (!checked) {
+      return;
+    }
+
+    // Only the explicit toggle takes the lease over from an agent; renewals
+    // must not, so a lost lease switches human operation back off
+    requestControl('acquire', true);
+    const intervalId = window.setInterval(async () => {
+      if (await requestControl('acquire') === 409) {
+        setChecked(false);
+      }
+    }, CONTROL_TTL_SECS * 1000 / 3);
+
+    return () => {
+      clearInterval(intervalId);
+      requestControl('release');
+    };
+  }, [checked]);
+
   return (
     <div className="container">
       <div className='container_content'>
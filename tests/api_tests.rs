@@ -3,6 +3,7 @@ use std::time::Duration;
 
 const API_BASE_URL: &str = "http://localhost:8090";
 
+#[allow(clippy::needless_borrows_for_generic_args)]
 async fn test_action_with_params(action: &str, text: Option<&str>, coordinate: Option<Vec<i32>>) -> reqwest::Response {
     let client = reqwest::Client::new();
     
@@ -53,6 +54,7 @@ async fn test_action_with_params(action: &str, text: Option<&str>, coordinate: O
     panic!("Should not reach here");
 }
 
+#[allow(clippy::needless_borrows_for_generic_args)]
 async fn wait_for_service() {
     let client = reqwest::Client::new();
     for i in 0..60 {
@@ -72,6 +74,7 @@ async fn wait_for_service() {
     panic!("Service did not become ready in time");
 }
 
+#[allow(clippy::needless_borrows_for_generic_args)]
 async fn test_edit_command(
     command: &str,
     path: &str,
@@ -141,6 +144,7 @@ async fn test_computer_actions() {
 }
 
 #[tokio::test]
+#[allow(clippy::type_complexity)]
 async fn test_keyboard_actions() {
     let keyboard_tests: Vec<(&str, Option<&str>, Option<Vec<i32>>, bool)> = vec![
         ("key", Some("Return"), None::<Vec<i32>>, true),
@@ -163,6 +167,7 @@ async fn test_keyboard_actions() {
 }
 
 #[tokio::test]
+#[allow(clippy::type_complexity)]
 async fn test_mouse_movement_actions() {
     let mouse_tests: Vec<(&str, Option<&str>, Option<Vec<i32>>, bool)> = vec![
         ("mouse_move", None, Some(vec![100, 100]), true),
@@ -186,6 +191,45 @@ async fn test_mouse_movement_actions() {
     }
 }
 
+#[tokio::test]
+async fn test_humanlike_input_profiles() {
+    let client = reqwest::Client::new();
+    let payloads = vec![
+        json!({
+            "action": "mouse_move",
+            "coordinate": [300, 200],
+            "motion": {"profile": "bezier", "duration_ms": 200, "jitter": 2}
+        }),
+        json!({
+            "action": "left_click_drag",
+            "coordinate": [320, 220],
+            "motion": {"profile": "linear", "duration_ms": 100}
+        }),
+        json!({
+            "action": "type",
+            "text": "abc",
+            "typing": {"delay_ms": 30, "variance_ms": 10}
+        }),
+    ];
+
+    for payload in payloads {
+        let response = client
+            .post(format!("{}/computer", API_BASE_URL))
+            .json(&payload)
+            .timeout(Duration::from_secs(10))
+            .send()
+            .await
+            .expect("Failed to execute request");
+        assert_eq!(response.status().as_u16(), 200, "Action {} should succeed", payload);
+
+        if payload["action"] == "mouse_move" {
+            let response = test_action_with_params("cursor_position", None, None).await;
+            let body: serde_json::Value = response.json().await.expect("Failed to parse response");
+            assert_eq!(body["data"], "Cursor position is: X=300, Y=200", "The pointer should end exactly on the target");
+        }
+    }
+}
+
 #[tokio::test]
 async fn test_screenshot_response() {
     let response = test_action_with_params("screenshot", None, None).await;
@@ -197,6 +241,30 @@ async fn test_screenshot_response() {
     assert!(!body["data"].as_str().unwrap().is_empty(), "Screenshot data should not be empty");
 }
 
+#[tokio::test]
+async fn test_action_with_screenshot_response() {
+    let client = reqwest::Client::new();
+    let response = client
+        .post(format!("{}/computer", API_BASE_URL))
+        .json(&json!({
+            "action": "mouse_move",
+            "coordinate": [120, 120],
+            "return_screenshot": true,
+            "settle_ms": 100
+        }))
+        .timeout(Duration::from_secs(10))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status().as_u16(), 200);
+
+    let body: serde_json::Value = response.json().await.expect("Failed to parse response");
+    assert_eq!(body["type"], "success", "Action result should be returned");
+    assert_eq!(body["screenshot"]["type"], "base64");
+    assert_eq!(body["screenshot"]["media_type"], "image/png");
+    assert!(!body["screenshot"]["data"].as_str().unwrap().is_empty(), "Screenshot data should not be empty");
+}
+
 #[tokio::test]
 async fn test_cursor_position_response() {
     println!("Starting cursor position test");
@@ -221,6 +289,60 @@ async fn test_cursor_position_response() {
     assert!(data.contains("Y="), "Response should contain Y coordinate");
 }
 
+/// Sends an `/edit` request with fields `test_edit_command` does not cover.
+async fn edit_request(payload: serde_json::Value) -> reqwest::Response {
+    let client = reqwest::Client::new();
+
+    client
+        .post(format!("{}/edit", API_BASE_URL))
+        .json(&payload)
+        .timeout(Duration::from_secs(10))
+        .send()
+        .await
+        .expect("Failed to execute request")
+}
+
+/// Returns the numbered content `view` shows for `path`.
+async fn view_file(path: &str) -> String {
+    let response = test_edit_command("view", path, None, None, None, None, None).await;
+    assert_eq!(response.status().as_u16(), 200, "Viewing {} should succeed", path);
+    let body: serde_json::Value = response.json().await.expect("Failed to parse response");
+    body["data"].as_str().unwrap().to_string()
+}
+
+/// Writes `content` to `path` through `/files`, replacing an existing file.
+async fn upload_file(path: &str, content: &[u8]) {
+    let client = reqwest::Client::new();
+
+    let response = client
+        .put(format!("{}/files", API_BASE_URL))
+        .query(&[("path", path), ("mkdir", "true"), ("overwrite", "true")])
+        .body(content.to_vec())
+        .timeout(Duration::from_secs(10))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status().as_u16(), 200, "Uploading {} should succeed", path);
+}
+
+/// Downloads `path` through `/files`; `None` if it does not exist.
+async fn download_file(path: &str) -> Option<Vec<u8>> {
+    let client = reqwest::Client::new();
+
+    let response = client
+        .get(format!("{}/files", API_BASE_URL))
+        .query(&[("path", path)])
+        .timeout(Duration::from_secs(10))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    match response.status().as_u16() {
+        200 => Some(response.bytes().await.expect("Failed to read response").to_vec()),
+        404 => None,
+        status => panic!("Downloading {} failed with {}", path, status),
+    }
+}
+
 #[tokio::test]
 async fn test_file_creation_and_view() {
     wait_for_service().await;
@@ -272,6 +394,76 @@ async fn test_file_creation_and_view() {
     assert!(body["data"].as_str().unwrap().contains("Hello"), "Range view should show specified lines");
 }
 
+#[tokio::test]
+async fn test_view_numbering_and_directories() {
+    wait_for_service().await;
+
+    let test_file = "/tmp/test_view_dir/numbered.txt";
+    let client = reqwest::Client::new();
+    let response = client
+        .post(format!("{}/bash", API_BASE_URL))
+        .json(&json!({ "command": "mkdir -p /tmp/test_view_dir/sub/deeper/deepest && touch /tmp/test_view_dir/.hidden" }))
+        .timeout(Duration::from_secs(10))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status().as_u16(), 200);
+
+    let response = test_edit_command("create", test_file, Some("a\nb\nc"), None, None, None, None).await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let response = test_edit_command("view", test_file, None, Some(vec![2, 3]), None, None, None).await;
+    assert_eq!(response.status().as_u16(), 200);
+    let body: serde_json::Value = response.json().await.expect("Failed to parse response");
+    assert_eq!(body["data"], "     2\tb\n     3\tc", "Range view should keep the real line numbers");
+
+    let response = test_edit_command("view", "/tmp/test_view_dir", None, None, None, None, None).await;
+    assert_eq!(response.status().as_u16(), 200, "Viewing a directory should succeed");
+    let body: serde_json::Value = response.json().await.expect("Failed to parse response");
+    let listing = body["data"].as_str().unwrap();
+    assert!(listing.contains("numbered.txt"), "Listing should contain files");
+    assert!(listing.contains("sub/"), "Listing should contain directories");
+    assert!(listing.contains("deeper/"), "Listing should include the second level");
+    assert!(!listing.contains("deepest"), "Listing should stop at the default depth");
+    assert!(!listing.contains(".hidden"), "Hidden files should be skipped by default");
+}
+
+#[tokio::test]
+async fn test_edit_through_symlink_keeps_mode_and_line_endings() {
+    wait_for_service().await;
+
+    let client = reqwest::Client::new();
+    let bash = |command: &'static str| {
+        client
+            .post(format!("{}/bash", API_BASE_URL))
+            .json(&json!({ "command": command }))
+            .timeout(Duration::from_secs(10))
+            .send()
+    };
+    let response = bash(
+        "rm -rf /tmp/test_atomic_write && mkdir -p /tmp/test_atomic_write && \
+         printf 'one\\r\\ntwo\\r\\n' > /tmp/test_atomic_write/real.txt && \
+         chmod 640 /tmp/test_atomic_write/real.txt && \
+         ln -s real.txt /tmp/test_atomic_write/link.txt",
+    )
+    .await
+    .expect("Failed to execute request");
+    assert_eq!(response.status().as_u16(), 200);
+
+    let response = test_edit_command("str_replace", "/tmp/test_atomic_write/link.txt", None, None, Some("one"), Some("1\n2"), None).await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let response = bash(
+        "cd /tmp/test_atomic_write && test -L link.txt && stat -c %a real.txt && od -An -c real.txt | tr -d ' \\n'",
+    )
+    .await
+    .expect("Failed to execute request");
+    let body: serde_json::Value = response.json().await.expect("Failed to parse response");
+    let output = body["data"].as_str().unwrap_or_default();
+    assert!(output.contains("640"), "Mode should be preserved: {}", output);
+    assert!(output.contains("1\\r\\n2\\r\\ntwo\\r\\n"), "Line endings should stay CRLF: {}", output);
+}
+
 #[tokio::test]
 async fn test_file_modifications() {
     wait_for_service().await;
@@ -331,6 +523,874 @@ async fn test_file_modifications() {
     assert_eq!(response.status().as_u16(), 200, "Undo operation should succeed");
 }
 
+#[tokio::test]
+async fn test_edit_history() {
+    wait_for_service().await;
+
+    // A fresh path per run, so that history left over from earlier runs does not interfere
+    let nanos = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_nanos();
+    let test_file = &format!("/tmp/test_history_file_{}.txt", nanos);
+
+    let response = test_edit_command("create", test_file, Some("one"), None, None, None, None).await;
+    assert_eq!(response.status().as_u16(), 200);
+    let response = test_edit_command("str_replace", test_file, None, None, Some("one"), Some("two"), None).await;
+    assert_eq!(response.status().as_u16(), 200);
+    let response = test_edit_command("str_replace", test_file, None, None, Some("two"), Some("three"), None).await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let response = test_edit_command("history", test_file, None, None, None, None, None).await;
+    assert_eq!(response.status().as_u16(), 200);
+    let body: serde_json::Value = response.json().await.expect("Failed to parse response");
+    let history: serde_json::Value = serde_json::from_str(body["data"].as_str().unwrap()).unwrap();
+    assert_eq!(history["undo"].as_array().unwrap().len(), 3, "Each edit should be recorded");
+
+    // Undo twice, then redo once
+    for _ in 0..2 {
+        let response = test_edit_command("undo_edit", test_file, None, None, None, None, None).await;
+        assert_eq!(response.status().as_u16(), 200, "Repeated undo should succeed");
+    }
+    let response = test_edit_command("view", test_file, None, None, None, None, None).await;
+    let body: serde_json::Value = response.json().await.expect("Failed to parse response");
+    assert!(body["data"].as_str().unwrap().contains("one"), "Two undos should restore the original text");
+
+    let response = test_edit_command("redo", test_file, None, None, None, None, None).await;
+    assert_eq!(response.status().as_u16(), 200, "Redo should succeed");
+    let response = test_edit_command("view", test_file, None, None, None, None, None).await;
+    let body: serde_json::Value = response.json().await.expect("Failed to parse response");
+    assert!(body["data"].as_str().unwrap().contains("two"), "Redo should re-apply the first replacement");
+
+    // Undoing the create removes the file again
+    for _ in 0..2 {
+        let response = test_edit_command("undo_edit", test_file, None, None, None, None, None).await;
+        assert_eq!(response.status().as_u16(), 200);
+    }
+    let response = test_edit_command("view", test_file, None, None, None, None, None).await;
+    assert_eq!(response.status().as_u16(), 400, "File should be gone after undoing its creation");
+}
+
+#[tokio::test]
+async fn test_str_replace_unique_match() {
+    wait_for_service().await;
+
+    let test_file = "/tmp/test_unique_match.txt";
+    let response = test_edit_command("create", test_file, Some("foo\nbar\nfoo"), None, None, None, None).await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    // Missing and ambiguous matches are rejected
+    let response = test_edit_command("str_replace", test_file, None, None, Some("missing"), Some("x"), None).await;
+    assert_eq!(response.status().as_u16(), 400, "Zero matches should be rejected");
+    let response = test_edit_command("str_replace", test_file, None, None, Some("foo"), Some("x"), None).await;
+    assert_eq!(response.status().as_u16(), 400, "Multiple matches should be rejected");
+    let body: serde_json::Value = response.json().await.expect("Failed to parse response");
+    assert!(body["data"].as_str().unwrap().contains("[1, 3]"), "Error should list the matching lines");
+
+    let response = edit_request(json!({
+        "command": "str_replace",
+        "path": test_file,
+        "old_str": "foo",
+        "new_str": "baz",
+        "occurrence": 2
+    })).await;
+    assert_eq!(response.status().as_u16(), 200, "Replacing a chosen occurrence should succeed");
+
+    let response = edit_request(json!({
+        "command": "str_replace",
+        "path": test_file,
+        "old_str": "ba",
+        "new_str": "qu",
+        "replace_all": true
+    })).await;
+    assert_eq!(response.status().as_u16(), 200, "replace_all should succeed");
+
+    let response = test_edit_command("view", test_file, None, None, None, None, None).await;
+    let body: serde_json::Value = response.json().await.expect("Failed to parse response");
+    let data = body["data"].as_str().unwrap();
+    assert!(data.contains("foo") && data.contains("qur") && data.contains("quz"), "Unexpected content: {}", data);
+}
+
+#[tokio::test]
+async fn test_edit_response_format() {
+    wait_for_service().await;
+
+    let test_file = "/tmp/test_edit_response_format.txt";
+    let response = test_edit_command("create", test_file, Some("one\ntwo\nthree"), None, None, None, None).await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    // Snippets are the default
+    let response = test_edit_command("str_replace", test_file, None, None, Some("two"), Some("TWO"), None).await;
+    assert_eq!(response.status().as_u16(), 200);
+    let body: serde_json::Value = response.json().await.expect("Failed to parse response");
+    let data = body["data"].as_str().unwrap();
+    assert!(data.contains("cat -n") && data.contains("     2\tTWO"), "Unexpected snippet: {}", data);
+
+    for (format, expected) in [("diff", "+THREE"), ("message", "String replacement completed successfully")] {
+        let response = edit_request(json!({
+            "command": "str_replace",
+            "path": test_file,
+            "old_str": if format == "diff" { "three" } else { "THREE" },
+            "new_str": if format == "diff" { "THREE" } else { "three" },
+            "response_format": format
+        })).await;
+        assert_eq!(response.status().as_u16(), 200);
+        let body: serde_json::Value = response.json().await.expect("Failed to parse response");
+        let data = body["data"].as_str().unwrap();
+        assert!(data.contains(expected), "Unexpected {} response: {}", format, data);
+        if format == "message" {
+            assert_eq!(data, expected);
+        }
+    }
+
+    let response = edit_request(json!({
+        "command": "view",
+        "path": test_file,
+        "response_format": "bogus"
+    })).await;
+    assert_eq!(response.status().as_u16(), 400, "Unknown response formats should be rejected");
+}
+
+#[tokio::test]
+async fn test_apply_patch() {
+    wait_for_service().await;
+
+    let client = reqwest::Client::new();
+    let dir = "/tmp/test_apply_patch";
+    let response = client
+        .post(format!("{}/bash", API_BASE_URL))
+        .json(&json!({ "command": format!("rm -rf {dir} && mkdir -p {dir} && seq 1 10 > {dir}/numbers.txt") }))
+        .timeout(Duration::from_secs(10))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status().as_u16(), 200);
+
+    let apply = |patch: &'static str| edit_request(json!({ "command": "apply_patch", "path": dir, "patch": patch }));
+
+    // The second hunk does not match, so the first one must not be applied either
+    let response = apply("--- a/numbers.txt\n+++ b/numbers.txt\n@@ -1,2 +1,2 @@\n-1\n+one\n 2\n@@ -8,1 +8,1 @@\n-missing\n+x\n")
+        .await;
+    assert_eq!(response.status().as_u16(), 400, "A failing hunk should reject the patch");
+    let body: serde_json::Value = response.json().await.expect("Failed to parse response");
+    let report: serde_json::Value = serde_json::from_str(body["data"].as_str().unwrap()).unwrap();
+    assert_eq!(report["files"][0]["hunks"][1]["status"], "failed");
+
+    // Stated line numbers are off by two, which is tolerated as an offset
+    let response = apply(
+        "--- a/numbers.txt\n+++ b/numbers.txt\n@@ -3,3 +3,3 @@\n 1\n-2\n+two\n 3\n--- /dev/null\n+++ b/added.txt\n@@ -0,0 +1 @@\n+new file\n",
+    )
+    .await;
+    assert_eq!(response.status().as_u16(), 200, "Patch should apply");
+    let body: serde_json::Value = response.json().await.expect("Failed to parse response");
+    let report: serde_json::Value = serde_json::from_str(body["data"].as_str().unwrap()).unwrap();
+    assert_eq!(report["files"][0]["hunks"][0]["offset"], -2);
+
+    let response = test_edit_command("view", &format!("{}/numbers.txt", dir), None, Some(vec![1, 3]), None, None, None).await;
+    let body: serde_json::Value = response.json().await.expect("Failed to parse response");
+    assert_eq!(body["data"], "     1\t1\n     2\ttwo\n     3\t3");
+
+    let response = test_edit_command("undo_edit", &format!("{}/added.txt", dir), None, None, None, None, None).await;
+    assert_eq!(response.status().as_u16(), 200, "Created files should have undo history");
+    let response = test_edit_command("view", &format!("{}/added.txt", dir), None, None, None, None, None).await;
+    assert_ne!(response.status().as_u16(), 200, "Undoing the patch should remove the created file");
+}
+
+#[tokio::test]
+async fn test_view_pagination() {
+    wait_for_service().await;
+
+    let test_file = &format!("/tmp/test_view_pages_{}.log", std::process::id());
+    let content: String = (1..=1000).map(|n| format!("line {}\n", n)).collect();
+    let response = test_edit_command("create", test_file, Some(&content), None, None, None, None).await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let response = edit_request(json!({"command": "view", "path": test_file, "max_output": 200})).await;
+    assert_eq!(response.status().as_u16(), 200);
+    let body: serde_json::Value = response.json().await.expect("Failed to parse response");
+    let data = body["data"].as_str().unwrap();
+    assert!(data.len() < 400, "Output should be limited: {}", data);
+    assert!(data.starts_with("     1\tline 1\n"));
+    assert!(data.contains("Use view_range [14, 26] to see more"), "Truncated view should point to the next page: {}", data);
+
+    let response = edit_request(json!({"command": "view", "path": test_file, "tail": 2})).await;
+    let body: serde_json::Value = response.json().await.expect("Failed to parse response");
+    assert_eq!(body["data"], "   999\tline 999\n  1000\tline 1000");
+
+    let response = edit_request(json!({"command": "view", "path": test_file, "tail": 2, "view_range": [1, 2]})).await;
+    assert_eq!(response.status().as_u16(), 400, "tail and view_range should be exclusive");
+}
+
+#[tokio::test]
+async fn test_structured_keys() {
+    wait_for_service().await;
+
+    let dir = format!("/tmp/test_structured_{}", std::process::id());
+    let response = edit_request(json!({"command": "mkdir", "path": dir, "parents": true})).await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    // TOML keeps comments and layout
+    let toml = format!("{}/config.toml", dir);
+    let response = test_edit_command("create", &toml, Some("# settings\n[server]\nport = 80  # public port\nhost = \"a\"\n"), None, None, None, None).await;
+    assert_eq!(response.status().as_u16(), 200);
+    let response = edit_request(json!({"command": "set_key", "path": toml, "key": "server.port", "value": 8080})).await;
+    assert_eq!(response.status().as_u16(), 200);
+    let response = edit_request(json!({"command": "set_key", "path": toml, "key": "/server/tls/enabled", "value": true})).await;
+    assert_eq!(response.status().as_u16(), 200, "Missing tables should be created");
+    let response = edit_request(json!({"command": "delete_key", "path": toml, "key": "server.host"})).await;
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(
+        download_file(&toml).await.unwrap(),
+        b"# settings\n[server]\nport = 8080  # public port\n\n[server.tls]\nenabled = true\n"
+    );
+
+    // JSON keeps key order and indentation
+    let json_file = format!("{}/package.json", dir);
+    let response = test_edit_command("create", &json_file, Some("{\n    \"name\": \"x\",\n    \"files\": [\"a\"]\n}\n"), None, None, None, None).await;
+    assert_eq!(response.status().as_u16(), 200);
+    let response = edit_request(json!({"command": "set_key", "path": json_file, "key": "/files/-", "value": "b"})).await;
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(
+        download_file(&json_file).await.unwrap(),
+        b"{\n    \"name\": \"x\",\n    \"files\": [\n        \"a\",\n        \"b\"\n    ]\n}\n"
+    );
+
+    let response = edit_request(json!({"command": "get_key", "path": json_file, "key": "files.1"})).await;
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(response.headers().contains_key("etag"));
+    let body: serde_json::Value = response.json().await.expect("Failed to parse response");
+    assert_eq!(body["data"], "\"b\"");
+
+    let yaml = format!("{}/values.yaml", dir);
+    let response = test_edit_command("create", &yaml, Some("replicas: 1\n"), None, None, None, None).await;
+    assert_eq!(response.status().as_u16(), 200);
+    let response = edit_request(json!({"command": "get_key", "path": yaml, "key": "image.tag"})).await;
+    assert_eq!(response.status().as_u16(), 404, "Missing keys should not be found");
+    let response = edit_request(json!({"command": "get_key", "path": format!("{}/missing.yaml", dir), "key": "replicas"})).await;
+    assert_eq!(response.status().as_u16(), 400, "Missing files should be reported as such");
+    let body: serde_json::Value = response.json().await.expect("Failed to parse response");
+    assert!(body["data"].as_str().unwrap().starts_with("File not found"), "Unexpected error: {}", body["data"]);
+    let response = edit_request(json!({"command": "set_key", "path": yaml, "key": "replicas", "value": 3})).await;
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(download_file(&yaml).await.unwrap(), b"replicas: 3\n");
+
+    let response = edit_request(json!({"command": "undo_edit", "path": yaml})).await;
+    assert_eq!(response.status().as_u16(), 200, "Key edits should be undoable");
+    assert_eq!(download_file(&yaml).await.unwrap(), b"replicas: 1\n");
+}
+
+#[tokio::test]
+async fn test_notebook_cells() {
+    wait_for_service().await;
+
+    let notebook = format!("/tmp/test_notebook_{}.ipynb", std::process::id());
+    let original = json!({
+        "cells": [
+            {"cell_type": "markdown", "id": "intro", "metadata": {}, "source": ["# Title"]},
+            {
+                "cell_type": "code",
+                "execution_count": 1,
+                "id": "calc",
+                "metadata": {"tags": ["keep"]},
+                "outputs": [{"name": "stdout", "output_type": "stream", "text": ["2\n"]}],
+                "source": ["print(1 + 1)"]
+            }
+        ],
+        "metadata": {"kernelspec": {"language": "python", "name": "python3"}},
+        "nbformat": 4,
+        "nbformat_minor": 5
+    });
+    let text = serde_json::to_string_pretty(&original).unwrap();
+    let response = test_edit_command("create", &notebook, Some(&text), None, None, None, None).await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let response = edit_request(json!({"command": "list_cells", "path": notebook})).await;
+    assert_eq!(response.status().as_u16(), 200);
+    let body: serde_json::Value = response.json().await.expect("Failed to parse response");
+    let listing: serde_json::Value = serde_json::from_str(body["data"].as_str().unwrap()).unwrap();
+    assert_eq!(listing["language"], "python");
+    assert_eq!(listing["cells"][1]["source"], "print(1 + 1)");
+    assert_eq!(listing["cells"][1]["outputs"][0]["text"], "2\n");
+
+    let response = edit_request(json!({"command": "insert_cell", "path": notebook, "cell": 1, "cell_type": "markdown", "file_text": "Setup\nnotes"})).await;
+    assert_eq!(response.status().as_u16(), 200);
+    let response = edit_request(json!({"command": "replace_cell", "path": notebook, "cell": 2, "file_text": "print(2 + 2)"})).await;
+    assert_eq!(response.status().as_u16(), 200);
+    let response = edit_request(json!({"command": "delete_cell", "path": notebook, "cell": 0})).await;
+    assert_eq!(response.status().as_u16(), 200);
+    let response = edit_request(json!({"command": "delete_cell", "path": notebook, "cell": 5})).await;
+    assert_eq!(response.status().as_u16(), 400, "Cells out of range should be rejected");
+
+    let saved: serde_json::Value = serde_json::from_slice(&download_file(&notebook).await.unwrap()).unwrap();
+    assert_eq!(saved["metadata"], original["metadata"]);
+    assert_eq!(saved["nbformat_minor"], 5);
+    let cells = saved["cells"].as_array().unwrap();
+    assert_eq!(cells.len(), 2);
+    assert_eq!(cells[0]["cell_type"], "markdown");
+    assert_eq!(cells[0]["source"], json!(["Setup\n", "notes"]));
+    assert!(cells[0]["id"].is_string(), "New cells should get an id in nbformat 4.5");
+    assert_eq!(cells[1]["id"], "calc");
+    assert_eq!(cells[1]["metadata"]["tags"], json!(["keep"]));
+    assert_eq!(cells[1]["source"], json!(["print(2 + 2)"]));
+    assert_eq!(cells[1]["outputs"], json!([]), "Outputs of the old source should be cleared");
+    assert!(cells[1]["execution_count"].is_null());
+}
+
+#[tokio::test]
+async fn test_replace_in_files() {
+    wait_for_service().await;
+
+    let dir = format!("/tmp/test_replace_{}", std::process::id());
+    let (a, b, notes) = (format!("{}/src/a.rs", dir), format!("{}/src/b.rs", dir), format!("{}/notes.txt", dir));
+    let response = edit_request(json!({"command": "mkdir", "path": format!("{}/src", dir), "parents": true})).await;
+    assert_eq!(response.status().as_u16(), 200);
+    for (path, content) in [(&a, "fn get_user() {}\nget_user();\n"), (&b, "use get_user;\r\n"), (&notes, "get_user\n")] {
+        let response = test_edit_command("create", path, Some(content), None, None, None, None).await;
+        assert_eq!(response.status().as_u16(), 200);
+    }
+    let request = json!({
+        "command": "replace_in_files",
+        "path": dir,
+        "pattern": "get_(\\w+)",
+        "replacement": "fetch_${1}",
+        "globs": ["*.rs"],
+        "dry_run": true
+    });
+
+    let response = edit_request(request.clone()).await;
+    assert_eq!(response.status().as_u16(), 200);
+    let body: serde_json::Value = response.json().await.expect("Failed to parse response");
+    let report: serde_json::Value = serde_json::from_str(body["data"].as_str().unwrap()).unwrap();
+    assert_eq!(report["applied"], false);
+    assert_eq!(report["replacements"], 3);
+    assert!(report["diff"].as_str().unwrap().contains("+fn fetch_user() {}"));
+    assert_eq!(view_file(&a).await, "     1\tfn get_user() {}\n     2\tget_user();\n     3\t", "A dry run should not write");
+
+    let mut apply = request.clone();
+    apply["dry_run"] = json!(false);
+    let response = edit_request(apply).await;
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(view_file(&a).await, "     1\tfn fetch_user() {}\n     2\tfetch_user();\n     3\t");
+    let changed = view_file(&b).await;
+    assert!(changed.contains("CRLF line endings"), "Line endings should be kept: {}", changed);
+    assert!(changed.contains("     1\tuse fetch_user;\n"));
+    assert_eq!(view_file(&notes).await, "     1\tget_user\n     2\t", "Files outside the globs should not change");
+
+    let response = edit_request(json!({"command": "undo_edit", "path": b})).await;
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(view_file(&b).await.contains("     1\tuse get_user;\n"));
+    assert_eq!(view_file(&a).await, "     1\tfn fetch_user() {}\n     2\tfetch_user();\n     3\t", "Undo should be per file");
+
+    let response = edit_request(json!({"command": "replace_in_files", "path": dir, "pattern": "missing", "replacement": "x"})).await;
+    assert_eq!(response.status().as_u16(), 400);
+
+    // Hidden and ignored files are only changed on request
+    let (hidden, ignored) = (format!("{}/.config/c.rs", dir), format!("{}/generated.rs", dir));
+    upload_file(&hidden, b"old\n").await;
+    upload_file(&ignored, b"old\n").await;
+    upload_file(&format!("{}/.gitignore", dir), b"generated.rs\n").await;
+    let request = json!({"command": "replace_in_files", "path": dir, "pattern": "old", "replacement": "new"});
+    let response = edit_request(request.clone()).await;
+    assert_eq!(response.status().as_u16(), 400);
+    let body: serde_json::Value = response.json().await.expect("Failed to parse response");
+    let message = body["data"].as_str().unwrap();
+    assert!(message.contains("show_hidden") && message.contains("no_ignore"), "Error should name the skipped files: {}", message);
+
+    let mut include = request.clone();
+    include["show_hidden"] = json!(true);
+    include["no_ignore"] = json!(true);
+    let response = edit_request(include).await;
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(download_file(&hidden).await.unwrap(), b"new\n");
+    assert_eq!(download_file(&ignored).await.unwrap(), b"new\n");
+}
+
+#[tokio::test]
+async fn test_file_operations() {
+    wait_for_service().await;
+
+    let dir = format!("/tmp/test_fsops_{}", std::process::id());
+    let (nested, script, moved) = (format!("{}/a/b", dir), format!("{}/a/b/run.sh", dir), format!("{}/run.sh", dir));
+    let report = |body: serde_json::Value| -> serde_json::Value { serde_json::from_str(body["data"].as_str().unwrap()).unwrap() };
+
+    let response = edit_request(json!({"command": "mkdir", "path": nested})).await;
+    assert_eq!(response.status().as_u16(), 400, "Missing parents should need parents");
+    let response = edit_request(json!({"command": "mkdir", "path": nested, "parents": true})).await;
+    assert_eq!(response.status().as_u16(), 200);
+    let response = edit_request(json!({"command": "stat", "path": nested})).await;
+    let body: serde_json::Value = response.json().await.expect("Failed to parse response");
+    assert_eq!(report(body)["kind"], "directory");
+
+    let response = test_edit_command("create", &script, Some("echo hi\n"), None, None, None, None).await;
+    assert_eq!(response.status().as_u16(), 200);
+    let response = edit_request(json!({"command": "chmod", "path": script, "mode": "0644"})).await;
+    assert_eq!(response.status().as_u16(), 200);
+    let response = edit_request(json!({"command": "chmod", "path": script, "mode": "u+x"})).await;
+    assert_eq!(response.status().as_u16(), 200);
+    let body: serde_json::Value = response.json().await.expect("Failed to parse response");
+    assert_eq!(report(body)["stat"]["mode"], "0744");
+
+    let response = edit_request(json!({"command": "move", "path": script, "destination": moved})).await;
+    assert_eq!(response.status().as_u16(), 200);
+    let response = edit_request(json!({"command": "stat", "path": moved})).await;
+    let body: serde_json::Value = response.json().await.expect("Failed to parse response");
+    let stat = report(body);
+    assert_eq!(stat["kind"], "file");
+    assert_eq!(stat["size"], 8);
+    let response = edit_request(json!({"command": "undo_edit", "path": moved})).await;
+    assert_eq!(response.status().as_u16(), 200);
+    let response = edit_request(json!({"command": "stat", "path": script})).await;
+    assert_eq!(response.status().as_u16(), 200, "Undoing a move should move the file back");
+
+    let response = edit_request(json!({"command": "delete", "path": format!("{}/a", dir)})).await;
+    assert_eq!(response.status().as_u16(), 200);
+    let body: serde_json::Value = response.json().await.expect("Failed to parse response");
+    let trash_path = report(body)["trash_path"].as_str().unwrap().to_string();
+    let response = edit_request(json!({"command": "stat", "path": format!("{}/b/run.sh", trash_path)})).await;
+    assert_eq!(response.status().as_u16(), 200, "Deleted entries should be kept in the trash");
+    let response = edit_request(json!({"command": "stat", "path": script})).await;
+    assert_eq!(response.status().as_u16(), 404);
+    let response = edit_request(json!({"command": "undo_edit", "path": format!("{}/a", dir)})).await;
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(view_file(&script).await, "     1\techo hi\n     2\t");
+
+    let response = edit_request(json!({"command": "undo_edit", "path": script})).await;
+    assert_eq!(response.status().as_u16(), 200);
+    let response = edit_request(json!({"command": "stat", "path": script})).await;
+    let body: serde_json::Value = response.json().await.expect("Failed to parse response");
+    assert_eq!(report(body)["mode"], "0644", "Undo should restore the mode");
+}
+
+#[tokio::test]
+async fn test_watch_events() {
+    wait_for_service().await;
+
+    let dir = format!("/tmp/test_watch_{}", std::process::id());
+    let response = edit_request(json!({"command": "mkdir", "path": format!("{}/src", dir), "parents": true})).await;
+    assert_eq!(response.status().as_u16(), 200);
+    let client = reqwest::Client::new();
+    let mut response = client
+        .get(format!("{}/watch", API_BASE_URL))
+        .query(&[("path", dir.as_str()), ("globs", "*.rs"), ("debounce_ms", "50")])
+        .timeout(Duration::from_secs(10))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(response.headers()["content-type"], "text/event-stream");
+
+    let mut events = String::new();
+    while !events.contains("event: ready") {
+        let chunk = response.chunk().await.expect("Failed to read events").expect("Stream ended");
+        events.push_str(&String::from_utf8_lossy(&chunk));
+    }
+    for (name, content) in [("notes.txt", "not reported"), ("src/main.rs", "fn main() {}\n")] {
+        let response = test_edit_command("create", &format!("{}/{}", dir, name), Some(content), None, None, None, None).await;
+        assert_eq!(response.status().as_u16(), 200);
+    }
+
+    while !events.contains("event: create") {
+        let chunk = response.chunk().await.expect("Failed to read events").expect("Stream ended");
+        events.push_str(&String::from_utf8_lossy(&chunk));
+    }
+    let data = events.lines().skip_while(|line| *line != "event: create").nth(1).unwrap();
+    let event: serde_json::Value = serde_json::from_str(data.strip_prefix("data: ").unwrap()).unwrap();
+    assert_eq!(event["path"], format!("{}/src/main.rs", dir), "Only files matching the globs should be reported");
+    assert_eq!(event["hash"].as_str().unwrap().len(), 64, "Events should carry the content hash");
+}
+
+#[tokio::test]
+async fn test_view_image() {
+    wait_for_service().await;
+
+    // 1x1 pixel RGB PNG
+    let png: [u8; 69] = [
+        0x89, 0x50, 0x4E, 0x47, 0x0D, 0x0A, 0x1A, 0x0A, 0x00, 0x00, 0x00, 0x0D, 0x49, 0x48, 0x44, 0x52, 0x00, 0x00, 0x00,
+        0x01, 0x00, 0x00, 0x00, 0x01, 0x08, 0x02, 0x00, 0x00, 0x00, 0x90, 0x77, 0x53, 0xDE, 0x00, 0x00, 0x00, 0x0C, 0x49,
+        0x44, 0x41, 0x54, 0x78, 0x9C, 0x63, 0xF8, 0xCF, 0xC0, 0x00, 0x00, 0x03, 0x01, 0x01, 0x00, 0xC9, 0xFE, 0x92, 0xEF,
+        0x00, 0x00, 0x00, 0x00, 0x49, 0x45, 0x4E, 0x44, 0xAE, 0x42, 0x60, 0x82,
+    ];
+    let test_file = &format!("/tmp/test_view_image_{}.dat", std::process::id());
+    upload_file(test_file, &png).await;
+
+    let response = test_edit_command("view", test_file, None, None, None, None, None).await;
+    assert_eq!(response.status().as_u16(), 200, "Images should be viewable");
+    let body: serde_json::Value = response.json().await.expect("Failed to parse response");
+    assert_eq!(body["type"], "base64");
+    assert_eq!(body["media_type"], "image/png", "The type should be detected from the content");
+    assert!(body["data"].as_str().unwrap().starts_with("iVBORw0KGgo"));
+}
+
+#[tokio::test]
+async fn test_edit_keeps_encoding_and_newlines() {
+    wait_for_service().await;
+
+    let dir = format!("/tmp/test_encoding_{}", std::process::id());
+
+    // UTF-16LE with BOM and CRLF line endings
+    let utf16 = format!("{}/utf16.txt", dir);
+    let encode_utf16 = |text: &str| [0xFF, 0xFE].into_iter().chain(text.encode_utf16().flat_map(u16::to_le_bytes)).collect::<Vec<u8>>();
+    upload_file(&utf16, &encode_utf16("héllo\r\nwörld\r\n")).await;
+    let response = test_edit_command("view", &utf16, None, None, None, None, None).await;
+    let body: serde_json::Value = response.json().await.expect("Failed to parse response");
+    let data = body["data"].as_str().unwrap();
+    assert!(data.contains("UTF-16LE with BOM, CRLF line endings"), "View should report the detected format: {}", data);
+    assert!(data.contains("     2\twörld\n"));
+
+    let response = test_edit_command("str_replace", &utf16, None, None, Some("héllo\nwörld"), Some("hi\nthere"), None).await;
+    assert_eq!(response.status().as_u16(), 200, "Multi-line matches should work in CRLF files");
+    let response = test_edit_command("insert", &utf16, Some("end"), None, None, None, Some(2)).await;
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(download_file(&utf16).await.unwrap(), encode_utf16("hi\r\nthere\r\nend\r\n"));
+
+    // ISO-8859-1 is written back byte for byte
+    let latin1 = format!("{}/latin1.txt", dir);
+    upload_file(&latin1, b"caf\xe9\nna\xefve\n").await;
+    let response = test_edit_command("str_replace", &latin1, None, None, Some("café"), Some("thé"), None).await;
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(download_file(&latin1).await.unwrap(), b"th\xe9\nna\xefve\n");
+    let response = test_edit_command("str_replace", &latin1, None, None, Some("thé"), Some("\u{2713}"), None).await;
+    assert_eq!(response.status().as_u16(), 400, "Characters the encoding lacks should be rejected");
+
+    // A missing trailing newline stays missing, an existing one is kept
+    let plain = format!("{}/plain.txt", dir);
+    upload_file(&plain, b"a\nb").await;
+    let response = test_edit_command("insert", &plain, Some("c"), None, None, None, Some(2)).await;
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(download_file(&plain).await.unwrap(), b"a\nb\nc");
+    upload_file(&plain, b"a\nb\n").await;
+    let response = test_edit_command("insert", &plain, Some("c"), None, None, None, Some(1)).await;
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(download_file(&plain).await.unwrap(), b"a\nc\nb\n");
+}
+
+#[tokio::test]
+async fn test_edit_conflict_detection() {
+    wait_for_service().await;
+
+    let test_file = &format!("/tmp/test_conflict_{}.txt", std::process::id());
+    let response = test_edit_command("create", test_file, Some("alpha\nbeta\n"), None, None, None, None).await;
+    assert_eq!(response.status().as_u16(), 200);
+    let response = test_edit_command("view", test_file, None, None, None, None, None).await;
+    let viewed = response.headers()["etag"].to_str().unwrap().trim_matches('"').to_string();
+
+    // Someone else changes the file after it was viewed
+    upload_file(test_file, b"alpha\nBETA\n").await;
+
+    let edit = |expected_hash: &str| {
+        edit_request(json!({
+            "command": "str_replace",
+            "path": test_file,
+            "old_str": "alpha",
+            "new_str": "gamma",
+            "expected_hash": expected_hash,
+        }))
+    };
+    let response = edit(&viewed).await;
+    assert_eq!(response.status().as_u16(), 409, "Edits based on an outdated view should be rejected");
+    let body: serde_json::Value = response.json().await.expect("Failed to parse response");
+    assert!(body["data"].as_str().unwrap().contains("+BETA"), "Conflict should show what changed");
+    assert_eq!(download_file(test_file).await.unwrap(), b"alpha\nBETA\n");
+
+    let response = test_edit_command("view", test_file, None, None, None, None, None).await;
+    let current = response.headers()["etag"].to_str().unwrap().to_string();
+    let response = edit(&current).await;
+    assert_eq!(response.status().as_u16(), 200, "Edits based on the current content should succeed");
+    assert_ne!(response.headers()["etag"].to_str().unwrap(), current, "Edits should return the new hash");
+    assert_eq!(download_file(test_file).await.unwrap(), b"gamma\nBETA\n");
+}
+
+#[tokio::test]
+async fn test_edit_batch() {
+    wait_for_service().await;
+
+    let client = reqwest::Client::new();
+    let first = "/tmp/test_edit_batch_first.txt";
+    let second = "/tmp/test_edit_batch_second.txt";
+    for path in [first, second] {
+        let response = test_edit_command("create", path, Some("alpha\nbeta"), None, None, None, None).await;
+        assert_eq!(response.status().as_u16(), 200);
+    }
+
+    let batch = |edits: serde_json::Value| {
+        client
+            .post(format!("{}/edit/batch", API_BASE_URL))
+            .json(&json!({ "edits": edits }))
+            .timeout(Duration::from_secs(10))
+            .send()
+    };
+
+    // The last edit is invalid, so the earlier ones must not be written
+    let response = batch(json!([
+        { "command": "str_replace", "path": first, "old_str": "alpha", "new_str": "ALPHA" },
+        { "command": "str_replace", "path": second, "old_str": "missing", "new_str": "x" },
+    ]))
+    .await
+    .expect("Failed to execute request");
+    assert_eq!(response.status().as_u16(), 400, "Invalid batches should be rejected");
+    let body: serde_json::Value = response.json().await.expect("Failed to parse response");
+    let report: serde_json::Value = serde_json::from_str(body["data"].as_str().unwrap()).unwrap();
+    assert_eq!(report["steps"][0]["status"], "ok");
+    assert_eq!(report["steps"][1]["status"], "failed");
+    let response = test_edit_command("view", first, None, None, None, None, None).await;
+    let body: serde_json::Value = response.json().await.expect("Failed to parse response");
+    assert!(body["data"].as_str().unwrap().contains("alpha"), "Rejected batch should not change files");
+
+    // Later edits see the result of earlier ones in the same file
+    let response = batch(json!([
+        { "command": "str_replace", "path": first, "old_str": "alpha", "new_str": "ALPHA" },
+        { "command": "str_replace", "path": first, "old_str": "ALPHA", "new_str": "gamma" },
+        { "command": "insert", "path": second, "file_text": "top", "insert_line": 0 },
+    ]))
+    .await
+    .expect("Failed to execute request");
+    assert_eq!(response.status().as_u16(), 200, "Valid batch should be applied");
+
+    let response = test_edit_command("view", first, None, None, None, None, None).await;
+    let body: serde_json::Value = response.json().await.expect("Failed to parse response");
+    assert_eq!(body["data"], "     1\tgamma\n     2\tbeta");
+    let response = test_edit_command("view", second, None, Some(vec![1, 1]), None, None, None).await;
+    let body: serde_json::Value = response.json().await.expect("Failed to parse response");
+    assert_eq!(body["data"], "     1\ttop");
+}
+
+#[tokio::test]
+async fn test_sandbox_policy() {
+    wait_for_service().await;
+
+    // The server's own source is read-only by default, also when reached via `..`
+    for path in ["/app/api-server/src/sandbox_test.rs", "/tmp/../app/api-server/sandbox_test.rs", "relative.txt"] {
+        let response = test_edit_command("create", path, Some("x"), None, None, None, None).await;
+        assert_eq!(response.status().as_u16(), 403, "Writing {} should be forbidden", path);
+        let body: serde_json::Value = response.json().await.expect("Failed to parse response");
+        assert!(body["data"].as_str().unwrap().contains("forbidden"), "Unexpected error: {}", body["data"]);
+    }
+
+    let response = test_edit_command("create", "/tmp/test_sandbox_allowed.txt", Some("x"), None, None, None, None).await;
+    assert_eq!(response.status().as_u16(), 200, "Writing to /tmp should be allowed");
+}
+
+#[tokio::test]
+async fn test_file_upload_and_download() {
+    wait_for_service().await;
+
+    let client = reqwest::Client::new();
+    let path = format!("/tmp/test_files_{}/data.bin", std::process::id());
+    let content: Vec<u8> = (0..=255u8).cycle().take(100_000).collect();
+    let upload = |query: String, body: Vec<u8>| {
+        client
+            .put(format!("{}/files?{}", API_BASE_URL, query))
+            .body(body)
+            .timeout(Duration::from_secs(10))
+            .send()
+    };
+
+    let response = upload(format!("path={}", path), content.clone()).await.expect("Failed to execute request");
+    assert_eq!(response.status().as_u16(), 400, "Missing directories should require mkdir");
+
+    let response = upload(format!("path={}&mkdir=true&sha256={}", path, "0".repeat(64)), content.clone())
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status().as_u16(), 400, "Checksum mismatch should be rejected");
+
+    let response = upload(format!("path={}&mkdir=true", path), content.clone()).await.expect("Failed to execute request");
+    assert_eq!(response.status().as_u16(), 200, "Upload should succeed");
+    let body: serde_json::Value = response.json().await.expect("Failed to parse response");
+    let uploaded: serde_json::Value = serde_json::from_str(body["data"].as_str().unwrap()).unwrap();
+    assert_eq!(uploaded["size"], 100_000);
+
+    // The reported checksum is accepted for a verified overwrite
+    let query = format!("path={}&overwrite=true&sha256={}", path, uploaded["sha256"].as_str().unwrap());
+    let response = upload(query, content.clone()).await.expect("Failed to execute request");
+    assert_eq!(response.status().as_u16(), 200, "Verified overwrite should succeed");
+    let response = upload(format!("path={}", path), content.clone()).await.expect("Failed to execute request");
+    assert_eq!(response.status().as_u16(), 409, "Overwriting should require overwrite=true");
+
+    let response = client
+        .get(format!("{}/files?path={}", API_BASE_URL, path))
+        .timeout(Duration::from_secs(10))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(response.bytes().await.unwrap().as_ref(), content.as_slice());
+
+    let response = client
+        .get(format!("{}/files?path={}", API_BASE_URL, path))
+        .header("Range", "bytes=1000-1099")
+        .timeout(Duration::from_secs(10))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status().as_u16(), 206, "Range requests should return partial content");
+    assert_eq!(response.bytes().await.unwrap().as_ref(), &content[1000..1100]);
+}
+
+/// Builds an uncompressed tar archive holding one regular file.
+fn tar_with_file(name: &str, content: &[u8]) -> Vec<u8> {
+    let mut header = [0u8; 512];
+    header[..name.len()].copy_from_slice(name.as_bytes());
+    header[100..107].copy_from_slice(b"0000644");
+    header[108..115].copy_from_slice(b"0000000");
+    header[116..123].copy_from_slice(b"0000000");
+    header[124..135].copy_from_slice(format!("{:011o}", content.len()).as_bytes());
+    header[136..147].copy_from_slice(b"00000000000");
+    header[156] = b'0';
+    header[257..263].copy_from_slice(b"ustar\0");
+    header[263..265].copy_from_slice(b"00");
+    header[148..156].copy_from_slice(b"        ");
+    let checksum: u32 = header.iter().map(|&b| b as u32).sum();
+    header[148..155].copy_from_slice(format!("{:06o}\0", checksum).as_bytes());
+
+    let mut archive = header.to_vec();
+    archive.extend_from_slice(content);
+    archive.resize(512 + content.len().div_ceil(512) * 512 + 1024, 0);
+    archive
+}
+
+#[tokio::test]
+async fn test_directory_archive() {
+    wait_for_service().await;
+
+    let client = reqwest::Client::new();
+    let base = format!("/tmp/test_archive_{}", std::process::id());
+    for (name, content) in [("src/main.rs", "fn main() {}\n"), ("target/debug/app", "binary"), ("README", "hello\n")] {
+        let response = client
+            .put(format!("{}/files?path={}/project/{}&mkdir=true&overwrite=true", API_BASE_URL, base, name))
+            .body(content)
+            .timeout(Duration::from_secs(10))
+            .send()
+            .await
+            .expect("Failed to execute request");
+        assert_eq!(response.status().as_u16(), 200);
+    }
+
+    for format in ["tar.gz", "zip"] {
+        let response = client
+            .get(format!("{}/files/archive?path={}/project&format={}&exclude=target", API_BASE_URL, base, format))
+            .timeout(Duration::from_secs(10))
+            .send()
+            .await
+            .expect("Failed to execute request");
+        assert_eq!(response.status().as_u16(), 200, "Export as {} should succeed", format);
+        let archive = response.bytes().await.unwrap();
+
+        let copy = format!("{}/copy_{}", base, format.replace('.', "_"));
+        let response = client
+            .post(format!("{}/files/archive?path={}&mkdir=true", API_BASE_URL, copy))
+            .body(archive.clone())
+            .timeout(Duration::from_secs(10))
+            .send()
+            .await
+            .expect("Failed to execute request");
+        assert_eq!(response.status().as_u16(), 200, "Import of {} should succeed", format);
+        let body: serde_json::Value = response.json().await.expect("Failed to parse response");
+        let report: serde_json::Value = serde_json::from_str(body["data"].as_str().unwrap()).unwrap();
+        assert_eq!(report["files"], 2, "Excluded files should not be exported");
+
+        let response = client
+            .get(format!("{}/files?path={}/src/main.rs", API_BASE_URL, copy))
+            .timeout(Duration::from_secs(10))
+            .send()
+            .await
+            .expect("Failed to execute request");
+        assert_eq!(response.text().await.unwrap(), "fn main() {}\n");
+
+        let response = client
+            .post(format!("{}/files/archive?path={}", API_BASE_URL, copy))
+            .body(archive)
+            .timeout(Duration::from_secs(10))
+            .send()
+            .await
+            .expect("Failed to execute request");
+        assert_eq!(response.status().as_u16(), 409, "Replacing files should require overwrite=true");
+    }
+
+    for name in ["../escaped.txt", "/tmp/escaped.txt"] {
+        let response = client
+            .post(format!("{}/files/archive?path={}/project", API_BASE_URL, base))
+            .body(tar_with_file(name, b"escaped"))
+            .timeout(Duration::from_secs(10))
+            .send()
+            .await
+            .expect("Failed to execute request");
+        assert_eq!(response.status().as_u16(), 400, "{} should be rejected", name);
+    }
+    assert!(download_file(&format!("{}/escaped.txt", base)).await.is_none());
+
+    let response = client
+        .post(format!("{}/files/archive?path={}/project", API_BASE_URL, base))
+        .body(tar_with_file("notes/ok.txt", b"fine"))
+        .timeout(Duration::from_secs(10))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status().as_u16(), 200, "Plain tar archives should be detected");
+}
+
+#[tokio::test]
+async fn test_search() {
+    wait_for_service().await;
+
+    let client = reqwest::Client::new();
+    let base = format!("/tmp/test_search_{}", std::process::id());
+    for (path, text) in [
+        ("src/main.rs", "fn main() {\n    let value = Helper::new();\n}\n"),
+        ("src/helper.rs", "pub struct Helper;\n"),
+        ("build/generated.rs", "struct Helper;\n"),
+        (".gitignore", "build\n"),
+    ] {
+        let response = client
+            .put(format!("{}/files?path={}/{}&mkdir=true&overwrite=true", API_BASE_URL, base, path))
+            .body(text)
+            .timeout(Duration::from_secs(10))
+            .send()
+            .await
+            .expect("Failed to execute request");
+        assert_eq!(response.status().as_u16(), 200);
+    }
+
+    let search = |payload: serde_json::Value| {
+        client
+            .post(format!("{}/search", API_BASE_URL))
+            .json(&payload)
+            .timeout(Duration::from_secs(10))
+            .send()
+    };
+
+    let response = search(json!({"path": base, "globs": ["*.rs"]})).await.expect("Failed to execute request");
+    assert_eq!(response.status().as_u16(), 200);
+    let body: serde_json::Value = response.json().await.expect("Failed to parse response");
+    let report: serde_json::Value = serde_json::from_str(body["data"].as_str().unwrap()).unwrap();
+    let files: Vec<&str> = report["files"].as_array().unwrap().iter().map(|f| f.as_str().unwrap()).collect();
+    assert_eq!(files.len(), 2, "Ignored files should not be listed: {:?}", files);
+    assert!(files.iter().all(|f| !f.contains("/build/")));
+
+    let response = search(json!({"path": base, "pattern": "helper::", "case_insensitive": true, "context": 1}))
+        .await
+        .expect("Failed to execute request");
+    let body: serde_json::Value = response.json().await.expect("Failed to parse response");
+    let report: serde_json::Value = serde_json::from_str(body["data"].as_str().unwrap()).unwrap();
+    let matches = report["matches"].as_array().unwrap();
+    assert_eq!(matches.len(), 1);
+    assert!(matches[0]["path"].as_str().unwrap().ends_with("src/main.rs"));
+    assert_eq!(matches[0]["line"], 2);
+    assert_eq!(matches[0]["column"], 17);
+    assert_eq!(matches[0]["before"], json!(["fn main() {"]));
+    assert_eq!(matches[0]["after"], json!(["}"]));
+
+    let response = search(json!({"path": base, "pattern": "Helper", "max_results": 1}))
+        .await
+        .expect("Failed to execute request");
+    let body: serde_json::Value = response.json().await.expect("Failed to parse response");
+    let report: serde_json::Value = serde_json::from_str(body["data"].as_str().unwrap()).unwrap();
+    assert_eq!(report["matches"].as_array().unwrap().len(), 1);
+    assert_eq!(report["truncated"], true);
+
+    let response = search(json!({"path": base, "pattern": "("})).await.expect("Failed to execute request");
+    assert_eq!(response.status().as_u16(), 400, "Invalid patterns should be rejected");
+}
+
 #[tokio::test]
 async fn test_edit_error_cases() {
     wait_for_service().await;
@@ -367,4 +1427,87 @@ async fn test_edit_error_cases() {
             expected_status
         );
     }
-} 
\ No newline at end of file
+} 
+#[tokio::test]
+async fn test_recording_and_replay() {
+    wait_for_service().await;
+
+    let client = reqwest::Client::new();
+    let recording_file = "/tmp/test_recording.json";
+
+    let response = client
+        .post(format!("{}/recording", API_BASE_URL))
+        .json(&json!({ "command": "start", "path": recording_file }))
+        .timeout(Duration::from_secs(10))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status().as_u16(), 200, "Starting a recording should succeed");
+
+    let response = test_edit_command(
+        "create",
+        "/tmp/test_recorded_file.txt",
+        Some("recorded"),
+        None,
+        None,
+        None,
+        None
+    ).await;
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(response.headers().contains_key("etag"), "Recording should keep the response headers");
+
+    let response = client
+        .post(format!("{}/recording", API_BASE_URL))
+        .json(&json!({ "command": "stop" }))
+        .timeout(Duration::from_secs(10))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status().as_u16(), 200, "Stopping a recording should succeed");
+
+    // Dry run only lists the recorded steps
+    let response = client
+        .post(format!("{}/replay", API_BASE_URL))
+        .json(&json!({ "path": recording_file, "dry_run": true }))
+        .timeout(Duration::from_secs(10))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status().as_u16(), 200, "Dry-run replay should succeed");
+    let body: serde_json::Value = response.json().await.expect("Failed to parse response");
+    let report: serde_json::Value = serde_json::from_str(body["data"].as_str().unwrap()).unwrap();
+    assert_eq!(report["total_steps"], 1);
+    assert_eq!(report["steps"][0]["summary"], "create /tmp/test_recorded_file.txt");
+
+    let response = client
+        .post(format!("{}/replay", API_BASE_URL))
+        .json(&json!({ "path": recording_file, "speed": 10.0 }))
+        .timeout(Duration::from_secs(10))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status().as_u16(), 200, "Replay should succeed");
+    let body: serde_json::Value = response.json().await.expect("Failed to parse response");
+    let report: serde_json::Value = serde_json::from_str(body["data"].as_str().unwrap()).unwrap();
+    assert_eq!(report["mismatches"], 0, "Replayed steps should match the recording");
+}
+
+#[tokio::test]
+async fn test_interference_status() {
+    wait_for_service().await;
+
+    let client = reqwest::Client::new();
+    let response = client
+        .get(format!("{}/interference", API_BASE_URL))
+        .timeout(Duration::from_secs(10))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status().as_u16(), 200);
+
+    let body: serde_json::Value = response.json().await.expect("Failed to parse response");
+    assert_eq!(body["media_type"], "application/json");
+    let status: serde_json::Value = serde_json::from_str(body["data"].as_str().unwrap()).unwrap();
+    assert!(status["count"].is_u64(), "Status should contain the interference counter");
+    assert!(status["monitoring"].is_boolean(), "Status should report whether input is monitored");
+}
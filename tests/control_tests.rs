@@ -0,0 +1,88 @@
+use serde_json::json;
+use std::time::Duration;
+
+// Kept in its own test binary: while the human lease is held, input actions
+// from concurrently running tests would be rejected.
+
+const API_BASE_URL: &str = "http://localhost:8090";
+
+async fn wait_for_service() {
+    let client = reqwest::Client::new();
+    for i in 0..60 {
+        if let Ok(response) = client
+            .get(format!("{}/health", API_BASE_URL))
+            .timeout(Duration::from_secs(2))
+            .send()
+            .await {
+            if response.status().is_success() {
+                println!("Service is ready after {} seconds", i);
+                return;
+            }
+        }
+        println!("Waiting for service... attempt {}/60", i + 1);
+        tokio::time::sleep(Duration::from_secs(1)).await;
+    }
+    panic!("Service did not become ready in time");
+}
+
+async fn test_computer_action(action: &str) -> reqwest::Response {
+    let client = reqwest::Client::new();
+
+    client
+        .post(format!("{}/computer", API_BASE_URL))
+        .json(&json!({ "action": action }))
+        .timeout(Duration::from_secs(10))
+        .send()
+        .await
+        .expect("Failed to execute request")
+}
+
+async fn test_control_command(payload: serde_json::Value) -> reqwest::Response {
+    let client = reqwest::Client::new();
+
+    client
+        .post(format!("{}/control", API_BASE_URL))
+        .json(&payload)
+        .timeout(Duration::from_secs(10))
+        .send()
+        .await
+        .expect("Failed to execute request")
+}
+
+#[tokio::test]
+async fn test_control_lease() {
+    wait_for_service().await;
+
+    let response = test_control_command(json!({
+        "command": "acquire",
+        "owner": "test-human",
+        "holder": "human",
+        "ttl_secs": 10,
+        "force": true
+    })).await;
+    assert_eq!(response.status().as_u16(), 200, "Acquiring control should succeed");
+
+    // Input actions are rejected while a human holds control
+    let response = test_computer_action("left_click").await;
+    assert_eq!(response.status().as_u16(), 423, "Input should be rejected while a human holds control");
+
+    // Another owner cannot take over without force
+    let response = test_control_command(json!({
+        "command": "acquire",
+        "owner": "test-agent",
+        "holder": "agent"
+    })).await;
+    assert_eq!(response.status().as_u16(), 409, "Acquiring a held lease should conflict");
+
+    let response = test_control_command(json!({
+        "command": "release",
+        "owner": "test-human"
+    })).await;
+    assert_eq!(response.status().as_u16(), 200, "Releasing control should succeed");
+
+    let response = test_control_command(json!({ "command": "status" })).await;
+    assert_eq!(response.status().as_u16(), 200);
+    let body: serde_json::Value = response.json().await.expect("Failed to parse response");
+    let status: serde_json::Value = serde_json::from_str(body["data"].as_str().unwrap()).unwrap();
+    assert_eq!(status["held"], false, "Lease should be free after release");
+}